@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use utree::eval::evaluate;
+use utree::render::render_html;
+use utree::template::Template;
+use utree::tree::TreeNode;
+use utree::value::Value;
+
+const PAGE: &str = r#"
+    html
+        >(head>meta[charset="utf-8"]+title>{@title})
+        +body>div#page-body.container
+            >h1.header>{@title}
+            +ul#list>(li.item[id=@items%id]>div.list_icon+{My name is @items%name})*@items
+            +$footer
+"#;
+
+fn page_data(items: isize) -> Value {
+    Value::object([
+        ("title", Value::from("Benchmark page")),
+        ("items", Value::List((0..items).map(|i| {
+            Value::object([("id", Value::from(i)), ("name", Value::from(format!("item {}", i)))])
+        }).collect())),
+        ("footer", TreeNode::parse("footer>p>{Generated by utree}").unwrap().into()),
+    ])
+}
+
+fn render_benchmark(c: &mut Criterion) {
+    let tree = TreeNode::parse(PAGE).unwrap();
+    let template = Template::compile(&tree);
+
+    for items in [10, 1000] {
+        let data = page_data(items);
+
+        c.bench_function(&format!("tree_walk_{}", items), |b| {
+            b.iter(|| render_html(&evaluate(black_box(&tree), black_box(&data)).unwrap()).unwrap())
+        });
+
+        c.bench_function(&format!("template_{}", items), |b| {
+            b.iter(|| template.render(black_box(&data)).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, render_benchmark);
+criterion_main!(benches);
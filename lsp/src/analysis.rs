@@ -0,0 +1,533 @@
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Diagnostic, DiagnosticSeverity, Hover, HoverContents, MarkupContent,
+    MarkupKind, Position, Range,
+};
+use pest::Parser;
+use std::collections::BTreeMap;
+use std::path::Path;
+use utree::library::{parse_file_recovering, FileLoader, Library, LibraryError, TemplateLoader};
+use utree::parser::{Rule, SyntaxParser};
+use utree::tree::TreeNode;
+use utree::value::LOOP_VARIABLES;
+
+// Reference to the binding (`@items%name`) or component (`$footer`) in the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub sigil: char,
+    pub path: String,
+    pub range: Range,
+}
+
+// Template files hold only imports and definitions (see `utree::library`), the other documents
+// are expressions, which may start with the definitions as well
+pub fn is_template_file(text: &str) -> bool {
+    let mut first = None;
+    let mut depth = 0;
+    let mut semicolon = false;
+    // Start of the code after the last top-level `;`
+    let mut tail = None;
+    scan(text, |i, c| {
+        if c.is_whitespace() {
+            return;
+        }
+        first.get_or_insert(i);
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                semicolon = true;
+                tail = None;
+                return;
+            },
+            _ => {},
+        }
+        tail.get_or_insert(i);
+    });
+    let starts_with_import = first.is_some_and(|i| {
+        text[i..].strip_prefix("import").is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || "_-".contains(c)))
+    });
+    starts_with_import || (semicolon && tail.is_none())
+}
+
+// All parse errors of the document with their hints. Parser recovers from errors at `+`, `>` and
+// `)` in expressions, and at imports and definitions in template files. Template files without
+// syntax errors are also loaded with their imports, which are read relative to `path`.
+pub fn diagnostics(text: &str, path: Option<&Path>) -> Vec<Diagnostic> {
+    let diagnostics = match is_template_file(text) {
+        true => parse_file_recovering(text).1,
+        false => TreeNode::parse_recovering(text).1,
+    };
+    if !diagnostics.is_empty() || !is_template_file(text) {
+        return diagnostics.into_iter().map(|d| diagnostic(text, (d.line, d.column), d.message, d.hint)).collect();
+    }
+
+    match load_library(text, path) {
+        Ok(_) => vec![],
+        Err(LibraryError::Parse { path: file, error }) if file == document_name(path) => {
+            vec![diagnostic(text, error.location().unwrap_or((1, 1)), error.message(), error.hint().map(str::to_owned))]
+        },
+        // Errors of the imported files are shown at the start of the document
+        Err(error) => vec![diagnostic(text, (1, 1), error.to_string(), None)],
+    }
+}
+
+fn diagnostic(text: &str, (line, column): (usize, usize), message: String, hint: Option<String>) -> Diagnostic {
+    let start = char_position(text, line - 1, column - 1);
+    // Error is highlighted until the end of the token
+    let offset = position_to_offset(text, start);
+    let len = text[offset..]
+        .find(|c: char| c.is_whitespace() || "()[]{}+>*;".contains(c))
+        .filter(|len| *len != 0)
+        .unwrap_or_else(|| text[offset..].chars().next().map(char::len_utf8).unwrap_or(0));
+    let end = offset_to_position(text, offset + len);
+
+    Diagnostic {
+        range: Range::new(start, end),
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("utree".to_owned()),
+        message: match hint {
+            Some(hint) => format!("{}\nhint: {}", message, hint),
+            None => message,
+        },
+        ..Diagnostic::default()
+    }
+}
+
+// Loads the template file with its imports. Document is taken from the editor, while the
+// imported files are read from the disk.
+fn load_library(text: &str, path: Option<&Path>) -> Result<Library, LibraryError> {
+    let loader = DocumentLoader { name: document_name(path), text, files: path.and_then(Path::parent).map(FileLoader::new) };
+    Library::load(&loader.name, &loader)
+}
+
+fn document_name(path: Option<&Path>) -> String {
+    let name = path.and_then(Path::file_name).map(|name| name.to_string_lossy().into_owned());
+    name.unwrap_or_else(|| format!("document.{}", crate::registry::EXTENSION))
+}
+
+struct DocumentLoader<'a> {
+    name: String,
+    text: &'a str,
+    files: Option<FileLoader>,
+}
+
+impl TemplateLoader for DocumentLoader<'_> {
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        match &self.files {
+            _ if path == self.name => Ok(self.text.to_owned()),
+            Some(files) => files.load(path),
+            None => Err(std::io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+// Debug tree of the document, with all `*N` multipliers expanded. Invalid parts of the
+// expression are shown as error nodes. Template files show the trees of their templates, if
+// the file is loaded without errors.
+pub fn hover(text: &str, path: Option<&Path>) -> Option<Hover> {
+    let mut debug_tree = String::new();
+    if is_template_file(text) {
+        let library = load_library(text, path).ok()?;
+        for definition in parse_file_recovering(text).0 {
+            let tree = library.get(&definition.name)?;
+            debug_tree.push_str(&format!("{}:\n", definition.name));
+            tree.write_debug_tree(&mut debug_tree).ok()?;
+        }
+    } else {
+        let (tree, _) = TreeNode::parse_recovering(text);
+        tree.write_debug_tree(&mut debug_tree).ok()?;
+    }
+    Some(Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: format!("```text\n{}```", debug_tree),
+        }),
+        range: None,
+    })
+}
+
+// Completes binding paths used in the document, and component names from the registry.
+// Document is scanned as text, so completion works while the expression is incomplete.
+pub fn completion(text: &str, position: Position, components: &[String]) -> Vec<CompletionItem> {
+    let offset = position_to_offset(text, position);
+    let prefix_start = text[..offset]
+        .rfind(|c: char| !(c.is_ascii_alphanumeric() || "_-%".contains(c)))
+        .filter(|i| matches!(text[*i..].chars().next(), Some('@' | '$')));
+    let (sigil, prefix) = match prefix_start {
+        Some(i) => (text[i..].chars().next().expect("Sigil is present"), &text[i + 1..offset]),
+        None => return vec![],
+    };
+
+    let detail = match sigil {
+        '@' => "binding",
+        _ => "component",
+    };
+    let mut candidates = BTreeMap::new();
+    for reference in references(text) {
+        // Reference which is being typed is not complete
+        if reference.path.len() == prefix.len() && position_to_offset(text, reference.range.end) == offset {
+            continue;
+        }
+        // Parent paths are known as well, e.g. `items` for `@items%name`
+        let mut path = String::new();
+        for part in reference.path.split('%') {
+            if !path.is_empty() {
+                path.push('%');
+            }
+            path.push_str(part);
+            candidates.insert((reference.sigil, path.clone()), detail);
+        }
+    }
+    if text.contains("*@") {
+        // Data field of the same name shadows the loop variable
+        for variable in LOOP_VARIABLES {
+            candidates.entry(('@', variable.to_string())).or_insert("loop variable, unless shadowed by data");
+        }
+    }
+    candidates.extend(components.iter().map(|c| (('$', c.clone()), detail)));
+
+    let kind = match sigil {
+        '@' => CompletionItemKind::VARIABLE,
+        _ => CompletionItemKind::MODULE,
+    };
+    candidates
+        .into_iter()
+        .filter(|((s, path), _)| *s == sigil && path.starts_with(prefix))
+        .map(|((_, path), detail)| CompletionItem {
+            label: path,
+            kind: Some(kind),
+            detail: Some(detail.to_owned()),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+// Reference under the cursor
+pub fn reference_at(text: &str, position: Position) -> Option<Reference> {
+    references(text).into_iter().find(|r| r.range.start <= position && position <= r.range.end)
+}
+
+// All `@binding` and `$component` references in the document, except for the ones in strings
+// and comments
+pub fn references(text: &str) -> Vec<Reference> {
+    let mut references = vec![];
+    let mut chars = text.char_indices().peekable();
+    let mut quote = None;
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            },
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '/') if text[i..].starts_with("//") => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            },
+            (None, '@' | '$') => {
+                let start = i + 1;
+                let mut end = start;
+                while let Some((j, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || "_-%".contains(*c)) {
+                    end = j + c.len_utf8();
+                }
+                if end != start {
+                    references.push(Reference {
+                        sigil: c,
+                        path: text[start..end].trim_end_matches('%').to_owned(),
+                        range: Range::new(offset_to_position(text, i), offset_to_position(text, end)),
+                    });
+                }
+            },
+            _ => {},
+        }
+    }
+    references
+}
+
+// Template name under the cursor in the template file, e.g. `menu` in `let page = body>menu;`.
+// Name is not checked to be defined.
+pub fn template_at(text: &str, position: Position) -> Option<String> {
+    if !is_template_file(text) {
+        return None;
+    }
+    let offset = position_to_offset(text, position);
+    let is_name = |c: char| c.is_ascii_alphanumeric() || "_-".contains(c);
+    let start = text[..offset].rfind(|c| !is_name(c)).map_or(0, |i| i + 1);
+    let end = text[offset..].find(|c| !is_name(c)).map_or(text.len(), |i| offset + i);
+    // Bindings, components, ids and classes are not template names
+    let prefixed = text[..start].ends_with(['@', '$', '%', '#', '.']);
+    match &text[start..end] {
+        name if !name.is_empty() && !prefixed && !name.starts_with(|c: char| c.is_ascii_digit()) => Some(name.to_owned()),
+        _ => None,
+    }
+}
+
+// Document in the canonical form. Comments are not preserved by the printer, so documents with
+// comments (or errors) are not formatted. Template files are formatted definition by definition.
+pub fn format(text: &str) -> Option<String> {
+    if has_comments(text) {
+        return None;
+    }
+    match is_template_file(text) {
+        true => format_file(text),
+        false => format_expression(text),
+    }
+}
+
+fn format_expression(text: &str) -> Option<String> {
+    let pairs = SyntaxParser::parse(Rule::tree, text).ok()?;
+    // Parsed tree has the definitions and `lorem` placeholders expanded, so such expressions
+    // can't be printed back
+    let expanded = pairs.flatten().any(|pair| match pair.as_rule() {
+        Rule::definition | Rule::call => true,
+        Rule::node_name | Rule::ident => pair.as_str().starts_with("lorem"),
+        _ => false,
+    });
+    if expanded {
+        return None;
+    }
+    Some(utree::convert::to_expression(&TreeNode::parse(text).ok()?))
+}
+
+// Bodies of the definitions are formatted on their own, so they may use the other definitions
+// only without arguments (these uses are printed as is)
+fn format_file(text: &str) -> Option<String> {
+    let file = SyntaxParser::parse(Rule::file, text).ok()?.next()?;
+    let mut lines = vec![];
+    for pair in file.into_inner() {
+        match pair.as_rule() {
+            Rule::import => {
+                let path = pair.into_inner().find(|part| part.as_rule() == Rule::string)?;
+                lines.push(format!("import {};", path.as_str()));
+            },
+            Rule::definition => {
+                let mut definition = String::from("let ");
+                for part in pair.into_inner() {
+                    match part.as_rule() {
+                        Rule::definition_name => definition.push_str(part.as_str()),
+                        Rule::parameters => {
+                            let parameters = part.into_inner().map(|p| p.as_str()).collect::<Vec<_>>();
+                            definition.push_str(&format!("({})", parameters.join(", ")));
+                        },
+                        Rule::expr => definition.push_str(&format!(" = {};", format_expression(part.as_str())?)),
+                        _ => {},
+                    }
+                }
+                lines.push(definition);
+            },
+            _ => {},
+        }
+    }
+    Some(lines.join("\n") + "\n")
+}
+
+fn has_comments(text: &str) -> bool {
+    let mut quote = None;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            },
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '/') if text[i..].starts_with("//") => return true,
+            _ => {},
+        }
+    }
+    false
+}
+
+// Calls `f` for each character outside of strings and comments
+fn scan(text: &str, mut f: impl FnMut(usize, char)) {
+    let mut quote = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            },
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                f(i, c);
+            },
+            (None, '/') if text[i..].starts_with("//") => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            },
+            (None, c) => f(i, c),
+        }
+    }
+}
+
+// Position of the character `column` at zero-based `line`
+fn char_position(text: &str, line: usize, column: usize) -> Position {
+    let line_text = text.split('\n').nth(line).unwrap_or("");
+    let character = line_text.chars().take(column).map(char::len_utf16).sum::<usize>();
+    Position::new(line as u32, character as u32)
+}
+
+// LSP positions count UTF-16 code units
+pub fn offset_to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let character = before[line_start..].chars().map(char::len_utf16).sum::<usize>();
+    Position::new(before.matches('\n').count() as u32, character as u32)
+}
+
+pub fn position_to_offset(text: &str, position: Position) -> usize {
+    let mut offset = 0;
+    for _ in 0..position.line {
+        match text[offset..].find('\n') {
+            Some(i) => offset += i + 1,
+            None => return text.len(),
+        }
+    }
+    let mut character = 0;
+    for c in text[offset..].chars() {
+        if c == '\n' || character >= position.character as usize {
+            break;
+        }
+        character += c.len_utf16();
+        offset += c.len_utf8();
+    }
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expect_test::expect;
+
+    #[test]
+    fn parse_diagnostics() {
+        assert_eq!(diagnostics("ul>li*3", None), []);
+
+        let diagnostic = diagnostics("ul>\n  li[n=99999999999999999999]", None).pop().unwrap();
+        assert_eq!(diagnostic.range, Range::new(Position::new(1, 7), Position::new(1, 27)));
+        assert_eq!(diagnostic.message, "Invalid number literal `99999999999999999999`");
+
+        let messages = diagnostics("ul>li[=1]+p>{a}>b+", None).into_iter().map(|d| d.message).collect::<Vec<_>>();
+        assert_eq!(messages, ["expected attribute name", "Leaf node can't have any children", "Expected term"]);
+
+        let diagnostic = diagnostics("ul>li[title='x]", None).pop().unwrap();
+        expect![[r#"
+            expected attribute value
+            hint: `'` is not closed with `'`"#]].assert_eq(&diagnostic.message);
+
+        let diagnostic = diagnostics("{ä}>b", None).pop().unwrap();
+        assert_eq!(diagnostic.range, Range::new(Position::new(0, 1), Position::new(0, 2)));
+        expect!["expected word, number, `true` or `false`, binding (`@name`), or string"].assert_eq(&diagnostic.message);
+    }
+
+    #[test]
+    fn hover_tree() {
+        let HoverContents::Markup(contents) = hover("ul>li.a*2", None).unwrap().contents else {
+            panic!("Hover contents should be markup");
+        };
+        expect![[r#"
+            ```text
+             - ul
+               - li[class="a"]
+               - li[class="a"]
+            ```"#]].assert_eq(&contents.value);
+        let HoverContents::Markup(contents) = hover("ul>", None).unwrap().contents else {
+            panic!("Hover contents should be markup");
+        };
+        assert!(contents.value.contains("- [ERROR] ``: Expected term"));
+    }
+
+    #[test]
+    fn complete_bindings() {
+        let text = "ul>(li[id=@items%id]>{@items%name '@quoted'})*@items+{@it}+$f";
+        let labels = |position| {
+            completion(text, position, &["footer".to_owned()])
+                .into_iter()
+                .map(|item| item.label)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(Position::new(0, 57)), ["items", "items%id", "items%name"]);
+        assert_eq!(labels(Position::new(0, 61)), ["footer"]);
+        assert_eq!(labels(Position::new(0, 2)), Vec::<String>::new());
+
+        // Loop variable already used in the document could be a data field
+        let text = "(li>{@first @i})*@items";
+        let items = completion(text, Position::new(0, 14), &[]);
+        let items = items.iter().map(|item| (item.label.as_str(), item.detail.as_deref().unwrap())).collect::<Vec<_>>();
+        assert_eq!(items, [("index", "loop variable, unless shadowed by data"), ("items", "binding")]);
+        let items = completion("(li>{@first})*@items+{@f}", Position::new(0, 24), &[]);
+        assert_eq!(items.iter().map(|item| item.detail.as_deref().unwrap()).collect::<Vec<_>>(), ["binding"]);
+    }
+
+    #[test]
+    fn find_reference() {
+        let text = "div>{'$skip'}+\n// $comment\n$footer%view";
+        let reference = reference_at(text, Position::new(2, 3)).unwrap();
+        assert_eq!(reference.sigil, '$');
+        assert_eq!(reference.path, "footer%view");
+        assert_eq!(reference.range, Range::new(Position::new(2, 0), Position::new(2, 12)));
+        assert_eq!(references(text).len(), 1);
+    }
+
+    #[test]
+    fn format_document() {
+        assert_eq!(format("ul > li.a[title='x y'] * 2 + p > {hello world}").unwrap(), "ul>li.a[title='x y']*2+p>{hello world}");
+        assert_eq!(format("ul>li // list"), None);
+        assert_eq!(format("ul>"), None);
+        // Definitions and generated text would be expanded
+        assert_eq!(format("let a = p; a+a"), None);
+        assert_eq!(format("p>lorem3"), None);
+        assert_eq!(format("p>{lorem 3}"), None);
+
+        expect![[r#"
+            import 'parts/menu.utree';
+            import "footer.utree";
+            let page = body>menu+(main>{@text})+footer;
+            let card(title, text) = div.card>(h2>{@title})+p+p;
+        "#]].assert_eq(&format(
+            "import 'parts/menu.utree'  import \"footer.utree\";\nlet page = body > menu + (main > {@text}) + footer;\nlet card(title,text)=div.card>(h2>{@title})+p+p;"
+        ).unwrap());
+        assert_eq!(format("let page = card('x'); let card(t) = p>{@t};"), None);
+    }
+
+    #[test]
+    fn template_files() {
+        assert!(is_template_file("import 'a.utree'"));
+        assert!(is_template_file("// Parts\nlet a = p;\nlet b(x) = (let c = i; c)+{@x}; // end"));
+        assert!(!is_template_file("let a = p; a+a"));
+        assert!(!is_template_file("ul>li"));
+        assert!(!is_template_file("p>{';'}"));
+        assert!(!is_template_file(""));
+
+        assert_eq!(diagnostics("let page = body>card('x');\nlet card(t) = p>{@t};", None), []);
+        let messages = |text| diagnostics(text, None).into_iter().map(|d| (d.range.start, d.message)).collect::<Vec<_>>();
+        assert_eq!(messages("let a = p[=1];\nlet b = i;"), [(Position::new(0, 10), "expected attribute name".to_owned())]);
+        assert_eq!(messages("let a = p;\nlet b = div>b;"), [(Position::new(1, 12), "Definition is recursive: b -> b".to_owned())]);
+        assert_eq!(messages("import 'x.utree';"), [(Position::new(0, 0), "Can't load `x.utree`: entity not found".to_owned())]);
+
+        let root = std::env::temp_dir().join(format!("utree-analysis-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("menu.utree"), "let menu = nav>a*2;").unwrap();
+        let text = "import 'menu.utree';\nlet page = body>menu;";
+        let path = root.join("page.utree");
+        assert_eq!(diagnostics(text, Some(&path)), []);
+        let HoverContents::Markup(contents) = hover(text, Some(&path)).unwrap().contents else {
+            panic!("Hover contents should be markup");
+        };
+        expect![[r#"
+            ```text
+            page:
+             - body
+               - nav
+                 - a
+                 - a
+            ```"#]].assert_eq(&contents.value);
+        std::fs::remove_dir_all(root).unwrap();
+
+        assert_eq!(template_at(text, Position::new(1, 18)), Some("menu".to_owned()));
+        assert_eq!(template_at("let a = p.menu;", Position::new(0, 12)), None);
+        assert_eq!(template_at("ul>menu", Position::new(0, 5)), None);
+    }
+}
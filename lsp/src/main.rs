@@ -0,0 +1,253 @@
+// Language server for μTree templates (`.utree` files), communicating over stdio
+mod analysis;
+mod registry;
+
+use lsp_server::{Connection, ExtractError, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidChangeWatchedFiles, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{Completion, Formatting, GotoDefinition, HoverRequest, RegisterCapability, Request as _};
+use lsp_types::{
+    CompletionOptions, CompletionResponse, DidChangeWatchedFilesRegistrationOptions, FileSystemWatcher, GlobPattern,
+    GotoDefinitionResponse, InitializeParams, Location, OneOf, Position, PublishDiagnosticsParams, Range, Registration,
+    RegistrationParams, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use registry::ComponentRegistry;
+use std::collections::HashMap;
+use std::error::Error;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+fn main() -> Result<(), BoxError> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        hover_provider: Some(true.into()),
+        definition_provider: Some(OneOf::Left(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec!["@".to_owned(), "$".to_owned(), "%".to_owned()]),
+            ..CompletionOptions::default()
+        }),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        ..ServerCapabilities::default()
+    };
+    let params: InitializeParams = serde_json::from_value(connection.initialize(serde_json::to_value(capabilities)?)?)?;
+
+    #[allow(deprecated)]
+    let roots = match (params.workspace_folders, params.root_uri) {
+        (Some(folders), _) => folders.into_iter().map(|folder| folder.uri).collect(),
+        (None, Some(root)) => vec![root],
+        (None, None) => vec![],
+    };
+    let registry = ComponentRegistry::new(roots.iter().filter_map(|uri| uri.to_file_path().ok()));
+
+    // Registry caches the component files, changes of them are watched by the client
+    let watches_files = params.capabilities.workspace
+        .and_then(|workspace| workspace.did_change_watched_files)
+        .and_then(|capabilities| capabilities.dynamic_registration)
+        .unwrap_or(false);
+    if watches_files {
+        watch_files(&connection)?;
+    }
+
+    // Connection should be dropped before joining, so the writer thread is finished
+    Server { connection, registry, watches_files, documents: HashMap::new() }.run()?;
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Server {
+    connection: Connection,
+    registry: ComponentRegistry,
+    // Whether the client notifies about the changed files, otherwise the files are read again for
+    // every request
+    watches_files: bool,
+    // Open documents by their URI
+    documents: HashMap<Url, String>,
+}
+
+impl Server {
+    fn run(mut self) -> Result<(), BoxError> {
+        let receiver = self.connection.receiver.clone();
+        for message in &receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let id = request.id.clone();
+                    let response = match self.handle_request(request) {
+                        Ok(result) => Response::new_ok(id, result),
+                        Err(e) => Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, e.to_string()),
+                    };
+                    self.connection.sender.send(response.into())?;
+                },
+                Message::Notification(notification) => self.handle_notification(notification)?,
+                Message::Response(_) => {},
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, request: Request) -> Result<serde_json::Value, BoxError> {
+        let value = match request.method.as_str() {
+            HoverRequest::METHOD => {
+                let params = extract::<HoverRequest>(request)?;
+                let uri = &params.text_document_position_params.text_document.uri;
+                let text = self.document(uri)?;
+                serde_json::to_value(analysis::hover(text, uri.to_file_path().ok().as_deref()))?
+            },
+            Completion::METHOD => {
+                let params = extract::<Completion>(request)?;
+                let position = params.text_document_position;
+                let text = self.document(&position.text_document.uri)?;
+                let components = self.registry_for(&position.text_document.uri).components();
+                let items = analysis::completion(text, position.position, &components);
+                serde_json::to_value(CompletionResponse::Array(items))?
+            },
+            GotoDefinition::METHOD => {
+                let params = extract::<GotoDefinition>(request)?;
+                let position = params.text_document_position_params;
+                let uri = &position.text_document.uri;
+                let text = self.document(uri)?;
+                let registry = self.registry_for(uri);
+                // Templates of the document itself are found first
+                let target = match analysis::reference_at(text, position.position) {
+                    Some(reference) if reference.sigil == '$' => registry.resolve(&reference.path),
+                    Some(_) => None,
+                    None => analysis::template_at(text, position.position).and_then(|name| {
+                        let (definitions, _) = utree::library::parse_file_recovering(text);
+                        match definitions.into_iter().find(|d| d.name == name) {
+                            Some(definition) => Some((uri.to_file_path().ok()?, definition.offset)),
+                            None => registry.template(&name),
+                        }
+                    }),
+                };
+                let location = target.and_then(|(path, offset)| {
+                    let text = match Url::from_file_path(&path) {
+                        Ok(target) if target == *uri => text.to_owned(),
+                        _ => std::fs::read_to_string(&path).ok()?,
+                    };
+                    let start = analysis::offset_to_position(&text, offset);
+                    let location = Location::new(Url::from_file_path(path).ok()?, Range::new(start, start));
+                    Some(GotoDefinitionResponse::Scalar(location))
+                });
+                serde_json::to_value(location)?
+            },
+            Formatting::METHOD => {
+                let params = extract::<Formatting>(request)?;
+                let text = self.document(&params.text_document.uri)?;
+                let edits = analysis::format(text).map(|formatted| {
+                    let end = analysis::offset_to_position(text, text.len());
+                    vec![TextEdit::new(Range::new(Position::new(0, 0), end), formatted)]
+                });
+                serde_json::to_value(edits)?
+            },
+            method => return Err(format!("Unsupported request `{}`", method).into()),
+        };
+        Ok(value)
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<(), BoxError> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params = extract_notification::<DidOpenTextDocument>(notification)?;
+                let document = params.text_document;
+                self.publish_diagnostics(&document.uri, &document.text)?;
+                self.documents.insert(document.uri, document.text);
+            },
+            DidChangeTextDocument::METHOD => {
+                let params = extract_notification::<DidChangeTextDocument>(notification)?;
+                // Documents are synchronized in full, so the last change has the whole text
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    let uri = params.text_document.uri;
+                    self.publish_diagnostics(&uri, &change.text)?;
+                    self.documents.insert(uri, change.text);
+                }
+            },
+            DidCloseTextDocument::METHOD => {
+                let params = extract_notification::<DidCloseTextDocument>(notification)?;
+                self.documents.remove(&params.text_document.uri);
+                self.send_diagnostics(params.text_document.uri, vec![])?;
+            },
+            DidChangeWatchedFiles::METHOD => {
+                let params = extract_notification::<DidChangeWatchedFiles>(notification)?;
+                for path in params.changes.iter().filter_map(|change| change.uri.to_file_path().ok()) {
+                    self.registry.invalidate(&path);
+                }
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&self, uri: &Url, text: &str) -> Result<(), BoxError> {
+        let path = uri.to_file_path().ok();
+        self.send_diagnostics(uri.clone(), analysis::diagnostics(text, path.as_deref()))
+    }
+
+    fn send_diagnostics(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<(), BoxError> {
+        let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+        self.connection.sender.send(notification.into())?;
+        Ok(())
+    }
+
+    fn document(&self, uri: &Url) -> Result<&str, BoxError> {
+        match self.documents.get(uri) {
+            Some(text) => Ok(text),
+            None => Err(format!("Document {} is not open", uri).into()),
+        }
+    }
+
+    // Components are also looked up next to the document
+    fn registry_for(&self, uri: &Url) -> ComponentRegistry {
+        if !self.watches_files {
+            self.registry.clear();
+        }
+        let dir = uri.to_file_path().ok().and_then(|path| path.parent().map(|dir| dir.to_owned()));
+        match dir {
+            Some(dir) => self.registry.clone().with_root(dir),
+            None => self.registry.clone(),
+        }
+    }
+}
+
+// Asks the client to notify about the changed `.utree` files
+fn watch_files(connection: &Connection) -> Result<(), BoxError> {
+    let options = DidChangeWatchedFilesRegistrationOptions {
+        watchers: vec![FileSystemWatcher {
+            glob_pattern: GlobPattern::String(format!("**/*.{}", registry::EXTENSION)),
+            kind: None,
+        }],
+    };
+    let params = RegistrationParams {
+        registrations: vec![Registration {
+            id: "utree-files".to_owned(),
+            method: DidChangeWatchedFiles::METHOD.to_owned(),
+            register_options: Some(serde_json::to_value(options)?),
+        }],
+    };
+    let request = Request::new("utree-files".to_owned().into(), RegisterCapability::METHOD.to_owned(), params);
+    connection.sender.send(request.into())?;
+    Ok(())
+}
+
+fn extract<R: lsp_types::request::Request>(request: Request) -> Result<R::Params, BoxError> {
+    let (_, params) = request.extract(R::METHOD).map_err(extract_error)?;
+    Ok(params)
+}
+
+fn extract_notification<N: lsp_types::notification::Notification>(
+    notification: Notification,
+) -> Result<N::Params, BoxError> {
+    notification.extract(N::METHOD).map_err(extract_error)
+}
+
+fn extract_error<T>(error: ExtractError<T>) -> BoxError {
+    match error {
+        ExtractError::MethodMismatch(_) => "Unexpected message method".into(),
+        ExtractError::JsonError { method, error } => format!("Invalid `{}` params: {}", method, error).into(),
+    }
+}
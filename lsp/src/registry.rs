@@ -0,0 +1,186 @@
+use crate::analysis::is_template_file;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use utree::library::{parse_file_recovering, TemplateDefinition};
+
+// Extension of the template files
+pub const EXTENSION: &str = "utree";
+
+// Build and dependency directories, which are not searched along with the hidden ones
+const SKIPPED_DIRECTORIES: &[&str] = &["target", "node_modules"];
+
+// Component templates available for `$component` references. Expression file is a component
+// named by its path relative to one of the registry roots: `$footer` is `footer.utree`, and
+// `$layout%header` is `layout/header.utree`. Templates of the template files (see
+// `utree::library`) are components named after their definitions. Roots are searched in order.
+//
+// Files of each root are read once and cached until `invalidate` is called for them; clones of
+// the registry share the cache.
+#[derive(Debug, Clone, Default)]
+pub struct ComponentRegistry {
+    roots: Vec<PathBuf>,
+    cache: Rc<RefCell<HashMap<PathBuf, Rc<Vec<RegistryFile>>>>>,
+}
+
+#[derive(Debug)]
+struct RegistryFile {
+    path: PathBuf,
+    // Component name of the expression file, `None` for the template file
+    component: Option<String>,
+    // Templates of the template file
+    definitions: Vec<TemplateDefinition>,
+}
+
+impl ComponentRegistry {
+    pub fn new(roots: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self { roots: roots.into_iter().collect(), cache: Rc::default() }
+    }
+
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.roots.push(root.into());
+        self
+    }
+
+    // Drops cached files of the roots containing the changed path
+    pub fn invalidate(&self, path: &Path) {
+        self.cache.borrow_mut().retain(|root, _| !path.starts_with(root));
+    }
+
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    // File of the component, and offset of its definition in the file
+    pub fn resolve(&self, component: &str) -> Option<(PathBuf, usize)> {
+        let files = self.files();
+        match files.iter().flat_map(|files| files.iter()).find(|file| file.component.as_deref() == Some(component)) {
+            Some(file) => Some((file.path.clone(), 0)),
+            None => self.template(component),
+        }
+    }
+
+    // File and offset of the template defined in one of the template files
+    pub fn template(&self, name: &str) -> Option<(PathBuf, usize)> {
+        self.files().iter().flat_map(|files| files.iter()).find_map(|file| {
+            let definition = file.definitions.iter().find(|d| d.name == name)?;
+            Some((file.path.clone(), definition.offset))
+        })
+    }
+
+    // Names of all components in the registry, e.g. `layout%header`
+    pub fn components(&self) -> Vec<String> {
+        let mut components = vec![];
+        for file in self.files().iter().flat_map(|files| files.iter()) {
+            components.extend(file.component.clone());
+            components.extend(file.definitions.iter().map(|d| d.name.clone()));
+        }
+        components.sort();
+        components.dedup();
+        components
+    }
+
+    // `.utree` files of each root, in order of the roots
+    fn files(&self) -> Vec<Rc<Vec<RegistryFile>>> {
+        let mut cache = self.cache.borrow_mut();
+        self.roots.iter().map(|root| cache.entry(root.clone()).or_insert_with(|| Rc::new(scan(root))).clone()).collect()
+    }
+}
+
+// Reads all `.utree` files under the root. Symbolic links to directories are not followed, so
+// the walk can't loop.
+fn scan(root: &Path) -> Vec<RegistryFile> {
+    let mut files = vec![];
+    let mut stack = vec![root.to_owned()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if !is_skipped(&entry.file_name()) {
+                    stack.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                files.extend(read_file(root, path));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+}
+
+fn read_file(root: &Path, path: PathBuf) -> Option<RegistryFile> {
+    let text = std::fs::read_to_string(&path).ok()?;
+    let file = match is_template_file(&text) {
+        true => RegistryFile { component: None, definitions: parse_file_recovering(&text).0, path },
+        false => RegistryFile { component: Some(component_path(root, &path)?), definitions: vec![], path },
+    };
+    Some(file)
+}
+
+fn is_skipped(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with('.') || SKIPPED_DIRECTORIES.contains(&name.as_ref())
+}
+
+fn component_path(root: &Path, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(root).ok()?.with_extension("");
+    let parts = relative.iter().map(|part| part.to_str()).collect::<Option<Vec<_>>>()?;
+    Some(parts.join("%"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_components() {
+        let root = std::env::temp_dir().join(format!("utree-registry-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("layout")).unwrap();
+        std::fs::write(root.join("footer.utree"), "footer").unwrap();
+        std::fs::write(root.join("layout/header.utree"), "header").unwrap();
+        std::fs::write(root.join("notes.txt"), "").unwrap();
+        std::fs::write(root.join("layout/parts.utree"), "import '../footer.utree';\nlet menu = nav;\nlet side = aside;").unwrap();
+
+        let registry = ComponentRegistry::default().with_root(root.join("missing")).with_root(&root);
+        assert_eq!(registry.components(), ["footer", "layout%header", "menu", "side"]);
+        assert_eq!(registry.resolve("layout%header"), Some((root.join("layout/header.utree"), 0)));
+        assert_eq!(registry.resolve("header"), None);
+        assert_eq!(registry.resolve("side"), Some((root.join("layout/parts.utree"), 46)));
+        assert_eq!(registry.resolve("layout%parts"), None);
+        assert_eq!(registry.template("footer"), None);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn cached_files() {
+        let root = std::env::temp_dir().join(format!("utree-registry-cache-{}", std::process::id()));
+        for dir in ["parts", ".git", "target/debug", "node_modules/x"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+            std::fs::write(root.join(dir).join("hidden.utree"), "p").unwrap();
+        }
+        // Symbolic link cycle isn't followed
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&root, root.join("parts/loop")).unwrap();
+
+        let registry = ComponentRegistry::default().with_root(&root);
+        assert_eq!(registry.components(), ["parts%hidden"]);
+
+        // Files are read again only after they are changed
+        std::fs::write(root.join("footer.utree"), "footer").unwrap();
+        assert_eq!(registry.clone().components(), ["parts%hidden"]);
+        registry.invalidate(&root.join("parts/other.utree"));
+        assert_eq!(registry.components(), ["footer", "parts%hidden"]);
+        assert_eq!(registry.resolve("footer"), Some((root.join("footer.utree"), 0)));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+// Minimal LSP client talking to the server binary over stdio
+struct Client {
+    server: Child,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_utree-lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(server.stdout.take().unwrap());
+        Self { server, stdout, next_id: 0 }
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let stdin = self.server.stdin.as_mut().unwrap();
+        write!(stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            self.stdout.read_line(&mut header).unwrap();
+            match header.trim_end() {
+                "" => break,
+                header => if let Some(value) = header.strip_prefix("Content-Length: ") {
+                    length = value.parse().unwrap();
+                },
+            }
+        }
+        let mut body = vec![0; length];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let message = self.receive();
+            if message["id"] == id {
+                return message["result"].clone();
+            }
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    fn diagnostics(&mut self) -> Value {
+        loop {
+            let message = self.receive();
+            if message["method"] == "textDocument/publishDiagnostics" {
+                return message["params"]["diagnostics"].clone();
+            }
+        }
+    }
+}
+
+#[test]
+fn stdio_session() {
+    let root = std::env::temp_dir().join(format!("utree-lsp-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("footer.utree"), "footer>{bye}").unwrap();
+    let uri = format!("file://{}/page.utree", root.display());
+    let document = json!({ "uri": uri });
+    let at = |line: u32, character: u32| {
+        json!({ "textDocument": document, "position": { "line": line, "character": character } })
+    };
+
+    let mut client = Client::start();
+    let result = client.request("initialize", json!({ "capabilities": {} }));
+    assert_eq!(result["capabilities"]["documentFormattingProvider"], true);
+    client.notify("initialized", json!({}));
+
+    let text = "ul>\n  li[n=99999999999999999999]";
+    client.notify("textDocument/didOpen", json!({
+        "textDocument": { "uri": uri, "languageId": "utree", "version": 1, "text": text },
+    }));
+    let diagnostics = client.diagnostics();
+    assert_eq!(diagnostics[0]["message"], "Invalid number literal `99999999999999999999`");
+    assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 1, "character": 7 }));
+
+    let text = "ul > (li>{@items%name}) * @items + $footer";
+    client.notify("textDocument/didChange", json!({
+        "textDocument": { "uri": uri, "version": 2 },
+        "contentChanges": [{ "text": text }],
+    }));
+    assert_eq!(client.diagnostics(), json!([]));
+
+    let hover = client.request("textDocument/hover", at(0, 0));
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("- [COLLECTION] @items"));
+
+    let completion = client.request("textDocument/completion", at(0, 37));
+    assert_eq!(completion[0]["label"], "footer");
+
+    let definition = client.request("textDocument/definition", at(0, 38));
+    assert_eq!(definition["uri"], format!("file://{}/footer.utree", root.display()));
+
+    let edits = client.request("textDocument/formatting", json!({
+        "textDocument": document,
+        "options": { "tabSize": 4, "insertSpaces": true },
+    }));
+    assert_eq!(edits[0]["newText"], "ul>(li>{@items%name})*@items+$footer");
+
+    assert_eq!(client.request("shutdown", Value::Null), Value::Null);
+    client.notify("exit", Value::Null);
+    assert!(client.server.wait().unwrap().success());
+    std::fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,128 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+use utree::tree::{
+    AttributeValue, CollectionTreeNode, Float, GenericValue, InnerContentTreeNode, NormalTreeNode,
+    PropertyBinding, RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+
+// Parses μTree expression at compile time and expands into `utree::builder` calls
+// producing `utree::tree::TreeNode`, e.g. `utree!("ul>li.item*3")`
+#[proc_macro]
+pub fn utree(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    expand(parse_macro_input!(input as LitStr)).into()
+}
+
+fn expand(literal: LitStr) -> TokenStream {
+    match TreeNode::parse(&literal.value()) {
+        Ok(tree) => expand_node(&tree),
+        Err(e) => {
+            let message = format!("Invalid μTree expression: {}", e);
+            syn::Error::new(literal.span(), message).to_compile_error()
+        },
+    }
+}
+
+fn expand_node(node: &TreeNode) -> TokenStream {
+    match node {
+        TreeNode::Root(RootTreeNode { children }) => {
+            let children = expand_nodes(children);
+            quote!(::utree::builder::root(#children))
+        },
+        TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+            let mut attrs = vec![];
+            for attribute in attributes {
+                let attr_name = &attribute.name;
+                let values = match &attribute.value {
+                    AttributeValue::None => {
+                        attrs.push(quote!(.flag(#attr_name)));
+                        continue;
+                    },
+                    AttributeValue::Single(value) => vec![value],
+                    AttributeValue::Multiple(values) => values.iter().collect(),
+                };
+                for value in values {
+                    let value = expand_value(value);
+                    attrs.push(quote!(.attr(#attr_name, #value)));
+                }
+            }
+            let children = children.iter().map(expand_node);
+            quote! {
+                ::utree::builder::node(#name)
+                    #(#attrs)*
+                    #(.child(#children))*
+                    .build()
+            }
+        },
+        TreeNode::InnerContent(InnerContentTreeNode { value, raw: false }) => {
+            let value = expand_value(value);
+            quote!(::utree::builder::text(#value))
+        },
+        TreeNode::InnerContent(InnerContentTreeNode { value, raw: true }) => {
+            let value = expand_value(value);
+            quote!(::utree::builder::raw_text(#value))
+        },
+        TreeNode::Subtree(SubtreeTreeNode { property }) => {
+            let property = expand_binding(property);
+            quote!(::utree::builder::subtree(#property))
+        },
+        TreeNode::Collection(CollectionTreeNode { nodes, collection, alias: None, key: None }) => {
+            let collection = expand_binding(collection);
+            let nodes = expand_nodes(nodes);
+            quote!(::utree::builder::collection(#collection, #nodes))
+        },
+        TreeNode::Collection(CollectionTreeNode { nodes, collection, alias: Some(alias), key: None }) => {
+            let collection = expand_binding(collection);
+            let nodes = expand_nodes(nodes);
+            quote!(::utree::builder::collection_as(#collection, #alias, #nodes))
+        },
+        TreeNode::Collection(CollectionTreeNode { nodes, collection, alias, key: Some(key) }) => {
+            let collection = expand_binding(collection);
+            let alias = match alias {
+                Some(alias) => quote!(::std::option::Option::Some(#alias)),
+                None => quote!(::std::option::Option::None),
+            };
+            let key = expand_binding(key);
+            let nodes = expand_nodes(nodes);
+            quote!(::utree::builder::keyed_collection(#collection, #alias, #key, #nodes))
+        },
+        TreeNode::Error(_) => unreachable!("Expression is parsed without recovery"),
+    }
+}
+
+fn expand_nodes(nodes: &[TreeNode]) -> TokenStream {
+    let nodes = nodes.iter().map(expand_node);
+    quote!(::std::vec::Vec::<::utree::tree::TreeNode>::from([#(#nodes),*]))
+}
+
+fn expand_value(value: &GenericValue) -> TokenStream {
+    match value {
+        GenericValue::Text(text) => quote!(#text),
+        GenericValue::Number(number) => quote!(#number),
+        GenericValue::Float(Float(number)) => quote!(#number),
+        GenericValue::Bool(value) => quote!(#value),
+        GenericValue::Binding(binding) => expand_binding(binding),
+    }
+}
+
+fn expand_binding(binding: &PropertyBinding) -> TokenStream {
+    let path = match binding {
+        PropertyBinding::RootIdentifier(ident) => ident.clone(),
+        PropertyBinding::NestedIdentifier(idents) => idents.join("%"),
+    };
+    // Path was parsed from the expression, so its components are never empty
+    quote!(::utree::tree::PropertyBinding::from(#path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proc_macro2::Span;
+
+    #[test]
+    fn invalid_expression_is_compile_error() {
+        let tokens = expand(LitStr::new("div>", Span::call_site())).to_string();
+        assert!(tokens.starts_with(":: core :: compile_error !"), "{}", tokens);
+        assert!(tokens.contains("Invalid μTree expression"), "{}", tokens);
+    }
+}
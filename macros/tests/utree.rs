@@ -0,0 +1,36 @@
+use utree::tree::TreeNode;
+use utree_macros::utree;
+
+#[test]
+fn expands_to_parsed_tree() {
+    let expressions = [
+        (utree!("ul>li.item*3"), "ul>li.item*3"),
+        (
+            utree!("div#main.a.b[k=1 title='hello world']>{hi @user%name}+$footer%view"),
+            "div#main.a.b[k=1 title='hello world']>{hi @user%name}+$footer%view",
+        ),
+        (
+            utree!("ul>(li[id=@items%id]>{@items%name}) * @items"),
+            "ul>(li[id=@items%id]>{@items%name}) * @items",
+        ),
+        (utree!("div>!{'<b>raw</b>' @html}"), "div>!{'<b>raw</b>' @html}"),
+        (utree!("div[o=0.5 n=-3 e=true]>{1e3}"), "div[o=0.5 n=-3 e=true]>{1e3}"),
+        (
+            utree!("ul>(li[id=@item%id]>{@index @item%name})*@items as item"),
+            "ul>(li[id=@item%id]>{@index @item%name})*@items as item",
+        ),
+        (utree!("ul>li*@items key @items%code"), "ul>li*@items key @items%code"),
+        (
+            utree!("ul>(li>{@item%name})*@items as item key @item%code"),
+            "ul>(li>{@item%name})*@items as item key @item%code",
+        ),
+        (
+            utree!(r#"input[type=checkbox checked]+{'it\'s \"quoted\"'}"#),
+            r#"input[type=checkbox checked]+{'it\'s \"quoted\"'}"#,
+        ),
+    ];
+
+    for (expanded, source) in expressions {
+        assert_eq!(expanded, TreeNode::parse(source).unwrap());
+    }
+}
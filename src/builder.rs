@@ -0,0 +1,189 @@
+use crate::tree::{
+    CollectionTreeNode, GenericValue, InnerContentTreeNode, NormalTreeNode, ParseError,
+    PropertyBinding, RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+
+// Fluent builder for the normal tree node, e.g.
+// `node("div").id("x").class("a").attr("k", 1).child(text("hi"))`
+#[derive(Debug, Clone)]
+pub struct NodeBuilder {
+    node: NormalTreeNode,
+}
+
+impl NodeBuilder {
+    pub fn id(self, id: impl Into<String>) -> Self {
+        self.attr("id", GenericValue::Text(id.into()))
+    }
+
+    pub fn class(self, class: impl Into<String>) -> Self {
+        self.attr("class", GenericValue::Text(class.into()))
+    }
+
+    // Same semantics as `[name=value]` in the expression: repeated attribute values are appended
+    pub fn attr(mut self, name: &str, value: impl Into<GenericValue>) -> Self {
+        self.node.append_attribute(name, value.into());
+        self
+    }
+
+    // Valueless attribute, same as `[name]` in the expression
+    pub fn flag(mut self, name: &str) -> Self {
+        self.node.append_flag(name);
+        self
+    }
+
+    pub fn child(mut self, child: impl Into<TreeNode>) -> Self {
+        self.node.children.push(child.into());
+        self
+    }
+
+    pub fn children<T: Into<TreeNode>>(mut self, children: impl IntoIterator<Item = T>) -> Self {
+        self.node.children.extend(children.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn build(self) -> TreeNode {
+        self.node.into()
+    }
+}
+
+impl From<NodeBuilder> for TreeNode {
+    fn from(builder: NodeBuilder) -> Self {
+        builder.build()
+    }
+}
+
+pub fn node(name: impl Into<String>) -> NodeBuilder {
+    NodeBuilder {
+        node: NormalTreeNode {
+            name: name.into(),
+            attributes: vec![],
+            children: vec![],
+        },
+    }
+}
+
+pub fn root<T: Into<TreeNode>>(children: impl IntoIterator<Item = T>) -> TreeNode {
+    RootTreeNode {
+        children: children.into_iter().map(Into::into).collect(),
+    }.into()
+}
+
+pub fn text(value: impl Into<GenericValue>) -> TreeNode {
+    InnerContentTreeNode { value: value.into(), raw: false }.into()
+}
+
+// Trusted markup content, which is rendered without escaping
+pub fn raw_text(value: impl Into<GenericValue>) -> TreeNode {
+    InnerContentTreeNode { value: value.into(), raw: true }.into()
+}
+
+// Property binding from `%`-separated path, e.g. `binding("items%name")` is `@items%name`;
+// `None` if the path has empty components, e.g. "" or "items%%name". Paths given to `subtree` and
+// `collection` as strings must be valid, otherwise they panic
+pub fn binding(path: &str) -> Option<PropertyBinding> {
+    PropertyBinding::from_parts(path.split('%').map(str::to_owned).collect())
+}
+
+pub fn subtree(property: impl Into<PropertyBinding>) -> TreeNode {
+    SubtreeTreeNode { property: property.into() }.into()
+}
+
+pub fn collection<T: Into<TreeNode>>(
+    collection: impl Into<PropertyBinding>,
+    nodes: impl IntoIterator<Item = T>,
+) -> TreeNode {
+    CollectionTreeNode {
+        nodes: nodes.into_iter().map(Into::into).collect(),
+        collection: collection.into(),
+        alias: None,
+        key: None,
+    }.into()
+}
+
+// Collection with the loop variable, e.g. `* @items as item`
+pub fn collection_as<T: Into<TreeNode>>(
+    collection: impl Into<PropertyBinding>,
+    alias: &str,
+    nodes: impl IntoIterator<Item = T>,
+) -> TreeNode {
+    CollectionTreeNode {
+        nodes: nodes.into_iter().map(Into::into).collect(),
+        collection: collection.into(),
+        alias: Some(alias.to_owned()),
+        key: None,
+    }.into()
+}
+
+// Collection with the item key and optional loop variable, e.g. `* @items as item key @item%id`
+pub fn keyed_collection<T: Into<TreeNode>>(
+    collection: impl Into<PropertyBinding>,
+    alias: Option<&str>,
+    key: impl Into<PropertyBinding>,
+    nodes: impl IntoIterator<Item = T>,
+) -> TreeNode {
+    CollectionTreeNode {
+        nodes: nodes.into_iter().map(Into::into).collect(),
+        collection: collection.into(),
+        alias: alias.map(str::to_owned),
+        key: Some(key.into()),
+    }.into()
+}
+
+// Parses expression and returns its top-level nodes, so they could be mixed with built ones
+pub fn fragment(input: &str) -> Result<Vec<TreeNode>, ParseError> {
+    match TreeNode::parse(input)? {
+        TreeNode::Root(RootTreeNode { children }) => Ok(children),
+        _ => unreachable!("Parsed tree always have root node"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::snapshot;
+    use expect_test::expect;
+
+    #[test]
+    fn build_tree() {
+        let tree = root([
+            node("div")
+                .id("main")
+                .class("a")
+                .class("b")
+                .attr("k", 1)
+                .attr("title", "hello")
+                .child(text("hi"))
+                .child(collection("items", [node("li").attr("id", binding("items%id").unwrap())]))
+                .child(subtree("footer%view")),
+        ]);
+
+        expect![[r#"
+            - div[id="main" class={"a", "b"} k=1 title="hello"]
+              - [CONTENT] "hi"
+              - [COLLECTION] @items
+                - li[id=@items%id]
+              - [SUBTREE] @footer%view
+        "#]].assert_eq(&snapshot(&tree));
+
+        assert_eq!(
+            tree,
+            TreeNode::parse("div#main.a.b[k=1 title=hello]>{hi}+li[id=@items%id]*@items+$footer%view")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn mix_with_fragments() {
+        let tree = root([node("ul").children(fragment("li.item*2+li>{last}").unwrap())]);
+        assert_eq!(tree, TreeNode::parse("ul>li.item*2+li>{last}").unwrap());
+    }
+
+    #[test]
+    fn invalid_bindings() {
+        assert_eq!(binding("items"), Some(PropertyBinding::RootIdentifier("items".to_owned())));
+        assert_eq!(binding(""), None);
+        assert_eq!(binding("items%"), None);
+        assert_eq!(binding("items%%name"), None);
+        assert_eq!(PropertyBinding::from_parts(vec![]), None);
+    }
+}
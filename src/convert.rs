@@ -0,0 +1,713 @@
+use crate::html::is_void_element;
+use crate::render::{Markup, PREFORMATTED_ELEMENTS};
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, ErrorTreeNode, GenericValue, InnerContentTreeNode, NormalTreeNode,
+    RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+use thiserror::Error;
+
+// Elements which content is not parsed as markup
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ConvertError {
+    #[error("Unexpected end of input at {line}:{column}")]
+    UnexpectedEnd { line: usize, column: usize },
+    #[error("Closing tag `{found}` doesn't match element `{expected}` at {line}:{column}")]
+    MismatchedTag { expected: String, found: String, line: usize, column: usize },
+    #[error("Unexpected closing tag `{name}` at {line}:{column}")]
+    UnexpectedClosingTag { name: String, line: usize, column: usize },
+    #[error("Element `{name}` at {line}:{column} is not closed")]
+    UnclosedElement { name: String, line: usize, column: usize },
+    #[error("Name `{name}` at {line}:{column} is not a valid identifier")]
+    InvalidName { name: String, line: usize, column: usize },
+}
+
+// Converts HTML/XML document into the shortest equivalent μTree expression
+pub fn convert(input: &str, markup: Markup) -> Result<String, ConvertError> {
+    Ok(to_expression(&parse_markup(input, markup)?))
+}
+
+// Parses HTML/XML document into the tree. Comments, doctype and processing instructions are
+// skipped. Outside of the preformatted elements, whitespace is collapsed, whitespace-only text
+// between elements is dropped, and text at the element boundaries is trimmed. HTML element and
+// attribute names are lowercased, void elements don't need the end tag; `class` attribute is
+// split into separate values.
+pub fn parse_markup(input: &str, markup: Markup) -> Result<TreeNode, ConvertError> {
+    MarkupParser { input, pos: 0, markup }.parse()
+}
+
+struct OpenElement {
+    node: NormalTreeNode,
+    pos: usize,
+}
+
+struct MarkupParser<'a> {
+    input: &'a str,
+    pos: usize,
+    markup: Markup,
+}
+
+impl MarkupParser<'_> {
+    fn parse(mut self) -> Result<TreeNode, ConvertError> {
+        let mut root = vec![];
+        let mut stack: Vec<OpenElement> = vec![];
+
+        while self.pos < self.input.len() {
+            let rest = &self.input[self.pos..];
+            let preformatted = stack.iter().any(|e| PREFORMATTED_ELEMENTS.contains(&e.node.name.as_str()));
+            let children = match stack.last_mut() {
+                Some(element) => &mut element.node.children,
+                None => &mut root,
+            };
+
+            if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if let Some(data) = rest.strip_prefix("<![CDATA[") {
+                let end = data.find("]]>").ok_or_else(|| self.unexpected_end())?;
+                push_text(children, data[..end].to_owned());
+                self.pos += "<![CDATA[".len() + end + "]]>".len();
+            } else if rest.starts_with("<!") || rest.starts_with("<?") {
+                self.skip_past(">")?;
+            } else if rest.starts_with("</") {
+                let start = self.pos;
+                self.pos += 2;
+                let name = self.name()?;
+                self.skip_whitespace();
+                self.expect('>')?;
+                let element = match stack.pop() {
+                    Some(element) if element.node.name == name => element,
+                    Some(element) => {
+                        let (line, column) = self.line_col(start);
+                        return Err(ConvertError::MismatchedTag {
+                            expected: element.node.name,
+                            found: name,
+                            line,
+                            column,
+                        });
+                    },
+                    None => {
+                        let (line, column) = self.line_col(start);
+                        return Err(ConvertError::UnexpectedClosingTag { name, line, column });
+                    },
+                };
+                let mut node = element.node;
+                if !preformatted {
+                    trim_text(&mut node.children);
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.node.children.push(node.into()),
+                    None => root.push(node.into()),
+                }
+            } else if rest.starts_with('<') && rest[1..].starts_with(|c: char| c.is_alphabetic() || c == '_') {
+                let start = self.pos;
+                let (node, closed) = self.start_tag()?;
+                if closed || (self.markup == Markup::Html && is_void_element(&node.name)) {
+                    children.push(node.into());
+                } else if self.markup == Markup::Html && RAW_TEXT_ELEMENTS.contains(&node.name.as_str()) {
+                    let mut node = node;
+                    let end_tag = format!("</{}", node.name);
+                    let end = self.input[self.pos..].to_ascii_lowercase().find(&end_tag).ok_or_else(|| {
+                        let (line, column) = self.line_col(start);
+                        ConvertError::UnclosedElement { name: node.name.clone(), line, column }
+                    })?;
+                    push_text(&mut node.children, self.input[self.pos..self.pos + end].to_owned());
+                    self.pos += end + end_tag.len();
+                    self.skip_past(">")?;
+                    children.push(node.into());
+                } else {
+                    stack.push(OpenElement { node, pos: start });
+                }
+            } else {
+                // Text starts with `<` that doesn't open a tag, so the search begins after it
+                let first = rest.chars().next().map_or(0, char::len_utf8);
+                let end = rest[first..].find('<').map(|i| i + first).unwrap_or(rest.len());
+                let text = decode_entities(&rest[..end]);
+                self.pos += end;
+                if preformatted {
+                    push_text(children, text);
+                } else {
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    let text = match (text.is_empty(), rest.starts_with(char::is_whitespace)) {
+                        (true, _) => continue,
+                        (false, true) => format!(" {}", text),
+                        (false, false) => text,
+                    };
+                    let text = match rest[..end].ends_with(char::is_whitespace) {
+                        true => text + " ",
+                        false => text,
+                    };
+                    push_text(children, text);
+                }
+            }
+        }
+
+        if let Some(element) = stack.pop() {
+            let (line, column) = self.line_col(element.pos);
+            return Err(ConvertError::UnclosedElement { name: element.node.name, line, column });
+        }
+        trim_text(&mut root);
+        Ok(RootTreeNode { children: root }.into())
+    }
+
+    // Parses start tag, returns the element and whether it is self-closed
+    fn start_tag(&mut self) -> Result<(NormalTreeNode, bool), ConvertError> {
+        self.pos += 1;
+        let mut node = NormalTreeNode { name: self.name()?, attributes: vec![], children: vec![] };
+        loop {
+            self.skip_whitespace();
+            let rest = &self.input[self.pos..];
+            if rest.starts_with("/>") {
+                self.pos += 2;
+                return Ok((node, true));
+            }
+            if rest.starts_with('>') {
+                self.pos += 1;
+                return Ok((node, false));
+            }
+
+            let name = self.name()?;
+            self.skip_whitespace();
+            if !self.input[self.pos..].starts_with('=') {
+                node.append_flag(&name);
+                continue;
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+            let value = decode_entities(self.attribute_value()?);
+            if name == "class" {
+                for class in value.split_whitespace() {
+                    node.append_attribute(&name, GenericValue::Text(class.to_owned()));
+                }
+            } else {
+                node.append_attribute(&name, GenericValue::Text(value));
+            }
+        }
+    }
+
+    fn attribute_value(&mut self) -> Result<&str, ConvertError> {
+        let rest = &self.input[self.pos..];
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let len = rest[1..].find(quote).ok_or_else(|| self.unexpected_end())?;
+                self.pos += len + 2;
+                &rest[1..len + 1]
+            },
+            Some(_) => {
+                let len = rest.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(rest.len());
+                self.pos += len;
+                &rest[..len]
+            },
+            None => return Err(self.unexpected_end()),
+        };
+        Ok(value)
+    }
+
+    // Element or attribute name, which should be valid μTree identifier
+    fn name(&mut self) -> Result<String, ConvertError> {
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|c: char| c.is_whitespace() || "/>=".contains(c)).unwrap_or(rest.len());
+        if len == rest.len() {
+            return Err(self.unexpected_end());
+        }
+        let name = match self.markup {
+            Markup::Html => rest[..len].to_ascii_lowercase(),
+            Markup::Xml => rest[..len].to_owned(),
+        };
+        if !is_ident(&name) {
+            let (line, column) = self.line_col(self.pos);
+            return Err(ConvertError::InvalidName { name, line, column });
+        }
+        self.pos += len;
+        Ok(name)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ConvertError> {
+        match self.input[self.pos..].starts_with(c) {
+            true => {
+                self.pos += c.len_utf8();
+                Ok(())
+            },
+            false => Err(self.unexpected_end()),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn skip_past(&mut self, pattern: &str) -> Result<(), ConvertError> {
+        let end = self.input[self.pos..].find(pattern).ok_or_else(|| self.unexpected_end())?;
+        self.pos += end + pattern.len();
+        Ok(())
+    }
+
+    fn unexpected_end(&self) -> ConvertError {
+        let (line, column) = self.line_col(self.input.len());
+        ConvertError::UnexpectedEnd { line, column }
+    }
+
+    fn line_col(&self, pos: usize) -> (usize, usize) {
+        let before = &self.input[..pos];
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+    }
+}
+
+fn push_text(children: &mut Vec<TreeNode>, text: String) {
+    if let Some(TreeNode::InnerContent(InnerContentTreeNode { value: GenericValue::Text(last), raw: false })) =
+        children.last_mut()
+    {
+        last.push_str(&text);
+        return;
+    }
+    children.push(InnerContentTreeNode { value: GenericValue::Text(text), raw: false }.into());
+}
+
+// Trims leading whitespace of the first and trailing whitespace of the last text node
+fn trim_text(children: &mut Vec<TreeNode>) {
+    if let Some(TreeNode::InnerContent(InnerContentTreeNode { value: GenericValue::Text(text), .. })) =
+        children.first_mut()
+    {
+        *text = text.trim_start().to_owned();
+    }
+    if let Some(TreeNode::InnerContent(InnerContentTreeNode { value: GenericValue::Text(text), .. })) =
+        children.last_mut()
+    {
+        *text = text.trim_end().to_owned();
+    }
+    children.retain(|c| !matches!(
+        c,
+        TreeNode::InnerContent(InnerContentTreeNode { value: GenericValue::Text(text), .. }) if text.is_empty()
+    ));
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let c = entity.and_then(|entity| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some('\u{a0}'),
+            _ => {
+                let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                };
+                code.and_then(char::from_u32)
+            },
+        });
+        match (c, entity) {
+            (Some(c), Some(entity)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            },
+            // Unknown entities are kept as is
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn is_ident(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Whether the word is `lorem` or `lorem20`, which are parsed as the generated text
+fn is_lorem(word: &str) -> bool {
+    word.strip_prefix("lorem").is_some_and(|count| count.bytes().all(|b| b.is_ascii_digit()))
+}
+
+// Emits the shortest expression which is parsed into the same tree, except for the text
+// content: adjacent text literals are merged, so `{hello world}` is emitted for the text
+// `"hello world"`. Identical siblings are folded into `*N` when it makes expression shorter,
+// and `#id`/`.class` shorthands are used for identifier-like values.
+pub fn to_expression(tree: &TreeNode) -> String {
+    let mut out = String::new();
+    write_siblings(std::slice::from_ref(tree), &mut out);
+    out
+}
+
+// Item of the sibling list
+enum Sibling<'a> {
+    Node(&'a TreeNode),
+    Content(Vec<&'a InnerContentTreeNode>),
+}
+
+fn flatten_siblings<'a>(nodes: &'a [TreeNode], siblings: &mut Vec<Sibling<'a>>) {
+    for node in nodes {
+        match node {
+            TreeNode::Root(RootTreeNode { children }) => flatten_siblings(children, siblings),
+            TreeNode::InnerContent(content) => match siblings.last_mut() {
+                Some(Sibling::Content(run)) if run[0].raw == content.raw => run.push(content),
+                _ => siblings.push(Sibling::Content(vec![content])),
+            },
+            node => siblings.push(Sibling::Node(node)),
+        }
+    }
+}
+
+fn write_siblings(nodes: &[TreeNode], out: &mut String) {
+    let mut siblings = vec![];
+    flatten_siblings(nodes, &mut siblings);
+
+    let mut i = 0;
+    while i < siblings.len() {
+        if i != 0 {
+            out.push('+');
+        }
+        let node = match &siblings[i] {
+            Sibling::Content(run) => {
+                write_content(run, out);
+                i += 1;
+                continue;
+            },
+            Sibling::Node(node) => *node,
+        };
+
+        let count = siblings[i..]
+            .iter()
+            .take_while(|s| matches!(s, Sibling::Node(other) if *other == node))
+            .count();
+        i += count;
+        let is_last = i == siblings.len();
+
+        let mut term = String::new();
+        let has_children = write_node(node, &mut term);
+        // Node with children is grouped, unless it is the last sibling
+        let groups = match (has_children, is_last) {
+            (false, _) => 0,
+            (true, false) => count,
+            (true, true) => count - 1,
+        };
+        let folded_len = term.len() + if has_children { 2 } else { 0 } + 1 + count.to_string().len();
+        let unfolded_len = count * term.len() + (count - 1) + 2 * groups;
+
+        if count > 1 && folded_len < unfolded_len {
+            match has_children {
+                true => out.push_str(&format!("({})*{}", term, count)),
+                false => out.push_str(&format!("{}*{}", term, count)),
+            }
+            continue;
+        }
+        for copy in 0..count {
+            if copy != 0 {
+                out.push('+');
+            }
+            match has_children && !(is_last && copy == count - 1) {
+                true => out.push_str(&format!("({})", term)),
+                false => out.push_str(&term),
+            }
+        }
+    }
+}
+
+// Writes node expression, returns whether it has `>` operator and should be grouped when
+// followed by siblings
+fn write_node(node: &TreeNode, out: &mut String) -> bool {
+    match node {
+        TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+            out.push_str(name);
+            write_attributes(attributes, out);
+            // `lorem` without properties is the generated text
+            if attributes.is_empty() && is_lorem(name) {
+                out.push_str("[]");
+            }
+            if children.is_empty() {
+                return false;
+            }
+            out.push('>');
+            write_siblings(children, out);
+            true
+        },
+        TreeNode::Subtree(SubtreeTreeNode { property }) => {
+            out.push('$');
+            out.push_str(&property.to_string()[1..]);
+            false
+        },
+        TreeNode::Collection(CollectionTreeNode { nodes, collection, alias, key }) => {
+            let mut body = String::new();
+            write_siblings(nodes, &mut body);
+            let is_term = match nodes.as_slice() {
+                [TreeNode::Normal(NormalTreeNode { children, .. })] => children.is_empty(),
+                [TreeNode::Subtree(_)] => true,
+                _ => false,
+            };
+            match is_term {
+                true => out.push_str(&body),
+                false => out.push_str(&format!("({})", body)),
+            }
+            out.push('*');
+            out.push_str(&collection.to_string());
+            if let Some(alias) = alias {
+                out.push_str(" as ");
+                out.push_str(alias);
+            }
+            if let Some(key) = key {
+                out.push_str(" key ");
+                out.push_str(&key.to_string());
+            }
+            false
+        },
+        // Invalid part is kept as is
+        TreeNode::Error(ErrorTreeNode { source, children, .. }) => {
+            out.push_str(source);
+            if children.is_empty() {
+                return false;
+            }
+            out.push('>');
+            write_siblings(children, out);
+            true
+        },
+        TreeNode::Root(_) | TreeNode::InnerContent(_) => unreachable!("Flattened into siblings"),
+    }
+}
+
+fn write_attributes(attributes: &[Attribute], out: &mut String) {
+    let mut in_brackets = false;
+    for attribute in attributes {
+        let values = match &attribute.value {
+            AttributeValue::None => &[][..],
+            AttributeValue::Single(value) => std::slice::from_ref(value),
+            AttributeValue::Multiple(values) => values.as_slice(),
+        };
+        let shorthand = match attribute.name.as_str() {
+            "id" => Some('#'),
+            "class" => Some('.'),
+            _ => None,
+        };
+
+        if values.is_empty() {
+            out.push_str(if in_brackets { " " } else { "[" });
+            out.push_str(&attribute.name);
+            in_brackets = true;
+        }
+        for value in values {
+            match (shorthand, value) {
+                (Some(prefix), GenericValue::Text(text)) if is_ident(text) => {
+                    if in_brackets {
+                        out.push(']');
+                        in_brackets = false;
+                    }
+                    out.push(prefix);
+                    out.push_str(text);
+                },
+                (_, value) => {
+                    out.push_str(if in_brackets { " " } else { "[" });
+                    out.push_str(&attribute.name);
+                    out.push('=');
+                    write_value(value, out);
+                    in_brackets = true;
+                },
+            }
+        }
+    }
+    if in_brackets {
+        out.push(']');
+    }
+}
+
+fn write_value(value: &GenericValue, out: &mut String) {
+    match value {
+        GenericValue::Text(text) if is_ident(text) && text != "true" && text != "false" => out.push_str(text),
+        GenericValue::Text(text) => write_string(text, out),
+        value => out.push_str(&value.to_string()),
+    }
+}
+
+fn write_string(text: &str, out: &mut String) {
+    out.push('\'');
+    for c in text.chars() {
+        match c {
+            '\'' | '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            },
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+}
+
+// Writes run of the content nodes. Values inside of `{}` are separated with the implicit space,
+// so adjacent values without space between them are placed into separate groups.
+fn write_content(run: &[&InnerContentTreeNode], out: &mut String) {
+    // Merge adjacent text values, empty text is not rendered
+    let mut values: Vec<GenericValue> = vec![];
+    for content in run {
+        match (values.last_mut(), &content.value) {
+            (_, GenericValue::Text(text)) if text.is_empty() => {},
+            (Some(GenericValue::Text(last)), GenericValue::Text(text)) => last.push_str(text),
+            (_, value) => values.push(value.clone()),
+        }
+    }
+    if values.is_empty() {
+        values.push(GenericValue::Text(String::new()));
+    }
+
+    let open = if run[0].raw { "!{" } else { "{" };
+    out.push_str(open);
+    // Whether the previous value was followed by space, which is replaced with the implicit one
+    let mut spaced = false;
+    for (i, value) in values.iter().enumerate() {
+        let mut value = value.clone();
+        if i != 0 {
+            if let GenericValue::Text(text) = &mut value {
+                if !spaced && text.starts_with(' ') {
+                    text.remove(0);
+                    spaced = true;
+                }
+                // Single space between the values is the implicit one
+                if text.is_empty() {
+                    continue;
+                }
+            }
+            match spaced {
+                true => out.push(' '),
+                false => {
+                    out.push_str("}+");
+                    out.push_str(open);
+                },
+            }
+        }
+        spaced = false;
+        if let GenericValue::Text(text) = &mut value {
+            if i + 1 != values.len() && text.ends_with(' ') {
+                text.pop();
+                spaced = true;
+            }
+        }
+        match &value {
+            // `{lorem ...}` is the generated text
+            GenericValue::Text(text) if text.split(' ').next() == Some("lorem") => write_string(text, out),
+            GenericValue::Text(text) if !text.is_empty() && text.split(' ').all(|word| {
+                is_ident(word) && word != "true" && word != "false"
+            }) => out.push_str(text),
+            value => write_value(value, out),
+        }
+    }
+    out.push('}');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{render_html, Renderer};
+    use expect_test::expect;
+
+    fn assert_converted(input: &str, markup: Markup, expect: expect_test::Expect) {
+        let tree = parse_markup(input, markup).unwrap();
+        let expression = to_expression(&tree);
+        expect.assert_eq(&expression);
+        // Expression is parsed back into the equivalent tree
+        let parsed = TreeNode::parse(&expression).unwrap();
+        let renderer = Renderer::new(markup);
+        assert_eq!(renderer.render(&parsed).unwrap(), renderer.render(&tree).unwrap());
+        assert_eq!(to_expression(&parsed), expression);
+    }
+
+    #[test]
+    fn convert_html() {
+        assert_converted(
+            r#"
+            <!DOCTYPE html>
+            <html>
+              <body class="main dark">
+                <!-- navigation -->
+                <ul id="menu">
+                  <li><a href="/">Home</a></li>
+                  <li><a href="/">Home</a></li>
+                  <li><a href="/">Home</a></li>
+                </ul>
+                <p>Hello, <b>world</b>! Tom &amp; Jerry's</p>
+                <input type=checkbox checked><br><br>
+                <div class="a:b c" data-x="1"></div><hr><hr>
+                <pre>  keep
+   it </pre>
+                <script>if (a < b) {}</script>
+              </body>
+            </html>
+            "#,
+            Markup::Html,
+            expect![[r#"html>body.main.dark>(ul#menu>(li>a[href='/']>{Home})*3)+(p>{'Hello, '}+(b>{world})+{'! Tom & Jerry\'s'})+input[type=checkbox checked]+br*2+div[class='a:b'].c[data-x='1']+hr*2+(pre>{'  keep\n   it '})+script>{'if (a < b) {}'}"#]],
+        );
+
+        assert_converted("<p>a</p><p>a</p><p>a</p>", Markup::Html, expect![["(p>{a})*3"]]);
+        assert_converted("<br/><br/><br/><br/>", Markup::Xml, expect![["br*4"]]);
+    }
+
+    #[test]
+    fn convert_lorem() {
+        assert_converted("<p>lorem</p>", Markup::Html, expect![["p>{'lorem'}"]]);
+        assert_converted("<p>lorem 5</p>", Markup::Html, expect![["p>{'lorem 5'}"]]);
+        assert_converted("<p>lorem 2 sentences</p><p>a lorem</p>", Markup::Html,
+            expect![["(p>{'lorem 2 sentences'})+p>{a lorem}"]]);
+        assert_converted("<lorem></lorem>", Markup::Html, expect![["lorem[]"]]);
+        assert_converted("<lorem3></lorem3><lorem3></lorem3>", Markup::Xml, expect![["lorem3[]*2"]]);
+        assert_converted("<lorem><b>x</b></lorem><lorem-ipsum/>", Markup::Xml, expect![["(lorem[]>b>{x})+lorem-ipsum"]]);
+
+        let tree = TreeNode::parse("p>{'lorem' 5}+{'lorem'}").unwrap();
+        expect!["p>{'lorem' 5}+{'lorem'}"].assert_eq(&to_expression(&tree));
+        assert_eq!(TreeNode::parse(&to_expression(&tree)).unwrap(), tree);
+    }
+
+    #[test]
+    fn convert_non_ascii_text() {
+        assert_converted("<p>é</p>", Markup::Html, expect![["p>{'é'}"]]);
+        assert_converted("<p>ünïcödé</p>", Markup::Xml, expect![["p>{'ünïcödé'}"]]);
+        assert_converted("<p>Привет, <b>мир</b></p>", Markup::Html, expect![["p>{'Привет, '}+b>{'мир'}"]]);
+    }
+
+    #[test]
+    fn convert_xml() {
+        assert_converted(
+            r#"<?xml version="1.0"?>
+            <Config><Item Name="a"/><Item Name="a"/><Item Name="a"/><Note><![CDATA[<raw>]]></Note></Config>"#,
+            Markup::Xml,
+            expect![[r#"Config>Item[Name=a]*3+Note>{'<raw>'}"#]],
+        );
+
+        let errors = ["<a><b></a>", "<a>", "</a>", "<a x:y=1>", "<a"]
+            .iter()
+            .map(|input| parse_markup(input, Markup::Xml).unwrap_err().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors, [
+            "Closing tag `a` doesn't match element `b` at 1:7",
+            "Element `a` at 1:1 is not closed",
+            "Unexpected closing tag `a` at 1:1",
+            "Name `x:y` at 1:4 is not a valid identifier",
+            "Unexpected end of input at 1:3",
+        ]);
+    }
+
+    #[test]
+    fn tree_expression() {
+        let tree = TreeNode::parse(
+            "ul>(li[id=@items%id]>{@items%name}+$items%extra)*@items as item+{@total'%' -1 2.5 true}+!{@html}",
+        ).unwrap();
+        let expression = to_expression(&tree);
+        expect!["ul>(li[id=@items%id]>{@items%name}+$items%extra)*@items as item+{@total '%' -1 2.5 true}+!{@html}"]
+            .assert_eq(&expression);
+        assert_eq!(render_html(&TreeNode::parse(&expression).unwrap()).unwrap_err().to_string(),
+            render_html(&tree).unwrap_err().to_string());
+
+        let tree = TreeNode::parse("ul>(li>{@item%name})*@items as item key @item%code").unwrap();
+        expect!["ul>(li>{@item%name})*@items as item key @item%code"].assert_eq(&to_expression(&tree));
+    }
+}
@@ -0,0 +1,537 @@
+use crate::eval::EvalError;
+use crate::render::{Indent, IoAdapter, RenderError, RenderOptions};
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, GenericValue, InnerContentTreeNode, NormalTreeNode,
+    RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+use std::collections::HashSet;
+use std::fmt::Write;
+
+// Name of the anonymous element, which represents an array item
+pub const ARRAY_ITEM: &str = "_";
+
+// Field name for the text content of the element which also has attributes or child elements
+pub const TEXT_FIELD: &str = "#text";
+
+// Data document produced from the evaluated tree. Object fields keep the document order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataValue {
+    Null,
+    Bool(bool),
+    Number(isize),
+    Float(f64),
+    Text(String),
+    Array(Vec<DataValue>),
+    Object(Vec<(String, DataValue)>),
+}
+
+impl DataValue {
+    // Converts evaluated tree to the data document using the following convention:
+    // - element name becomes the field name, attributes and child elements become fields of the
+    //   nested object; valueless attributes are `true`, attributes with multiple values are arrays
+    // - repeated fields (siblings with the same name) are collected into arrays
+    // - element which contains only text becomes a scalar; single literal keeps its type
+    //   (`{42}` is a number), while multiple text nodes are joined into a string. Text of the
+    //   element with other fields is stored in `#text` field
+    // - element containing only anonymous `_` elements becomes an array, even with zero or one
+    //   item, e.g. `ports>_>{80}` is `{"ports": [80]}`
+    // - empty element is `null`
+    // Top-level nodes follow the same rules as element children.
+    pub fn from_tree(tree: &TreeNode) -> Result<DataValue, RenderError> {
+        DataValue::from_template(tree, &TreeNode::Root(RootTreeNode { children: vec![] }))
+    }
+
+    // Same as `from_tree`, but the elements holding collection of `_` elements in the template
+    // are arrays even when the collection has no items, e.g. `hosts>(_>{@hosts%name})*@hosts`
+    // is `{"hosts": []}` for the empty `@hosts`. Evaluated tree alone can't tell them from the
+    // empty elements.
+    pub fn from_template(tree: &TreeNode, template: &TreeNode) -> Result<DataValue, RenderError> {
+        let mut arrays = HashSet::new();
+        collect_arrays(std::slice::from_ref(template), &mut vec![], &mut arrays);
+        element_value(&[], std::slice::from_ref(tree), &mut vec![], &arrays)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DataFormat {
+    Json,
+    // Pretty options produce block style YAML, minified ones - flow style, which is also JSON
+    Yaml,
+}
+
+// Renderer of the evaluated tree (see `eval::evaluate`) into the data document
+#[derive(Debug, Clone)]
+pub struct DataRenderer {
+    format: DataFormat,
+    options: RenderOptions,
+    // Template the rendered trees are evaluated from (see `DataValue::from_template`)
+    template: Option<TreeNode>,
+}
+
+impl DataRenderer {
+    pub fn new(format: DataFormat) -> Self {
+        Self { format, options: RenderOptions::default(), template: None }
+    }
+
+    pub fn with_options(mut self, options: RenderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_template(mut self, template: TreeNode) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    pub fn render(&self, tree: &TreeNode) -> Result<String, RenderError> {
+        let mut out = String::new();
+        self.render_to(tree, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn render_to(&self, tree: &TreeNode, out: &mut impl Write) -> Result<(), RenderError> {
+        let value = match &self.template {
+            Some(template) => DataValue::from_template(tree, template)?,
+            None => DataValue::from_tree(tree)?,
+        };
+        match self.format {
+            DataFormat::Yaml if self.options.is_pretty() => {
+                // Tabs are not allowed for indentation in YAML
+                let options = match self.options.indent() {
+                    Some(Indent::Tabs) => self.options.clone().with_indent(Indent::Spaces(2)),
+                    _ => self.options.clone(),
+                };
+                write_yaml_block(&value, 0, false, &options, out)?;
+            },
+            DataFormat::Json | DataFormat::Yaml => {
+                write_json(&value, 0, &self.options, out)?;
+                if self.options.is_pretty() {
+                    out.write_str(self.options.line_ending())?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn render_to_io(&self, tree: &TreeNode, out: &mut impl std::io::Write) -> Result<(), RenderError> {
+        let mut adapter = IoAdapter::new(out);
+        let result = self.render_to(tree, &mut adapter);
+        adapter.finish(result)
+    }
+}
+
+// Renders evaluated tree as pretty-printed JSON document
+pub fn render_json(tree: &TreeNode) -> Result<String, RenderError> {
+    DataRenderer::new(DataFormat::Json).with_options(RenderOptions::pretty()).render(tree)
+}
+
+// Renders evaluated tree as YAML document
+pub fn render_yaml(tree: &TreeNode) -> Result<String, RenderError> {
+    DataRenderer::new(DataFormat::Yaml).with_options(RenderOptions::pretty()).render(tree)
+}
+
+fn scalar_value(value: &GenericValue) -> Result<DataValue, RenderError> {
+    let value = match value {
+        GenericValue::Text(s) => DataValue::Text(s.clone()),
+        GenericValue::Number(n) => DataValue::Number(*n),
+        GenericValue::Float(n) => DataValue::Float(n.0),
+        GenericValue::Bool(b) => DataValue::Bool(*b),
+        GenericValue::Binding(binding) => return Err(RenderError::UnresolvedBinding(binding.clone())),
+    };
+    Ok(value)
+}
+
+fn attribute_value(attribute: &Attribute) -> Result<DataValue, RenderError> {
+    match &attribute.value {
+        AttributeValue::None => Ok(DataValue::Bool(true)),
+        AttributeValue::Single(value) => scalar_value(value),
+        AttributeValue::Multiple(values) => {
+            Ok(DataValue::Array(values.iter().map(scalar_value).collect::<Result<_, _>>()?))
+        },
+    }
+}
+
+// Collects element name paths of the template, which hold collection of `_` elements
+fn collect_arrays(nodes: &[TreeNode], path: &mut Vec<String>, arrays: &mut HashSet<Vec<String>>) {
+    for node in nodes {
+        match node {
+            TreeNode::Normal(NormalTreeNode { name, children, .. }) => {
+                path.push(name.clone());
+                collect_arrays(children, path, arrays);
+                path.pop();
+            },
+            TreeNode::Root(RootTreeNode { children }) => collect_arrays(children, path, arrays),
+            TreeNode::Collection(CollectionTreeNode { nodes, .. }) => {
+                if has_array_items(nodes) {
+                    arrays.insert(path.clone());
+                }
+                collect_arrays(nodes, path, arrays);
+            },
+            TreeNode::InnerContent(_) | TreeNode::Subtree(_) | TreeNode::Error(_) => {},
+        }
+    }
+}
+
+fn has_array_items(nodes: &[TreeNode]) -> bool {
+    nodes.iter().any(|node| match node {
+        TreeNode::Normal(NormalTreeNode { name, .. }) => name == ARRAY_ITEM,
+        TreeNode::Root(RootTreeNode { children }) => has_array_items(children),
+        _ => false,
+    })
+}
+
+// `path` is the element names from the top, `arrays` are paths of the elements which are always
+// arrays (see `collect_arrays`)
+fn element_value(
+    attributes: &[Attribute],
+    children: &[TreeNode],
+    path: &mut Vec<String>,
+    arrays: &HashSet<Vec<String>>,
+) -> Result<DataValue, RenderError> {
+    let mut text = vec![];
+    let mut elements = vec![];
+    // Nested roots are transparent
+    let mut stack = children.iter().rev().collect::<Vec<_>>();
+    while let Some(child) = stack.pop() {
+        match child {
+            TreeNode::Root(RootTreeNode { children }) => stack.extend(children.iter().rev()),
+            TreeNode::Normal(node) => elements.push(node),
+            TreeNode::InnerContent(InnerContentTreeNode { value, .. }) => text.push(value),
+            TreeNode::Subtree(SubtreeTreeNode { property }) => {
+                return Err(RenderError::UnresolvedBinding(property.clone()));
+            },
+            TreeNode::Collection(CollectionTreeNode { collection, .. }) => {
+                return Err(RenderError::UnresolvedBinding(collection.clone()));
+            },
+            TreeNode::Error(node) => return Err(EvalError::from(node).into()),
+        }
+    }
+
+    let text = match text.as_slice() {
+        [] => None,
+        [value] => Some(scalar_value(value)?),
+        values => {
+            let mut joined = String::new();
+            for value in values {
+                match value {
+                    GenericValue::Binding(binding) => return Err(RenderError::UnresolvedBinding(binding.clone())),
+                    value => joined.push_str(&value.to_text().unwrap_or_default()),
+                }
+            }
+            Some(DataValue::Text(joined))
+        },
+    };
+
+    if attributes.is_empty() && text.is_none() && (!elements.is_empty() || arrays.contains(path))
+        && elements.iter().all(|e| e.name == ARRAY_ITEM)
+    {
+        let items = elements.iter().map(|e| child_value(e, path, arrays));
+        return Ok(DataValue::Array(items.collect::<Result<_, _>>()?));
+    }
+    if attributes.is_empty() && elements.is_empty() {
+        return Ok(text.unwrap_or(DataValue::Null));
+    }
+
+    // Values of the each field, in order of the first occurrence
+    let mut fields: Vec<(String, Vec<DataValue>)> = vec![];
+    let mut push_field = |name: &str, value: DataValue| {
+        match fields.iter_mut().find(|(field, _)| field == name) {
+            Some((_, values)) => values.push(value),
+            None => fields.push((name.to_owned(), vec![value])),
+        }
+    };
+    for attribute in attributes {
+        push_field(&attribute.name, attribute_value(attribute)?);
+    }
+    if let Some(text) = text {
+        push_field(TEXT_FIELD, text);
+    }
+    for element in elements {
+        push_field(&element.name, child_value(element, path, arrays)?);
+    }
+
+    let fields = fields.into_iter().map(|(name, mut values)| {
+        let value = match values.len() {
+            1 => values.pop().expect("Field has single value"),
+            _ => DataValue::Array(values),
+        };
+        (name, value)
+    });
+    Ok(DataValue::Object(fields.collect()))
+}
+
+fn child_value(
+    element: &NormalTreeNode,
+    path: &mut Vec<String>,
+    arrays: &HashSet<Vec<String>>,
+) -> Result<DataValue, RenderError> {
+    path.push(element.name.clone());
+    let value = element_value(&element.attributes, &element.children, path, arrays);
+    path.pop();
+    value
+}
+
+fn write_json_string(s: &str, out: &mut impl Write) -> std::fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+fn write_float(n: f64, out: &mut impl Write) -> std::fmt::Result {
+    // JSON doesn't support special float values
+    if n.is_finite() {
+        write!(out, "{:?}", n)
+    } else {
+        out.write_str("null")
+    }
+}
+
+fn write_json_scalar(value: &DataValue, out: &mut impl Write) -> std::fmt::Result {
+    match value {
+        DataValue::Null => out.write_str("null"),
+        DataValue::Bool(b) => write!(out, "{}", b),
+        DataValue::Number(n) => write!(out, "{}", n),
+        DataValue::Float(n) => write_float(*n, out),
+        DataValue::Text(s) => write_json_string(s, out),
+        DataValue::Array(items) if items.is_empty() => out.write_str("[]"),
+        DataValue::Object(fields) if fields.is_empty() => out.write_str("{}"),
+        DataValue::Array(_) | DataValue::Object(_) => unreachable!("Value is not a scalar"),
+    }
+}
+
+fn write_json(value: &DataValue, depth: usize, options: &RenderOptions, out: &mut impl Write) -> std::fmt::Result {
+    let pretty = options.is_pretty();
+    let (open, close, len) = match value {
+        DataValue::Array(items) if !items.is_empty() => ('[', ']', items.len()),
+        DataValue::Object(fields) if !fields.is_empty() => ('{', '}', fields.len()),
+        value => return write_json_scalar(value, out),
+    };
+
+    out.write_char(open)?;
+    for i in 0..len {
+        if i != 0 {
+            out.write_char(',')?;
+        }
+        if pretty {
+            out.write_str(options.line_ending())?;
+            options.write_indent(out, depth + 1)?;
+        }
+        match value {
+            DataValue::Array(items) => write_json(&items[i], depth + 1, options, out)?,
+            DataValue::Object(fields) => {
+                let (name, value) = &fields[i];
+                write_json_string(name, out)?;
+                out.write_str(if pretty { ": " } else { ":" })?;
+                write_json(value, depth + 1, options, out)?;
+            },
+            _ => unreachable!("Only arrays and objects have items"),
+        }
+    }
+    if pretty {
+        out.write_str(options.line_ending())?;
+        options.write_indent(out, depth)?;
+    }
+    out.write_char(close)
+}
+
+// Plain (unquoted) YAML scalars are used only when they can't be mistaken for the other type
+fn write_yaml_string(s: &str, out: &mut impl Write) -> std::fmt::Result {
+    const RESERVED: &[&str] = &["true", "false", "yes", "no", "on", "off", "null", "~", "y", "n"];
+    let is_plain = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '/')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || " _-./".contains(c))
+        && !s.ends_with(' ')
+        && !RESERVED.contains(&s.to_ascii_lowercase().as_str());
+    if is_plain {
+        out.write_str(s)
+    } else {
+        write_json_string(s, out)
+    }
+}
+
+fn write_yaml_scalar(value: &DataValue, out: &mut impl Write) -> std::fmt::Result {
+    match value {
+        DataValue::Text(s) => write_yaml_string(s, out),
+        DataValue::Float(n) if n.is_nan() => out.write_str(".nan"),
+        DataValue::Float(n) if n.is_infinite() => out.write_str(if *n > 0.0 { ".inf" } else { "-.inf" }),
+        value => write_json_scalar(value, out),
+    }
+}
+
+fn is_block(value: &DataValue) -> bool {
+    match value {
+        DataValue::Array(items) => !items.is_empty(),
+        DataValue::Object(fields) => !fields.is_empty(),
+        _ => false,
+    }
+}
+
+// Writes value in the block style at `depth`. When `inline` is set, first line continues the
+// sequence item marker (`- `) which is already written.
+fn write_yaml_block(
+    value: &DataValue,
+    depth: usize,
+    inline: bool,
+    options: &RenderOptions,
+    out: &mut impl Write,
+) -> std::fmt::Result {
+    let line_ending = options.line_ending();
+    match value {
+        DataValue::Array(items) if !items.is_empty() => {
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 || !inline {
+                    options.write_indent(out, depth)?;
+                }
+                out.write_str("- ")?;
+                if is_block(item) {
+                    write_yaml_block(item, depth + 1, true, options, out)?;
+                } else {
+                    write_yaml_scalar(item, out)?;
+                    out.write_str(line_ending)?;
+                }
+            }
+        },
+        DataValue::Object(fields) if !fields.is_empty() => {
+            for (i, (name, value)) in fields.iter().enumerate() {
+                if i != 0 || !inline {
+                    options.write_indent(out, depth)?;
+                }
+                write_yaml_string(name, out)?;
+                out.write_char(':')?;
+                if is_block(value) {
+                    out.write_str(line_ending)?;
+                    write_yaml_block(value, depth + 1, false, options, out)?;
+                } else {
+                    out.write_char(' ')?;
+                    write_yaml_scalar(value, out)?;
+                    out.write_str(line_ending)?;
+                }
+            }
+        },
+        value => {
+            write_yaml_scalar(value, out)?;
+            out.write_str(line_ending)?;
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::evaluate;
+    use crate::value::Value;
+    use expect_test::expect;
+
+    fn config() -> TreeNode {
+        let tree = TreeNode::parse(
+            "server[name=@name debug=true]>(port>{8080})+(host>{@host})+(host>{backup 2})+(tags>_>{web})+(ratio>{0.5})+empty+(limits[soft=-1 hard=1e3]>{'note: ok'})+users>(_[admin=@user%admin]>{@user%name})*@users as user",
+        ).unwrap();
+        let data = Value::object([
+            ("name", Value::from("main")),
+            ("host", Value::from("example.com")),
+            ("users", Value::from(vec![
+                Value::object([("name", Value::from("ann")), ("admin", Value::from(true))]),
+                Value::object([("name", Value::from("bob")), ("admin", Value::from(false))]),
+            ])),
+        ]);
+        evaluate(&tree, &data).unwrap()
+    }
+
+    #[test]
+    fn json() {
+        expect![[r##"
+            {
+              "server": {
+                "name": "main",
+                "debug": true,
+                "port": 8080,
+                "host": [
+                  "example.com",
+                  "backup 2"
+                ],
+                "tags": [
+                  "web"
+                ],
+                "ratio": 0.5,
+                "empty": null,
+                "limits": {
+                  "soft": -1,
+                  "hard": 1000.0,
+                  "#text": "note: ok"
+                },
+                "users": [
+                  {
+                    "admin": true,
+                    "#text": "ann"
+                  },
+                  {
+                    "admin": false,
+                    "#text": "bob"
+                  }
+                ]
+              }
+            }
+        "##]].assert_eq(&render_json(&config()).unwrap());
+
+        let renderer = DataRenderer::new(DataFormat::Json);
+        // Empty `_` items collections are arrays only if the template is known
+        let tree = TreeNode::parse("server>(hosts>(_>{@hosts%name})*@hosts)+(ports>(port>{@ports})*@ports)").unwrap();
+        let data = Value::object([("hosts", Value::from(Vec::<Value>::new())), ("ports", Value::from(Vec::<Value>::new()))]);
+        let evaluated = evaluate(&tree, &data).unwrap();
+        expect![[r##"{"server":{"hosts":null,"ports":null}}"##]].assert_eq(&renderer.render(&evaluated).unwrap());
+        expect![[r##"{"server":{"hosts":[],"ports":null}}"##]]
+            .assert_eq(&renderer.clone().with_template(tree).render(&evaluated).unwrap());
+        let tree = TreeNode::parse("(_>(tags>(_>{@tag})*@item%tags as tag)+(none>_*@item%tags))*@items as item").unwrap();
+        let data = Value::object([("items", Value::from(vec![Value::object([("tags", Vec::<Value>::new())])]))]);
+        expect![[r##"[{"tags":[],"none":[]}]"##]]
+            .assert_eq(&renderer.clone().with_template(tree.clone()).render(&evaluate(&tree, &data).unwrap()).unwrap());
+        let tree = TreeNode::parse("a>{1}+(b>_)+c").unwrap();
+        expect![[r##"{"a":{"#text":1,"b":[null],"c":null}}"##]].assert_eq(&renderer.render(&tree).unwrap());
+        assert_eq!(renderer.render(&TreeNode::parse("_*2").unwrap()).unwrap(), "[null,null]");
+        assert_eq!(renderer.render(&TreeNode::parse("{-3}").unwrap()).unwrap(), "-3");
+        assert_eq!(
+            renderer.render(&TreeNode::parse("a>{@x}").unwrap()).unwrap_err().to_string(),
+            "Binding @x should be evaluated before rendering"
+        );
+    }
+
+    #[test]
+    fn yaml() {
+        expect![[r##"
+            server:
+              name: main
+              debug: true
+              port: 8080
+              host:
+                - example.com
+                - backup 2
+              tags:
+                - web
+              ratio: 0.5
+              empty: null
+              limits:
+                soft: -1
+                hard: 1000.0
+                "#text": "note: ok"
+              users:
+                - admin: true
+                  "#text": ann
+                - admin: false
+                  "#text": bob
+        "##]].assert_eq(&render_yaml(&config()).unwrap());
+
+        let renderer = DataRenderer::new(DataFormat::Yaml);
+        assert_eq!(renderer.render(&TreeNode::parse("a>_>b>{yes}").unwrap()).unwrap(), r#"{"a":[{"b":"yes"}]}"#);
+        assert_eq!(render_yaml(&TreeNode::parse("(_>_>{1})+_>a>{x}").unwrap()).unwrap(), "- - 1\n- a: x\n");
+    }
+}
@@ -0,0 +1,224 @@
+use crate::lorem::Lorem;
+use crate::parser::Rule;
+use crate::tree::{GenericValue, PropertyBinding, TreeBuildError};
+use pest::iterators::Pair;
+use pest::Span;
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DefinitionError {
+    #[error("`{0}` is not defined")]
+    NotDefined(String),
+    #[error("`{0}` is defined twice")]
+    DefinedTwice(String),
+    #[error("Definition is recursive: {0}")]
+    Recursive(String),
+    #[error("`{name}` takes {expected} argument(s), but {found} given")]
+    ArgumentCount { name: String, expected: usize, found: usize },
+    #[error("`{parameter}` is used as a binding, but `{argument}` is given")]
+    NotABinding { parameter: String, argument: String },
+    #[error("Use of `{0}` can't have id, class or attributes")]
+    UseWithProperties(String),
+}
+
+// Definitions (`let name(params) = ...;`) and definition arguments visible at some point of the
+// expression. Definitions of one expression see each other, and their bodies are built in the
+// scope of the definition rather than of the use. Parameters are referred to as bindings, which
+// are replaced with the arguments when the body is built.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DefinitionScope<'i> {
+    frame: Option<Rc<Frame<'i>>>,
+    // Definitions being expanded, outermost first, identified by their source span (definitions
+    // may come from the different sources, see `library` module)
+    expanding: Vec<(Span<'i>, &'i str)>,
+    // Generator of the `lorem` text, shared by all scopes of the expression, so every use of
+    // `lorem` (including the expanded ones) gets the different text
+    lorem: Rc<RefCell<Lorem>>,
+}
+
+#[derive(Debug)]
+struct Frame<'i> {
+    definitions: Vec<Definition<'i>>,
+    // Frames of the other sources, which are searched after the own definitions
+    imports: Vec<Rc<Frame<'i>>>,
+    arguments: Vec<(&'i str, GenericValue)>,
+    parent: Option<Rc<Frame<'i>>>,
+}
+
+#[derive(Debug)]
+struct Definition<'i> {
+    span: Span<'i>,
+    name: &'i str,
+    parameters: Vec<&'i str>,
+    body: Pair<'i, Rule>,
+}
+
+impl<'i> DefinitionScope<'i> {
+    pub(crate) fn new(lorem: Lorem) -> Self {
+        Self { frame: None, expanding: vec![], lorem: Rc::new(RefCell::new(lorem)) }
+    }
+
+    pub(crate) fn lorem(&self) -> RefMut<'_, Lorem> {
+        self.lorem.borrow_mut()
+    }
+
+    // Scope of the expression with its `definition` pairs
+    pub(crate) fn with_definitions(&self, pairs: Vec<Pair<'i, Rule>>) -> Result<Self, TreeBuildError> {
+        if pairs.is_empty() {
+            return Ok(self.clone());
+        }
+        let mut definitions: Vec<Definition> = vec![];
+        for pair in pairs {
+            let span = pair.as_span();
+            let mut inner = pair.into_inner().skip_while(|p| p.as_rule() == Rule::let_keyword);
+            let name = inner.next().expect("Definition has name");
+            if definitions.iter().any(|d| d.name == name.as_str()) {
+                return Err(error(&name, DefinitionError::DefinedTwice(name.as_str().to_owned())));
+            }
+            let mut body = inner.next().expect("Definition has body");
+            let mut parameters = vec![];
+            if body.as_rule() == Rule::parameters {
+                for parameter in body.into_inner() {
+                    if parameters.contains(&parameter.as_str()) {
+                        return Err(error(&parameter, DefinitionError::DefinedTwice(parameter.as_str().to_owned())));
+                    }
+                    parameters.push(parameter.as_str());
+                }
+                body = inner.next().expect("Definition has body");
+            }
+            definitions.push(Definition { span, name: name.as_str(), parameters, body });
+        }
+        let frame = Frame { definitions, imports: vec![], arguments: vec![], parent: self.frame.clone() };
+        Ok(Self { frame: Some(Rc::new(frame)), expanding: self.expanding.clone(), lorem: self.lorem.clone() })
+    }
+
+    // Scope which also sees the definitions of the other scopes (e.g. of the imported files);
+    // their bodies are still built in their own scopes
+    pub(crate) fn with_imports(&self, imports: impl IntoIterator<Item = DefinitionScope<'i>>) -> Self {
+        let frame = Frame {
+            definitions: vec![],
+            imports: imports.into_iter().filter_map(|scope| scope.frame).collect(),
+            arguments: vec![],
+            parent: self.frame.clone(),
+        };
+        Self { frame: Some(Rc::new(frame)), expanding: self.expanding.clone(), lorem: self.lorem.clone() }
+    }
+
+    pub(crate) fn is_defined(&self, name: &str) -> bool {
+        self.definition(name).is_some()
+    }
+
+    // Body of the definition used with `arguments`, and the scope to build it in; `name` is the
+    // pair of the name at the use site
+    pub(crate) fn expand(
+        &self,
+        name: &Pair<'i, Rule>,
+        arguments: Vec<GenericValue>,
+    ) -> Result<(Self, Pair<'i, Rule>), TreeBuildError> {
+        let Some((frame, definition)) = self.definition(name.as_str()) else {
+            return Err(error(name, DefinitionError::NotDefined(name.as_str().to_owned())));
+        };
+        if definition.parameters.len() != arguments.len() {
+            return Err(error(name, DefinitionError::ArgumentCount {
+                name: definition.name.to_owned(),
+                expected: definition.parameters.len(),
+                found: arguments.len(),
+            }));
+        }
+        let recursive = self.expanding.iter().any(|(span, _)| *span == definition.span);
+        let expanded = self.expanded(frame, definition, arguments);
+        if recursive {
+            let chain = expanded.expanding.iter().map(|(_, name)| *name).collect::<Vec<_>>().join(" -> ");
+            return Err(error(name, DefinitionError::Recursive(chain)));
+        }
+        Ok((expanded, definition.body.clone()))
+    }
+
+    // Body of the definition used as a standalone template, and the scope to build it in; its
+    // parameters stay bindings, which are resolved against the data. Panics if `name` is not
+    // defined.
+    pub(crate) fn expand_template(&self, name: &str) -> (Self, Pair<'i, Rule>) {
+        let (frame, definition) = self.definition(name).expect("Template is defined");
+        let arguments = definition.parameters.iter().map(|p| GenericValue::Binding((*p).into())).collect();
+        (self.expanded(frame, definition, arguments), definition.body.clone())
+    }
+
+    fn expanded(&self, frame: &Rc<Frame<'i>>, definition: &Definition<'i>, arguments: Vec<GenericValue>) -> Self {
+        let mut expanding = self.expanding.clone();
+        expanding.push((definition.span, definition.name));
+        let frame = Frame {
+            definitions: vec![],
+            imports: vec![],
+            arguments: definition.parameters.iter().copied().zip(arguments).collect(),
+            parent: Some(frame.clone()),
+        };
+        Self { frame: Some(Rc::new(frame)), expanding, lorem: self.lorem.clone() }
+    }
+
+    // Value of the binding, which may refer to the parameter; `pair` is the binding source
+    pub(crate) fn substitute(&self, binding: PropertyBinding, pair: &Pair<Rule>) -> Result<GenericValue, TreeBuildError> {
+        let parts = binding.parts();
+        let Some(argument) = self.argument(&parts[0]) else {
+            return Ok(GenericValue::Binding(binding));
+        };
+        match argument {
+            GenericValue::Binding(argument) => {
+                let parts = [argument.parts(), &parts[1..]].concat();
+                let binding = PropertyBinding::from_parts(parts).expect("Argument binding has parts");
+                Ok(GenericValue::Binding(binding))
+            },
+            argument if parts.len() == 1 => Ok(argument.clone()),
+            argument => Err(error(pair, DefinitionError::NotABinding {
+                parameter: binding.to_string(),
+                argument: argument.to_string(),
+            })),
+        }
+    }
+
+    // Same as `substitute`, for the places which require binding (collections and components)
+    pub(crate) fn substitute_binding(
+        &self,
+        binding: PropertyBinding,
+        pair: &Pair<Rule>,
+    ) -> Result<PropertyBinding, TreeBuildError> {
+        let parameter = binding.to_string();
+        match self.substitute(binding, pair)? {
+            GenericValue::Binding(binding) => Ok(binding),
+            argument => Err(error(pair, DefinitionError::NotABinding { parameter, argument: argument.to_string() })),
+        }
+    }
+
+    fn definition(&self, name: &str) -> Option<(&Rc<Frame<'i>>, &Definition<'i>)> {
+        self.frame.as_ref().and_then(|frame| find_definition(frame, name))
+    }
+
+    fn argument(&self, name: &str) -> Option<&GenericValue> {
+        let mut frame = self.frame.as_ref();
+        while let Some(current) = frame {
+            if let Some((_, value)) = current.arguments.iter().find(|(parameter, _)| *parameter == name) {
+                return Some(value);
+            }
+            frame = current.parent.as_ref();
+        }
+        None
+    }
+}
+
+// Definition with its frame, searched in the frame, its imports and then its parents
+fn find_definition<'f, 'i>(frame: &'f Rc<Frame<'i>>, name: &str) -> Option<(&'f Rc<Frame<'i>>, &'f Definition<'i>)> {
+    if let Some(definition) = frame.definitions.iter().find(|d| d.name == name) {
+        return Some((frame, definition));
+    }
+    frame
+        .imports
+        .iter()
+        .chain(&frame.parent)
+        .find_map(|frame| find_definition(frame, name))
+}
+
+fn error(pair: &Pair<Rule>, error: DefinitionError) -> TreeBuildError {
+    let (line, column) = pair.as_span().start_pos().line_col();
+    TreeBuildError::Definition { error, line, column }
+}
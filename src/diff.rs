@@ -0,0 +1,274 @@
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, NormalTreeNode, RootTreeNode, TreeNode,
+};
+use std::fmt::Display;
+
+// Path to the node from the tree root, expressed as child indices on each level
+pub type NodePath = Vec<usize>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TreeDifference {
+    // Node is present only in the new tree (path points into the new tree)
+    NodeAdded { path: NodePath, node: TreeNode },
+    // Node is present only in the old tree (path points into the old tree)
+    NodeRemoved { path: NodePath, node: TreeNode },
+    // Node at the same position has different kind or name
+    NodeReplaced { path: NodePath, old: TreeNode, new: TreeNode },
+    AttributeAdded { path: NodePath, attribute: Attribute },
+    AttributeRemoved { path: NodePath, attribute: Attribute },
+    AttributeChanged { path: NodePath, name: String, old: AttributeValue, new: AttributeValue },
+}
+
+impl Display for TreeDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeDifference::NodeAdded { path, node } => {
+                write!(f, "+ {} {}", format_path(path), node_summary(node))
+            },
+            TreeDifference::NodeRemoved { path, node } => {
+                write!(f, "- {} {}", format_path(path), node_summary(node))
+            },
+            TreeDifference::NodeReplaced { path, old, new } => write!(
+                f,
+                "~ {} {} -> {}",
+                format_path(path),
+                node_summary(old),
+                node_summary(new)
+            ),
+            TreeDifference::AttributeAdded { path, attribute } => {
+                write!(f, "+ {} [{}]", format_path(path), attribute)
+            },
+            TreeDifference::AttributeRemoved { path, attribute } => {
+                write!(f, "- {} [{}]", format_path(path), attribute)
+            },
+            TreeDifference::AttributeChanged { path, name, old, new } => {
+                write!(f, "~ {} [{}={} -> {}]", format_path(path), name, old, new)
+            },
+        }
+    }
+}
+
+pub(crate) fn format_path(path: &[usize]) -> String {
+    let parts = path.iter().map(|i| i.to_string()).collect::<Vec<String>>();
+    format!("/{}", parts.join("/"))
+}
+
+fn node_summary(node: &TreeNode) -> String {
+    // Reuse debug tree representation, but keep only the first line
+    node.to_string()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim_start_matches(" - ")
+        .to_owned()
+}
+
+// Computes differences between two trees. Equal subtrees are skipped, children lists are
+// aligned by their longest common subsequence, so single insertion or removal of the node is
+// reported as one difference instead of the cascade of changes for all following siblings.
+pub fn diff(old: &TreeNode, new: &TreeNode) -> Vec<TreeDifference> {
+    let mut differences = vec![];
+    diff_node(old, new, &mut vec![], &mut differences);
+    differences
+}
+
+fn diff_node(old: &TreeNode, new: &TreeNode, path: &mut NodePath, out: &mut Vec<TreeDifference>) {
+    if old == new {
+        return;
+    }
+
+    match (old, new) {
+        (
+            TreeNode::Root(RootTreeNode { children: old_children }),
+            TreeNode::Root(RootTreeNode { children: new_children }),
+        ) => {
+            diff_children(old_children, new_children, path, out);
+        },
+        (TreeNode::Normal(old_node), TreeNode::Normal(new_node)) if old_node.name == new_node.name => {
+            diff_attributes(old_node, new_node, path, out);
+            diff_children(&old_node.children, &new_node.children, path, out);
+        },
+        (
+            TreeNode::Collection(CollectionTreeNode { nodes: old_nodes, collection: old_collection, alias: old_alias, key: old_key }),
+            TreeNode::Collection(CollectionTreeNode { nodes: new_nodes, collection: new_collection, alias: new_alias, key: new_key }),
+        ) if old_collection == new_collection && old_alias == new_alias && old_key == new_key => {
+            diff_children(old_nodes, new_nodes, path, out);
+        },
+        _ => out.push(TreeDifference::NodeReplaced {
+            path: path.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        }),
+    }
+}
+
+fn diff_attributes(
+    old: &NormalTreeNode,
+    new: &NormalTreeNode,
+    path: &NodePath,
+    out: &mut Vec<TreeDifference>,
+) {
+    for old_attr in &old.attributes {
+        match new.attributes.iter().find(|a| a.name == old_attr.name) {
+            Some(new_attr) if new_attr.value != old_attr.value => {
+                out.push(TreeDifference::AttributeChanged {
+                    path: path.clone(),
+                    name: old_attr.name.clone(),
+                    old: old_attr.value.clone(),
+                    new: new_attr.value.clone(),
+                });
+            },
+            Some(_) => {},
+            None => out.push(TreeDifference::AttributeRemoved {
+                path: path.clone(),
+                attribute: old_attr.clone(),
+            }),
+        }
+    }
+
+    for new_attr in &new.attributes {
+        if !old.attributes.iter().any(|a| a.name == new_attr.name) {
+            out.push(TreeDifference::AttributeAdded {
+                path: path.clone(),
+                attribute: new_attr.clone(),
+            });
+        }
+    }
+}
+
+fn diff_children(
+    old: &[TreeNode],
+    new: &[TreeNode],
+    path: &mut NodePath,
+    out: &mut Vec<TreeDifference>,
+) {
+    // lcs[i][j] - longest common subsequence length of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    // Unmatched nodes between two matched pairs
+    let mut removed = vec![];
+    let mut added = vec![];
+
+    loop {
+        let matched = i < old.len() && j < new.len() && old[i] == new[j];
+        if matched || (i == old.len() && j == new.len()) {
+            diff_unmatched(old, new, &removed, &added, path, out);
+            removed.clear();
+            added.clear();
+            if !matched {
+                break;
+            }
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            removed.push(i);
+            i += 1;
+        } else {
+            added.push(j);
+            j += 1;
+        }
+    }
+}
+
+fn diff_unmatched(
+    old: &[TreeNode],
+    new: &[TreeNode],
+    removed: &[usize],
+    added: &[usize],
+    path: &mut NodePath,
+    out: &mut Vec<TreeDifference>,
+) {
+    let paired = removed.len().min(added.len());
+
+    for (&i, &j) in removed.iter().zip(added.iter()) {
+        path.push(i);
+        diff_node(&old[i], &new[j], path, out);
+        path.pop();
+    }
+
+    for &i in &removed[paired..] {
+        out.push(TreeDifference::NodeRemoved { path: child_path(path, i), node: old[i].clone() });
+    }
+
+    for &j in &added[paired..] {
+        out.push(TreeDifference::NodeAdded { path: child_path(path, j), node: new[j].clone() });
+    }
+}
+
+fn child_path(path: &[usize], index: usize) -> NodePath {
+    let mut path = path.to_vec();
+    path.push(index);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expect_test::{expect, Expect};
+
+    fn assert_diff(old: &str, new: &str, expect: Expect) {
+        let differences = diff(&TreeNode::parse(old).unwrap(), &TreeNode::parse(new).unwrap())
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+        expect.assert_eq(&differences);
+    }
+
+    #[test]
+    fn equal_trees() {
+        assert_diff("div>p+a", "div > p + a", expect![[""]]);
+    }
+
+    #[test]
+    fn inserted_and_removed_nodes() {
+        assert_diff(
+            "ul>li#a+li#b+li#c",
+            "ul>li#a+li#x+li#b+li#c",
+            expect![[r#"+ /0/1 li[id="x"]"#]],
+        );
+        assert_diff(
+            "ul>li#a+li#b+li#c",
+            "ul>li#a+li#c",
+            expect![[r#"- /0/1 li[id="b"]"#]],
+        );
+        assert_diff(
+            "div>p+{text}",
+            "div>p+$sub",
+            expect![[r#"~ /0/1 [CONTENT] "text" -> [SUBTREE] @sub"#]],
+        );
+    }
+
+    #[test]
+    fn changed_attributes() {
+        assert_diff(
+            "div#main.a>p[x=1 y=2]",
+            "div#main.b>p[x=1 z=3]",
+            expect![[r#"
+                ~ /0 [class="a" -> "b"]
+                - /0/0 [y=2]
+                + /0/0 [z=3]"#]],
+        );
+    }
+
+    #[test]
+    fn replaced_nodes() {
+        assert_diff(
+            "div+(li>{@items%name})*@items",
+            "p+(li>{@items%id})*@items",
+            expect![[r#"
+                ~ /0 div -> p
+                ~ /1/0/0 [CONTENT] @items%name -> [CONTENT] @items%id"#]],
+        );
+    }
+}
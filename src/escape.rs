@@ -0,0 +1,174 @@
+use std::fmt::{Display, Write};
+
+// Position of the value in the rendered document, which defines how it should be escaped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EscapeContext {
+    // Element text content
+    Text,
+    // Content of the HTML raw text elements (`script`, `style`), where entities are not decoded
+    RawText,
+    // Quoted attribute value
+    Attribute,
+    // Quoted attribute value which is interpreted as URL (`href`, `src`, etc.)
+    UrlAttribute,
+    // Trusted markup, written as is
+    Trusted,
+}
+
+// HTML elements, content of which is not parsed as markup
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+const URL_ATTRIBUTES: &[&str] = &[
+    "action", "background", "cite", "codebase", "data", "formaction", "href", "icon", "longdesc",
+    "manifest", "poster", "src", "usemap",
+];
+
+impl EscapeContext {
+    // Context for text node placed inside of the given element
+    pub fn for_text(parent: Option<&str>) -> Self {
+        match parent {
+            Some(name) if RAW_TEXT_ELEMENTS.contains(&name.to_ascii_lowercase().as_str()) => {
+                EscapeContext::RawText
+            },
+            _ => EscapeContext::Text,
+        }
+    }
+
+    // Context for value of the given attribute
+    pub fn for_attribute(name: &str) -> Self {
+        if URL_ATTRIBUTES.contains(&name.to_ascii_lowercase().as_str()) {
+            EscapeContext::UrlAttribute
+        } else {
+            EscapeContext::Attribute
+        }
+    }
+}
+
+// Markup fragment which is trusted by the application and should be inserted without escaping
+// when bound to text content
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SafeHtml(String);
+
+impl SafeHtml {
+    pub fn new(markup: impl Into<String>) -> Self {
+        Self(markup.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SafeHtml {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub fn write_escaped(out: &mut impl Write, s: &str, context: EscapeContext) -> std::fmt::Result {
+    match context {
+        EscapeContext::Trusted => out.write_str(s),
+        EscapeContext::Text => write_replaced(out, s, |c| match c {
+            '&' => Some("&amp;"),
+            '<' => Some("&lt;"),
+            '>' => Some("&gt;"),
+            _ => None,
+        }),
+        EscapeContext::Attribute => write_attribute_escaped(out, s),
+        EscapeContext::UrlAttribute => {
+            let mut encoded = String::with_capacity(s.len());
+            for c in s.chars() {
+                if c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&'()*+,;=%".contains(c) {
+                    encoded.push(c);
+                } else {
+                    let mut buffer = [0u8; 4];
+                    for byte in c.encode_utf8(&mut buffer).bytes() {
+                        write!(encoded, "%{:02X}", byte)?;
+                    }
+                }
+            }
+            write_attribute_escaped(out, &encoded)
+        },
+        EscapeContext::RawText => {
+            // Entities are not decoded inside of raw text elements, so only prevent premature
+            // closing of the element
+            let mut parts = s.split("</");
+            out.write_str(parts.next().unwrap_or_default())?;
+            for part in parts {
+                out.write_str("<\\/")?;
+                out.write_str(part)?;
+            }
+            Ok(())
+        },
+    }
+}
+
+pub fn escape(s: &str, context: EscapeContext) -> String {
+    let mut out = String::with_capacity(s.len());
+    write_escaped(&mut out, s, context).expect("Write to string can't fail");
+    out
+}
+
+fn write_attribute_escaped(out: &mut impl Write, s: &str) -> std::fmt::Result {
+    write_replaced(out, s, |c| match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&#39;"),
+        _ => None,
+    })
+}
+
+fn write_replaced(
+    out: &mut impl Write,
+    s: &str,
+    replacement: impl Fn(char) -> Option<&'static str>,
+) -> std::fmt::Result {
+    let mut last = 0;
+    for (i, c) in s.char_indices() {
+        if let Some(replacement) = replacement(c) {
+            out.write_str(&s[last..i])?;
+            out.write_str(replacement)?;
+            last = i + c.len_utf8();
+        }
+    }
+    out.write_str(&s[last..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_contexts() {
+        let payload = r#"<script>alert("x" + 'y')</script> & co"#;
+        assert_eq!(
+            escape(payload, EscapeContext::Text),
+            r#"&lt;script&gt;alert("x" + 'y')&lt;/script&gt; &amp; co"#
+        );
+        assert_eq!(
+            escape(payload, EscapeContext::Attribute),
+            "&lt;script&gt;alert(&quot;x&quot; + &#39;y&#39;)&lt;/script&gt; &amp; co"
+        );
+        assert_eq!(
+            escape(payload, EscapeContext::UrlAttribute),
+            "%3Cscript%3Ealert(%22x%22%20+%20&#39;y&#39;)%3C/script%3E%20&amp;%20co"
+        );
+        assert_eq!(
+            escape(payload, EscapeContext::RawText),
+            r#"<script>alert("x" + 'y')<\/script> & co"#
+        );
+        assert_eq!(escape(payload, EscapeContext::Trusted), payload);
+        assert_eq!(escape("/search?q=привіт", EscapeContext::UrlAttribute), "/search?q=%D0%BF%D1%80%D0%B8%D0%B2%D1%96%D1%82");
+    }
+
+    #[test]
+    fn context_by_position() {
+        assert_eq!(EscapeContext::for_text(Some("SCRIPT")), EscapeContext::RawText);
+        assert_eq!(EscapeContext::for_text(Some("div")), EscapeContext::Text);
+        assert_eq!(EscapeContext::for_text(None), EscapeContext::Text);
+        assert_eq!(EscapeContext::for_attribute("href"), EscapeContext::UrlAttribute);
+        assert_eq!(EscapeContext::for_attribute("title"), EscapeContext::Attribute);
+    }
+}
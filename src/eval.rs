@@ -0,0 +1,316 @@
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, ErrorTreeNode, Float, GenericValue, InnerContentTreeNode,
+    NormalTreeNode, PropertyBinding, RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+use crate::value::{Scope, Value};
+use std::borrow::Cow;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EvalError {
+    #[error("Binding {0} is not found in data")]
+    BindingNotFound(PropertyBinding),
+    #[error("Binding {0} is not a collection")]
+    NotACollection(PropertyBinding),
+    #[error("Binding {0} is not a component tree")]
+    NotAComponent(PropertyBinding),
+    #[error("Binding {0} can't be represented as text")]
+    NotAScalar(PropertyBinding),
+    #[error("Component {0} is nested too deep, it is probably recursive")]
+    ComponentTooDeep(PropertyBinding),
+    #[error("Invalid expression `{expression}`: {message}")]
+    InvalidExpression { expression: String, message: String },
+}
+
+impl From<&ErrorTreeNode> for EvalError {
+    fn from(node: &ErrorTreeNode) -> Self {
+        EvalError::InvalidExpression { expression: node.source.clone(), message: node.message.clone() }
+    }
+}
+
+// Substitutes bindings with the values from `data`, expands collections and inserts
+// component subtrees. Resulting tree doesn't contain any bindings.
+pub fn evaluate(tree: &TreeNode, data: &Value) -> Result<TreeNode, EvalError> {
+    let mut scope = Scope::new(data);
+    let mut children = vec![];
+    evaluate_node(tree, &mut scope, &mut children)?;
+    Ok(RootTreeNode { children }.into())
+}
+
+pub(crate) fn resolve<'a>(
+    scope: &Scope<'a>,
+    binding: &PropertyBinding,
+) -> Result<Cow<'a, Value>, EvalError> {
+    scope.resolve(binding).ok_or_else(|| EvalError::BindingNotFound(binding.clone()))
+}
+
+pub(crate) fn resolve_scalar<'a>(
+    scope: &Scope<'a>,
+    binding: &PropertyBinding,
+) -> Result<Cow<'a, Value>, EvalError> {
+    let value = resolve(scope, binding)?;
+    if !value.is_scalar() {
+        return Err(EvalError::NotAScalar(binding.clone()));
+    }
+    Ok(value)
+}
+
+pub(crate) fn resolve_collection<'a>(
+    scope: &Scope<'a>,
+    binding: &PropertyBinding,
+) -> Result<&'a [Value], EvalError> {
+    match resolve(scope, binding)? {
+        Cow::Borrowed(Value::List(items)) => Ok(items),
+        Cow::Borrowed(Value::Null) => Ok(&[]),
+        _ => Err(EvalError::NotACollection(binding.clone())),
+    }
+}
+
+// Components nested deeper than this are most likely recursive (`$footer` inside of `$footer`)
+const MAX_COMPONENT_DEPTH: usize = 64;
+
+// Component tree bound to `binding`; callers enter the component scope before evaluating it, so
+// the recursive components fail instead of overflowing the stack
+pub(crate) fn resolve_component<'a>(
+    scope: &Scope<'a>,
+    binding: &PropertyBinding,
+) -> Result<&'a TreeNode, EvalError> {
+    if scope.depth() >= MAX_COMPONENT_DEPTH {
+        return Err(EvalError::ComponentTooDeep(binding.clone()));
+    }
+    match resolve(scope, binding)? {
+        Cow::Borrowed(Value::Tree(tree)) => Ok(tree),
+        _ => Err(EvalError::NotAComponent(binding.clone())),
+    }
+}
+
+// Evaluated value along with the flag whether it is trusted markup
+pub(crate) struct EvaluatedValue {
+    pub value: GenericValue,
+    pub trusted: bool,
+}
+
+// Evaluates generic value; `None` is returned for values bound to null
+pub(crate) fn evaluate_value(value: &GenericValue, scope: &Scope) -> Result<Option<EvaluatedValue>, EvalError> {
+    let binding = match value {
+        GenericValue::Binding(binding) => binding,
+        value => return Ok(Some(EvaluatedValue { value: value.clone(), trusted: false })),
+    };
+
+    let (value, trusted) = match &*resolve_scalar(scope, binding)? {
+        Value::Null => return Ok(None),
+        Value::Number(n) => (GenericValue::Number(*n), false),
+        Value::Float(n) => (GenericValue::Float(Float(*n)), false),
+        Value::Bool(b) => (GenericValue::Bool(*b), false),
+        Value::Text(s) => (GenericValue::Text(s.clone()), false),
+        Value::SafeHtml(s) => (GenericValue::Text(s.as_str().to_owned()), true),
+        value => (GenericValue::Text(value.to_string()), false),
+    };
+    Ok(Some(EvaluatedValue { value, trusted }))
+}
+
+// Attributes with the bindings substituted; values bound to null are dropped
+pub(crate) fn evaluate_attributes(attributes: &[Attribute], scope: &Scope) -> Result<Vec<Attribute>, EvalError> {
+    let mut evaluated_attributes: Vec<Attribute> = Vec::with_capacity(attributes.len());
+    for Attribute { name, value, .. } in attributes {
+        let values = match value {
+            AttributeValue::None => {
+                evaluated_attributes.push(Attribute { name: name.clone(), value: AttributeValue::None, bound: false });
+                continue;
+            },
+            AttributeValue::Single(value) => std::slice::from_ref(value),
+            AttributeValue::Multiple(values) => values.as_slice(),
+        };
+        for value in values {
+            let Some(evaluated) = evaluate_value(value, scope)? else {
+                continue;
+            };
+            let bound = matches!(value, GenericValue::Binding(_));
+            match evaluated_attributes.iter_mut().find(|a| a.name == *name) {
+                Some(existing) => {
+                    existing.value.append(evaluated.value);
+                    existing.bound |= bound;
+                },
+                None => evaluated_attributes.push(Attribute {
+                    name: name.clone(),
+                    value: AttributeValue::Single(evaluated.value),
+                    bound,
+                }),
+            }
+        }
+    }
+    Ok(evaluated_attributes)
+}
+
+fn evaluate_node<'a>(
+    node: &'a TreeNode,
+    scope: &mut Scope<'a>,
+    out: &mut Vec<TreeNode>,
+) -> Result<(), EvalError> {
+    match node {
+        TreeNode::Root(RootTreeNode { children }) => {
+            // Component root is transparent
+            for child in children {
+                evaluate_node(child, scope, out)?;
+            }
+        },
+        TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+            let mut node = NormalTreeNode {
+                name: name.clone(),
+                attributes: evaluate_attributes(attributes, scope)?,
+                children: Vec::with_capacity(children.len()),
+            };
+
+            for child in children {
+                evaluate_node(child, scope, &mut node.children)?;
+            }
+
+            out.push(node.into());
+        },
+        TreeNode::InnerContent(InnerContentTreeNode { value, raw }) => {
+            if let Some(evaluated) = evaluate_value(value, scope)? {
+                out.push(InnerContentTreeNode {
+                    value: evaluated.value,
+                    raw: *raw || evaluated.trusted,
+                }.into());
+            }
+        },
+        TreeNode::Subtree(SubtreeTreeNode { property }) => {
+            let component = resolve_component(scope, property)?;
+            scope.enter_component();
+            evaluate_node(component, scope, out)?;
+            scope.exit_component();
+        },
+        TreeNode::Collection(node @ CollectionTreeNode { nodes, collection, .. }) => {
+            let items = resolve_collection(scope, collection)?;
+            for (index, item) in items.iter().enumerate() {
+                scope.push_item(node.item_path(), item, index, items.len());
+                for node in nodes {
+                    evaluate_node(node, scope, out)?;
+                }
+                scope.pop_item();
+            }
+        },
+        TreeNode::Error(node) => return Err(node.into()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::snapshot;
+    use expect_test::expect;
+
+    #[test]
+    fn evaluate_bindings() {
+        let tree = TreeNode::parse(
+            "ul[title=@title]>(li[id=@items%id]>{Item @items%name})*@items+$footer",
+        ).unwrap();
+        let data = Value::object([
+            ("title", Value::from("List")),
+            ("items", Value::from(vec![
+                Value::object([("id", 1), ("name", 10)]),
+                Value::object([("id", Value::from(2)), ("name", Value::from("two"))]),
+            ])),
+            ("footer", TreeNode::parse("p>{@title}").unwrap().into()),
+        ]);
+
+        expect![[r#"
+            - ul[title="List"]
+              - li[id=1]
+                - [CONTENT] "Item"
+                - [CONTENT] " "
+                - [CONTENT] 10
+              - li[id=2]
+                - [CONTENT] "Item"
+                - [CONTENT] " "
+                - [CONTENT] "two"
+              - p
+                - [CONTENT] "List"
+        "#]].assert_eq(&snapshot(&evaluate(&tree, &data).unwrap()));
+    }
+
+    #[test]
+    fn loop_variables() {
+        let tree = TreeNode::parse(
+            "(ul[title=@group%name]>(li[first=@first last=@last even=@even]>{@index @item @group%name})*@group%items as item)*@groups as group",
+        ).unwrap();
+        let data = Value::object([
+            ("groups", Value::from(vec![
+                Value::object([("name", Value::from("a")), ("items", Value::from(vec!["x", "y", "z"]))]),
+                Value::object([("name", Value::from("b")), ("items", Value::from(vec!["w"]))]),
+            ])),
+        ]);
+
+        expect![[r#"
+            - ul[title="a"]
+              - li[first=true last=false even=true]
+                - [CONTENT] 0
+                - [CONTENT] " "
+                - [CONTENT] "x"
+                - [CONTENT] " "
+                - [CONTENT] "a"
+              - li[first=false last=false even=false]
+                - [CONTENT] 1
+                - [CONTENT] " "
+                - [CONTENT] "y"
+                - [CONTENT] " "
+                - [CONTENT] "a"
+              - li[first=false last=true even=true]
+                - [CONTENT] 2
+                - [CONTENT] " "
+                - [CONTENT] "z"
+                - [CONTENT] " "
+                - [CONTENT] "a"
+            - ul[title="b"]
+              - li[first=true last=true even=true]
+                - [CONTENT] 0
+                - [CONTENT] " "
+                - [CONTENT] "w"
+                - [CONTENT] " "
+                - [CONTENT] "b"
+        "#]].assert_eq(&snapshot(&evaluate(&tree, &data).unwrap()));
+
+        // Data fields shadow the loop variables, also in the components rendered inside of the loop
+        let tree = TreeNode::parse("(li>{@first @last}+$row)*@items").unwrap();
+        let data = Value::object([
+            ("first", Value::from("F")),
+            ("items", Value::from(vec!["x", "y"])),
+            ("row", TreeNode::parse("b>{@first @index}").unwrap().into()),
+        ]);
+        expect![[r#"
+            - li
+              - [CONTENT] "F"
+              - [CONTENT] " "
+              - [CONTENT] false
+              - b
+                - [CONTENT] "F"
+                - [CONTENT] " "
+                - [CONTENT] 0
+            - li
+              - [CONTENT] "F"
+              - [CONTENT] " "
+              - [CONTENT] true
+              - b
+                - [CONTENT] "F"
+                - [CONTENT] " "
+                - [CONTENT] 1
+        "#]].assert_eq(&snapshot(&evaluate(&tree, &data).unwrap()));
+    }
+
+    #[test]
+    fn evaluation_errors() {
+        let data = Value::object([("text", "hi")]);
+        let evaluate_str = |input| evaluate(&TreeNode::parse(input).unwrap(), &data).unwrap_err().to_string();
+
+        assert_eq!(evaluate_str("div>{@missing}"), "Binding @missing is not found in data");
+        assert_eq!(evaluate_str("div*@text"), "Binding @text is not a collection");
+        assert_eq!(evaluate_str("$text"), "Binding @text is not a component tree");
+
+        let data = Value::object([("footer", TreeNode::parse("p+$footer").unwrap())]);
+        let error = evaluate(&TreeNode::parse("div>$footer").unwrap(), &data).unwrap_err();
+        assert_eq!(error.to_string(), "Component @footer is nested too deep, it is probably recursive");
+    }
+}
@@ -0,0 +1,123 @@
+use crate::diff::{format_path, NodePath};
+use crate::tree::{NormalTreeNode, TreeNode};
+use thiserror::Error;
+
+// Elements which can't have any content and are rendered without the end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+// Elements which are allowed only inside of the specific parents
+const PERMITTED_PARENTS: &[(&str, &[&str])] = &[
+    ("li", &["ul", "ol", "menu"]),
+    ("dt", &["dl", "div"]),
+    ("dd", &["dl", "div"]),
+    ("tr", &["table", "thead", "tbody", "tfoot"]),
+    ("td", &["tr"]),
+    ("th", &["tr"]),
+    ("thead", &["table"]),
+    ("tbody", &["table"]),
+    ("tfoot", &["table"]),
+    ("caption", &["table"]),
+    ("colgroup", &["table"]),
+    ("col", &["colgroup", "table"]),
+    ("option", &["select", "datalist", "optgroup"]),
+    ("optgroup", &["select"]),
+    ("legend", &["fieldset"]),
+    ("figcaption", &["figure"]),
+    ("summary", &["details"]),
+    ("source", &["audio", "video", "picture"]),
+    ("track", &["audio", "video"]),
+    ("param", &["object"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum HtmlViolation {
+    #[error("{}: void element `{name}` can't have children", format_path(path))]
+    VoidElementChildren { path: NodePath, name: String },
+    #[error(
+        "{}: element `{name}` can't be placed inside of `{parent}` (expected {})",
+        format_path(path),
+        format_names(allowed)
+    )]
+    MisplacedElement { path: NodePath, name: String, parent: String, allowed: &'static [&'static str] },
+}
+
+pub fn is_void_element(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name.to_ascii_lowercase().as_str())
+}
+
+// Returns list of the allowed parents if element can't be placed anywhere
+pub fn permitted_parents(name: &str) -> Option<&'static [&'static str]> {
+    let name = name.to_ascii_lowercase();
+    PERMITTED_PARENTS.iter().find(|(element, _)| *element == name).map(|(_, parents)| *parents)
+}
+
+// Checks HTML content model rules for the tree. Collections and nested roots are transparent,
+// so their nodes are checked against the closest element. Top-level elements and content of
+// the component subtrees are not checked, as their parent is not known until evaluation.
+pub fn validate(tree: &TreeNode) -> Vec<HtmlViolation> {
+    let mut violations = vec![];
+    let mut stack: Vec<(&TreeNode, NodePath, Option<&str>)> = vec![(tree, vec![], None)];
+
+    while let Some((node, path, parent)) = stack.pop() {
+        let mut children_parent = parent;
+        if let TreeNode::Normal(NormalTreeNode { name, children, .. }) = node {
+            if is_void_element(name) && !children.is_empty() {
+                violations.push(HtmlViolation::VoidElementChildren { path: path.clone(), name: name.clone() });
+            }
+            if let (Some(allowed), Some(parent)) = (permitted_parents(name), parent) {
+                if !allowed.contains(&parent.to_ascii_lowercase().as_str()) {
+                    violations.push(HtmlViolation::MisplacedElement {
+                        path: path.clone(),
+                        name: name.clone(),
+                        parent: parent.to_owned(),
+                        allowed,
+                    });
+                }
+            }
+            children_parent = Some(name.as_str());
+        }
+
+        for (i, child) in node.children().iter().enumerate().rev() {
+            let mut child_path = path.clone();
+            child_path.push(i);
+            stack.push((child, child_path, children_parent));
+        }
+    }
+
+    violations
+}
+
+fn format_names(names: &[&str]) -> String {
+    let names = names.iter().map(|name| format!("`{}`", name)).collect::<Vec<String>>();
+    match names.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} or {}", rest.join(", "), last),
+        _ => names.join(""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_content_model() {
+        let tree = TreeNode::parse(
+            "div>(img>{text})+(li>br)*@items+(ul>li+(li>input)*3)+(table>tbody>tr>td)+select>option+optgroup>option",
+        ).unwrap();
+        let violations = validate(&tree).iter().map(|v| v.to_string()).collect::<Vec<String>>();
+        assert_eq!(violations, [
+            "/0/0: void element `img` can't have children",
+            "/0/1/0: element `li` can't be placed inside of `div` (expected `ul`, `ol` or `menu`)",
+        ]);
+
+        assert!(validate(&TreeNode::parse("li>a+$nested").unwrap()).is_empty());
+        assert_eq!(validate(&TreeNode::parse("tr>td").unwrap()), []);
+        assert_eq!(
+            validate(&TreeNode::parse("table>tr+td").unwrap()).iter().map(|v| v.to_string()).collect::<Vec<String>>(),
+            ["/0/1: element `td` can't be placed inside of `table` (expected `tr`)"]
+        );
+    }
+}
@@ -0,0 +1,449 @@
+use crate::definition::DefinitionScope;
+use crate::lorem::Lorem;
+use crate::parser::{Rule, SyntaxError, SyntaxParser};
+use crate::tree::{
+    apply_multiplier, join_terms, parse_term_any, ExpressionTerm, Operator, ParseError, RootTreeNode, TreeBuildError,
+    TreeNode,
+};
+use pest::iterators::{Pair, Pairs};
+use pest::Parser;
+use std::ops::Range;
+
+// Parsed expression which can be updated after an edit of its source. Terms of the expression
+// are kept with their source spans, and the edit re-parses only the innermost term containing it,
+// reusing the nodes of all other terms. Edits which can change the meaning of the surrounding
+// source (e.g. spanning several terms, or opening a comment) fall back to the full parse, so the
+// tree is always equal to the one `TreeNode::parse` produces. Definitions (`let name = ...;`)
+// change the meaning of the terms using them, and generated `lorem` text depends on all previous
+// uses, so the source with either of them is always parsed fully.
+#[derive(Debug, Clone)]
+pub struct IncrementalTree {
+    source: String,
+    // Missing after the failed edit, so the next edit parses the whole source
+    parsed: Option<Parsed>,
+    reparsed: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Parsed {
+    terms: Vec<SpannedTerm>,
+    tree: TreeNode,
+}
+
+// Expression term with its source span. Group keeps its inner terms, so the edit inside the group
+// doesn't re-parse the whole group.
+#[derive(Debug, Clone)]
+struct SpannedTerm {
+    span: Range<usize>,
+    nodes: Vec<TreeNode>,
+    group: Option<Group>,
+    // Term has `*` multiplier, so it can't be followed by `>`
+    multiplied: bool,
+    operator: Option<Operator>,
+}
+
+#[derive(Debug, Clone)]
+struct Group {
+    terms: Vec<SpannedTerm>,
+    // Source of the multiplier after `*`
+    multiplier: Option<String>,
+}
+
+impl IncrementalTree {
+    pub fn parse(source: &str) -> Result<Self, ParseError> {
+        let parsed = Parsed::parse(source)?;
+        Ok(Self { source: source.to_owned(), parsed: Some(parsed), reparsed: 0..source.len() })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // Tree of the current source, missing if the last edit made the source invalid
+    pub fn tree(&self) -> Option<&TreeNode> {
+        self.parsed.as_ref().map(|parsed| &parsed.tree)
+    }
+
+    // Range of the current source which was parsed by the last edit
+    pub fn reparsed(&self) -> Range<usize> {
+        self.reparsed.clone()
+    }
+
+    // Source span (in bytes) of the term which produced the node at `path` (child indices from
+    // the root). Nodes of the multiplied group point to the group terms, and the collection node
+    // to the whole group. Missing if the source has definitions or generated text, as the terms
+    // aren't kept then.
+    pub fn node_span(&self, path: &[usize]) -> Option<Range<usize>> {
+        let (first, rest) = path.split_first()?;
+        let roots = term_spans(&self.parsed.as_ref()?.terms);
+        let mut node = roots.get(*first)?;
+        for &i in rest {
+            node = node.children.get(i)?;
+        }
+        Some(node.span.clone())
+    }
+
+    // Replaces `range` (in bytes) of the source with `text`. Source is updated even if the edit
+    // makes it invalid. Panics if the range is out of bounds or not on char boundaries.
+    pub fn edit(&mut self, range: Range<usize>, text: &str) -> Result<&TreeNode, ParseError> {
+        let mut source = std::mem::take(&mut self.source);
+        source.replace_range(range.clone(), text);
+        self.source = source;
+
+        let delta = text.len() as isize - range.len() as isize;
+        let reparsed = self.parsed.take().and_then(|parsed| parsed.reparse(&self.source, range, delta));
+        let parsed = match reparsed {
+            Some((parsed, reparsed)) => {
+                self.reparsed = reparsed;
+                parsed
+            },
+            None => {
+                self.reparsed = 0..self.source.len();
+                Parsed::parse(&self.source)?
+            },
+        };
+        Ok(&self.parsed.insert(parsed).tree)
+    }
+}
+
+impl Parsed {
+    fn parse(source: &str) -> Result<Self, ParseError> {
+        let pairs = SyntaxParser::parse(Rule::tree, source).map_err(|e| SyntaxError::new(e, source))?;
+        if !has_definitions(&pairs) {
+            let scope = DefinitionScope::default();
+            let terms = spanned_terms(pairs.clone().next().expect("Expression is present"), 0, source, &scope)?;
+            if *scope.lorem() == Lorem::default() {
+                let tree = join(&terms, source)?;
+                return Ok(Self { terms, tree });
+            }
+        }
+        // No terms, so every edit falls back to the full parse
+        let tree = TreeNode::from_pest_pairs(pairs)?;
+        Ok(Self { terms: vec![], tree })
+    }
+
+    // Re-parses the innermost term containing the edit, returns `None` if the full parse is
+    // required. Source is already edited, `range` is the edited range of the old source.
+    fn reparse(mut self, source: &str, range: Range<usize>, delta: isize) -> Option<(Self, Range<usize>)> {
+        let mut path = vec![];
+        let mut terms = &self.terms;
+        while let Some(i) = terms.iter().position(|t| t.span.start <= range.start && range.end <= t.span.end) {
+            path.push(i);
+            match &terms[i].group {
+                Some(group) => terms = &group.terms,
+                None => break,
+            }
+        }
+        let (&index, parents) = path.split_last()?;
+        shift(&mut self.terms, &path, range.end, delta);
+
+        let terms = terms_at(&mut self.terms, parents);
+        let term = &terms[index];
+        let text = &source[term.span.clone()];
+        // Comment at the end of the term would hide the rest of the line in the full source
+        if has_open_comment(text) {
+            return None;
+        }
+        let mut pairs = SyntaxParser::parse(Rule::tree, text).ok()?;
+        if has_definitions(&pairs) {
+            return None;
+        }
+        let scope = DefinitionScope::default();
+        let expr = pairs.next().expect("Expression is present");
+        let mut new_terms = spanned_terms(expr, term.span.start, source, &scope).ok()?;
+        if *scope.lorem() != Lorem::default() {
+            return None;
+        }
+        let last = new_terms.last_mut().expect("Expression has at least one term");
+        if last.multiplied && matches!(term.operator, Some(Operator::Child(_))) {
+            return None;
+        }
+        last.operator = term.operator;
+        let reparsed = term.span.clone();
+        terms.splice(index..=index, new_terms);
+
+        // Groups containing the term are joined again
+        for depth in (0..parents.len()).rev() {
+            let term = &mut terms_at(&mut self.terms, &parents[..depth])[parents[depth]];
+            term.nodes = term.group.as_ref().expect("Parent term is group").nodes(source).ok()?;
+        }
+        self.tree = join(&self.terms, source).ok()?;
+        Some((self, reparsed))
+    }
+}
+
+impl Group {
+    fn nodes(&self, source: &str) -> Result<Vec<TreeNode>, TreeBuildError> {
+        let nodes = join_terms(expression_terms(&self.terms), source)?;
+        match &self.multiplier {
+            Some(multiplier) => {
+                let mut pairs = SyntaxParser::parse(Rule::multiplier, multiplier).expect("Multiplier was parsed");
+                apply_multiplier(nodes, pairs.next().expect("Multiplier is present"), &DefinitionScope::default())
+            },
+            None => Ok(nodes),
+        }
+    }
+}
+
+// Terms of the expression; spans are shifted by `offset`, if the expression is a part of `source`
+fn spanned_terms<'i>(
+    expr: Pair<'i, Rule>,
+    offset: usize,
+    source: &str,
+    scope: &DefinitionScope<'i>,
+) -> Result<Vec<SpannedTerm>, TreeBuildError> {
+    let mut terms = vec![];
+    let mut pairs = expr.into_inner();
+    while let Some(pair) = pairs.next() {
+        let span = pair.as_span().start() + offset..pair.as_span().end() + offset;
+        let multiplied = pair.as_rule() == Rule::term_list;
+        let operator = pairs.next().map(|op| match Operator::from_pair(op) {
+            Operator::Child(pos) => Operator::Child(pos + offset),
+            op => op,
+        });
+
+        let mut inner = pair.clone().into_inner();
+        let target = inner.next().expect("Term has target");
+        let (nodes, group) = match target.as_rule() {
+            Rule::expr => {
+                let group = Group {
+                    terms: spanned_terms(target, offset, source, scope)?,
+                    multiplier: inner.next().map(|multiplier| multiplier.as_str().to_owned()),
+                };
+                (group.nodes(source)?, Some(group))
+            },
+            _ => (parse_term_any(pair, scope)?, None),
+        };
+        terms.push(SpannedTerm { span, nodes, group, multiplied, operator });
+    }
+    Ok(terms)
+}
+
+// Span of the term producing the node, arranged the same way as the nodes themselves
+#[derive(Debug, Clone)]
+struct SpanNode {
+    span: Range<usize>,
+    children: Vec<SpanNode>,
+}
+
+impl SpanNode {
+    fn new(node: &TreeNode, span: &Range<usize>) -> Self {
+        let children = node.children().iter().map(|child| SpanNode::new(child, span)).collect();
+        Self { span: span.clone(), children }
+    }
+}
+
+// Spans of the joined terms, see `join_terms`
+fn term_spans(terms: &[SpannedTerm]) -> Vec<SpanNode> {
+    let mut tail = vec![];
+    for term in terms.iter().rev() {
+        let mut nodes = match &term.group {
+            None => term.nodes.iter().map(|node| SpanNode::new(node, &term.span)).collect(),
+            Some(group) => {
+                let inner = term_spans(&group.terms);
+                match &group.multiplier {
+                    Some(multiplier) if multiplier.starts_with('@') => {
+                        vec![SpanNode { span: term.span.clone(), children: inner }]
+                    },
+                    _ => inner.iter().cycle().take(term.nodes.len()).cloned().collect(),
+                }
+            },
+        };
+        if let Some(Operator::Child(_)) = term.operator {
+            if let Some(last) = nodes.last_mut() {
+                last.children.append(&mut tail);
+            }
+        }
+        nodes.append(&mut tail);
+        tail = nodes;
+    }
+    tail
+}
+
+fn has_definitions(pairs: &Pairs<Rule>) -> bool {
+    pairs.clone().flatten().any(|pair| pair.as_rule() == Rule::definition)
+}
+
+fn join(terms: &[SpannedTerm], source: &str) -> Result<TreeNode, TreeBuildError> {
+    let children = join_terms(expression_terms(terms), source)?;
+    Ok(RootTreeNode { children }.into())
+}
+
+fn expression_terms(terms: &[SpannedTerm]) -> Vec<ExpressionTerm> {
+    terms.iter().map(|term| ExpressionTerm { nodes: term.nodes.clone(), operator: term.operator }).collect()
+}
+
+fn terms_at<'a>(mut terms: &'a mut Vec<SpannedTerm>, path: &[usize]) -> &'a mut Vec<SpannedTerm> {
+    for &i in path {
+        terms = &mut terms[i].group.as_mut().expect("Path goes through groups").terms;
+    }
+    terms
+}
+
+// Moves spans after the edit. Terms on the `path` contain the edit, so only their end moves.
+fn shift(terms: &mut [SpannedTerm], path: &[usize], edit_end: usize, delta: isize) {
+    let moved = |pos: usize| if pos >= edit_end { pos.wrapping_add_signed(delta) } else { pos };
+    for (i, term) in terms.iter_mut().enumerate() {
+        let path = match path.split_first() {
+            Some((&index, rest)) if index == i => {
+                term.span.end = moved(term.span.end);
+                rest
+            },
+            _ if term.span.start >= edit_end => {
+                term.span = moved(term.span.start)..moved(term.span.end);
+                &[]
+            },
+            _ => continue,
+        };
+        if let Some(Operator::Child(pos)) = &mut term.operator {
+            *pos = moved(*pos);
+        }
+        if let Some(group) = &mut term.group {
+            shift(&mut group.terms, path, edit_end, delta);
+        }
+    }
+}
+
+// Whether the text has a line comment without the line break after it
+fn has_open_comment(text: &str) -> bool {
+    let mut chars = text.char_indices();
+    let mut in_string = false;
+    while let Some((i, c)) = chars.next() {
+        match (in_string, c) {
+            (true, '\\') => {
+                chars.next();
+            },
+            // Strings can be closed with either quote
+            (_, '"' | '\'') => in_string = !in_string,
+            (false, '/') if text[i..].starts_with("//") => return !text[i..].contains('\n'),
+            _ => {},
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+
+    const TEMPLATES: [&str; 4] = [
+        "html>(head>title>{'Page title'})+(body>(header#top>h1.title>{@title})+(ul>(li.item>{@items%name})*@items)+footer>{bye})",
+        "div>(p>{a b 1})*3+(section>(h2>{'a+b>c'})+p>!{'<b>x</b>'})+$footer",
+        "ul // list\n>(li[k=1 flag]>{x})*2+\n(table>(tr>(td>{1})*2)*2)",
+        "a>b>c>d+e+(f>(g>(h>{deep})))",
+    ];
+    const FRAGMENTS: [&str; 20] = [
+        "", "a", "x1", "+", ">", "(", ")", "*2", "*@items", "{x}", "{'s'}", "[k=1]", ".c", "#i", " ", "'", "//c\n",
+        "//", "$comp", "p>{t}",
+    ];
+
+    #[test]
+    fn reparse_term() {
+        let mut tree = IncrementalTree::parse("ul>(li>{a})*2+p>{text}").unwrap();
+        tree.edit(8..9, "b c").unwrap();
+        assert_eq!(tree.source(), "ul>(li>{b c})*2+p>{text}");
+        assert_eq!(&tree.source()[tree.reparsed()], "{b c}");
+        assert_eq!(tree.tree(), Some(&TreeNode::parse(tree.source()).unwrap()));
+
+        // Inserted term is parsed together with the term after it
+        tree.edit(3..3, "b+").unwrap();
+        assert_eq!(&tree.source()[tree.reparsed()], "b+(li>{b c})*2");
+
+        // Operator of the top-level expression is edited
+        tree.edit(17..18, "*").unwrap_err();
+        assert_eq!(tree.tree(), None);
+        tree.edit(17..18, "+").unwrap();
+        assert_eq!(tree.reparsed(), 0..tree.source().len());
+        assert_eq!(tree.tree(), Some(&TreeNode::parse("ul>b+(li>{b c})*2+p>{text}").unwrap()));
+    }
+
+    #[test]
+    fn node_spans() {
+        let tree = IncrementalTree::parse("ul>(li.a>{@items%name})*@items+(p+b)*2+i>{x}+footer").unwrap();
+        let span = |path: &[usize]| tree.node_span(path).map(|span| &tree.source()[span]);
+        assert_eq!(span(&[0]), Some("ul"));
+        assert_eq!(span(&[0, 0]), Some("(li.a>{@items%name})*@items"));
+        assert_eq!(span(&[0, 0, 0]), Some("li.a"));
+        assert_eq!(span(&[0, 0, 0, 0]), Some("{@items%name}"));
+        assert_eq!(span(&[0, 1]), Some("p"));
+        assert_eq!(span(&[0, 4]), Some("b"));
+        assert_eq!(span(&[0, 5]), Some("i"));
+        assert_eq!(span(&[0, 5, 1]), Some("footer"));
+        assert_eq!(span(&[0, 5, 1, 0]), None);
+        assert_eq!(span(&[0, 6]), None);
+        assert_eq!(span(&[]), None);
+
+        // Every node has the span
+        for template in TEMPLATES {
+            let tree = IncrementalTree::parse(template).unwrap();
+            let mut stack = vec![(tree.tree().unwrap(), vec![])];
+            while let Some((node, path)) = stack.pop() {
+                assert!(path.is_empty() || tree.node_span(&path).is_some(), "{} at {:?}", template, path);
+                for (i, child) in node.children().iter().enumerate() {
+                    stack.push((child, [path.as_slice(), &[i]].concat()));
+                }
+            }
+        }
+        assert_eq!(IncrementalTree::parse("let x = p; ul>x").unwrap().node_span(&[0]), None);
+    }
+
+    #[test]
+    fn reparse_definitions() {
+        // Edit of the definition changes the terms using it
+        let mut tree = IncrementalTree::parse("let x = p; ul>x+x").unwrap();
+        tree.edit(8..9, "b").unwrap();
+        assert_eq!(tree.reparsed(), 0..tree.source().len());
+        assert_eq!(tree.tree(), Some(&TreeNode::parse("ul>b+b").unwrap()));
+
+        // Definition added inside the group
+        let mut tree = IncrementalTree::parse("ul>(x)+x").unwrap();
+        tree.edit(4..4, "let x = li; ").unwrap();
+        assert_eq!(tree.reparsed(), 0..tree.source().len());
+        assert_eq!(tree.tree(), Some(&TreeNode::parse("ul>li+x").unwrap()));
+
+        // Generated text depends on the previous uses of `lorem`
+        let mut tree = IncrementalTree::parse("p>lorem5+p>{lorem 3}").unwrap();
+        tree.edit(7..8, "4").unwrap();
+        assert_eq!(tree.reparsed(), 0..tree.source().len());
+        assert_eq!(tree.tree(), Some(&TreeNode::parse("p>lorem4+p>{lorem 3}").unwrap()));
+    }
+
+    #[test]
+    fn randomized_edits() {
+        let mut random = Random::new(0x5eed_1234_abcd_ef01);
+        let (mut valid_edits, mut incremental_edits) = (0, 0);
+        for template in TEMPLATES {
+            let mut tree = IncrementalTree::parse(template).unwrap();
+            for _ in 0..500 {
+                let source = tree.source().to_owned();
+                let was_valid = tree.tree().is_some();
+                let start = random.next(source.len() + 1);
+                let end = start + random.next((source.len() - start).min(3) + 1);
+                let fragment = FRAGMENTS[random.next(FRAGMENTS.len())];
+
+                let result = tree.edit(start..end, fragment).cloned();
+                let expected = TreeNode::parse(tree.source());
+                let edit = format!("`{}` -> `{}`", source, tree.source());
+                match (result, expected) {
+                    (Ok(tree), Ok(expected)) => assert_eq!(tree, expected, "{}", edit),
+                    (Err(e), Err(expected)) => assert_eq!(e.to_string(), expected.to_string(), "{}", edit),
+                    (result, expected) => panic!("{}: {:?} != {:?}", edit, result, expected),
+                }
+
+                if was_valid && tree.tree().is_some() {
+                    valid_edits += 1;
+                    if tree.reparsed().len() < tree.source().len() {
+                        incremental_edits += 1;
+                    }
+                }
+                // Invalid source is kept for a few edits only
+                if tree.tree().is_none() && random.next(2) == 0 {
+                    tree = IncrementalTree::parse(template).unwrap();
+                }
+            }
+        }
+        // Most of the edits within a single term should reuse the rest of the tree
+        assert!(incremental_edits * 2 > valid_edits, "{} of {} edits were incremental", incremental_edits, valid_edits);
+    }
+}
@@ -1,2 +1,24 @@
+pub mod builder;
+pub mod convert;
+pub mod definition;
+pub mod data;
+pub mod diff;
+pub mod escape;
+pub mod eval;
+pub mod html;
+pub mod incremental;
+pub mod library;
+pub mod lorem;
 pub mod parser;
+pub mod reactive;
+pub mod recover;
+pub mod render;
+pub mod sanitize;
+pub mod schema;
+pub mod shape;
+pub mod template;
+#[cfg(test)]
+mod test_util;
 pub mod tree;
+pub mod value;
+pub mod vdom;
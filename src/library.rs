@@ -0,0 +1,364 @@
+use crate::definition::DefinitionScope;
+use crate::parser::{Rule, SyntaxError, SyntaxParser};
+use crate::recover::ParseDiagnostic;
+use crate::tree::{parse_expression, parse_string, ParseError, RootTreeNode, TreeNode};
+use crate::value::Value;
+use pest::iterators::Pair;
+use pest::Parser;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LibraryError {
+    #[error("Can't load `{path}`: {error}")]
+    Load { path: String, error: std::io::Error },
+    #[error("`{path}`: {error}")]
+    Parse { path: String, error: ParseError },
+    #[error("Import cycle: {0}")]
+    ImportCycle(String),
+    #[error("`{name}` is defined in both `{first}` and `{second}`")]
+    DefinedTwice { name: String, first: String, second: String },
+}
+
+// Source of the template files. Paths are `/`-separated and relative to the loader root, imports
+// are resolved relative to the importing file before loading.
+pub trait TemplateLoader {
+    fn load(&self, path: &str) -> std::io::Result<String>;
+}
+
+// Loads files from the directory
+#[derive(Debug, Clone)]
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+impl FileLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl TemplateLoader for FileLoader {
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        std::fs::read_to_string(self.root.join(path))
+    }
+}
+
+// Loads files from memory, e.g. embedded with `include_str!` or created in tests
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader {
+    files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    pub fn with_file(mut self, path: impl Into<String>, source: impl Into<String>) -> Self {
+        self.files.insert(path.into(), source.into());
+        self
+    }
+}
+
+impl TemplateLoader for MemoryLoader {
+    fn load(&self, path: &str) -> std::io::Result<String> {
+        self.files.get(path).cloned().ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+}
+
+// Component registry with the templates of the template file and of all files it imports. Every
+// definition of the file is a template, named after the definition; parameters of the template
+// are bindings resolved against the data. Definitions of the imported files (directly or not) are
+// visible in the file, and template names are unique across all files. Definition bodies are built
+// in the scope of their own file, so the importing file can't change their meaning.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Library {
+    templates: BTreeMap<String, TreeNode>,
+    files: Vec<String>,
+}
+
+// Loaded template file
+struct File {
+    path: String,
+    source: String,
+    // Imported files, which are always loaded before the file
+    imports: Vec<usize>,
+}
+
+impl Library {
+    // Loads the file and its imports from the file system, relative to the file directory
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, LibraryError> {
+        let path = path.as_ref();
+        let root = path.parent().unwrap_or(Path::new(""));
+        let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        Library::load(&name, &FileLoader::new(root))
+    }
+
+    pub fn load(path: &str, loader: &(impl TemplateLoader + ?Sized)) -> Result<Self, LibraryError> {
+        let mut files = vec![];
+        load_file(resolve_import("", path), loader, &mut files, &mut vec![])?;
+
+        let definitions = files.iter().map(|file| definition_pairs(&file.source)).collect::<Vec<_>>();
+        let mut owners = HashMap::new();
+        let mut templates = BTreeMap::new();
+        let mut scopes: Vec<DefinitionScope> = vec![];
+        for (index, file) in files.iter().enumerate() {
+            let parse_error = |error| LibraryError::Parse { path: file.path.clone(), error };
+            // Duplicates within the file are reported by the definition scope
+            for pair in &definitions[index] {
+                let name = definition_name(pair);
+                match owners.insert(name, index) {
+                    Some(first) if first != index => {
+                        return Err(LibraryError::DefinedTwice {
+                            name: name.to_owned(),
+                            first: files[first].path.clone(),
+                            second: file.path.clone(),
+                        });
+                    },
+                    _ => {},
+                }
+            }
+
+            let imports = file.imports.iter().map(|&i| scopes[i].clone());
+            let scope = DefinitionScope::default()
+                .with_imports(imports)
+                .with_definitions(definitions[index].clone())
+                .map_err(|e| parse_error(e.into()))?;
+            for pair in &definitions[index] {
+                let name = definition_name(pair);
+                let (scope, body) = scope.expand_template(name);
+                let children = parse_expression(body, &scope).map_err(|e| parse_error(e.into()))?;
+                templates.insert(name.to_owned(), RootTreeNode { children }.into());
+            }
+            scopes.push(scope);
+        }
+        Ok(Self { templates, files: files.into_iter().map(|file| file.path).collect() })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TreeNode> {
+        self.templates.get(name)
+    }
+
+    // Template names in alphabetical order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.templates.keys().map(String::as_str)
+    }
+
+    // Paths of the loaded files, imported files first
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    // Object with all templates, to be used as data for `$name` components
+    pub fn components(&self) -> Value {
+        Value::Object(self.templates.iter().map(|(name, tree)| (name.clone(), Value::Tree(tree.clone()))).collect())
+    }
+}
+
+// Template defined in the file, with the byte offset of its name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TemplateDefinition {
+    pub name: String,
+    pub offset: usize,
+}
+
+// Parses template file without loading its imports and without stopping at the first error (see
+// `TreeNode::parse_recovering`). Returns templates of the file and syntax errors; errors found
+// while building the templates (e.g. recursive definitions) are reported by `Library::load`.
+pub fn parse_file_recovering(source: &str) -> (Vec<TemplateDefinition>, Vec<ParseDiagnostic>) {
+    let (definitions, diagnostics) = crate::recover::parse_file(source);
+    let definitions = definitions.into_iter().map(|(name, offset)| TemplateDefinition { name, offset });
+    (definitions.collect(), diagnostics)
+}
+
+// Loads the file with its imports, returns index of the file; `stack` is the chain of the
+// files importing it
+fn load_file(
+    path: String,
+    loader: &(impl TemplateLoader + ?Sized),
+    files: &mut Vec<File>,
+    stack: &mut Vec<String>,
+) -> Result<usize, LibraryError> {
+    if let Some(start) = stack.iter().position(|importing| *importing == path) {
+        let chain = stack[start..].iter().chain([&path]).map(String::as_str).collect::<Vec<_>>();
+        return Err(LibraryError::ImportCycle(chain.join(" -> ")));
+    }
+    if let Some(index) = files.iter().position(|file| file.path == path) {
+        return Ok(index);
+    }
+
+    let source = loader.load(&path).map_err(|error| LibraryError::Load { path: path.clone(), error })?;
+    let imports = match import_paths(&path, &source) {
+        Ok(imports) => imports,
+        Err(error) => return Err(LibraryError::Parse { path, error }),
+    };
+    stack.push(path);
+    let imports = imports
+        .into_iter()
+        .map(|import| load_file(import, loader, files, stack))
+        .collect::<Result<Vec<_>, _>>()?;
+    let path = stack.pop().expect("File is on the stack");
+    files.push(File { path, source, imports });
+    Ok(files.len() - 1)
+}
+
+fn import_paths(path: &str, source: &str) -> Result<Vec<String>, ParseError> {
+    let mut pairs = SyntaxParser::parse(Rule::file, source).map_err(|e| SyntaxError::new(e, source))?;
+    let mut imports = vec![];
+    for pair in pairs.next().expect("File is parsed").into_inner() {
+        if pair.as_rule() == Rule::import {
+            let import = pair.into_inner().find(|p| p.as_rule() == Rule::string).expect("Import has path");
+            imports.push(resolve_import(path, &parse_string(import)?));
+        }
+    }
+    Ok(imports)
+}
+
+fn definition_pairs(source: &str) -> Vec<Pair<'_, Rule>> {
+    let mut pairs = SyntaxParser::parse(Rule::file, source).expect("File was parsed during loading");
+    let file = pairs.next().expect("File is parsed");
+    file.into_inner().filter(|pair| pair.as_rule() == Rule::definition).collect()
+}
+
+fn definition_name<'i>(pair: &Pair<'i, Rule>) -> &'i str {
+    let name = pair.clone().into_inner().find(|p| p.as_rule() == Rule::definition_name);
+    name.expect("Definition has name").as_str()
+}
+
+// Path of the import relative to the directory of the importing file; path starting with `/`
+// is relative to the loader root
+fn resolve_import(from: &str, import: &str) -> String {
+    let mut parts = match import.starts_with('/') {
+        true => vec![],
+        false => from.split('/').collect::<Vec<_>>(),
+    };
+    parts.pop();
+    for part in import.split('/') {
+        match part {
+            "" | "." => {},
+            ".." => {
+                parts.pop();
+            },
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::snapshot;
+    use crate::template::Template;
+    use expect_test::expect;
+
+    fn load(loader: &MemoryLoader) -> Result<Library, String> {
+        Library::load("main.utree", loader).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn templates_and_imports() {
+        let loader = MemoryLoader::default()
+            .with_file("main.utree", r#"
+                // Page layout
+                import "widgets/list.utree";
+                import "./widgets/../footer.utree"
+
+                let page = body>(h1>{@title})+list+$bottom;
+            "#)
+            .with_file("widgets/list.utree", r#"
+                import "item.utree";
+                let list = ul>item(@items%name)*@items;
+            "#)
+            .with_file("widgets/item.utree", "let item(text) = li.item>{@text};")
+            .with_file("footer.utree", "import '/widgets/item.utree'; let bottom = footer>{bye};");
+        let library = load(&loader).unwrap();
+        assert_eq!(library.files(), ["widgets/item.utree", "widgets/list.utree", "footer.utree", "main.utree"]);
+        assert_eq!(library.names().collect::<Vec<_>>(), ["bottom", "item", "list", "page"]);
+        expect![[r#"
+            - li[class="item"]
+              - [CONTENT] @text
+        "#]]
+        .assert_eq(&snapshot(library.get("item").unwrap()));
+
+        let Value::Object(mut data) = library.components() else {
+            unreachable!("Components are object");
+        };
+        data.insert("title".to_owned(), "Hi".into());
+        data.insert("items".to_owned(), Value::List(vec![Value::object([("name", "a")]), Value::object([("name", "b")])]));
+        let html = Template::compile(library.get("page").unwrap()).render(&Value::Object(data)).unwrap();
+        assert_eq!(html, r#"<body><h1>Hi</h1><ul><li class="item">a</li><li class="item">b</li></ul><footer>bye</footer></body>"#);
+    }
+
+    #[test]
+    fn lexical_scope() {
+        // Definitions of the importing file don't change the meaning of the imported ones
+        let loader = MemoryLoader::default()
+            .with_file("main.utree", "import 'a.utree'; let b = p; let page = a+b;")
+            .with_file("a.utree", "let a = div>b;");
+        let library = load(&loader).unwrap();
+        assert_eq!(library.get("a"), Some(&TreeNode::parse("div>b").unwrap()));
+        assert_eq!(library.get("page"), Some(&TreeNode::parse("(div>b)+p").unwrap()));
+    }
+
+    #[test]
+    fn errors() {
+        let file = |source: &str| MemoryLoader::default().with_file("main.utree", source);
+        assert_eq!(load(&file("import 'main.utree';")).unwrap_err(), "Import cycle: main.utree -> main.utree");
+        let loader = file("import 'a.utree';")
+            .with_file("a.utree", "import 'b/b.utree'; let a = p;")
+            .with_file("b/b.utree", "import '../a.utree';");
+        assert_eq!(load(&loader).unwrap_err(), "Import cycle: a.utree -> b/b.utree -> a.utree");
+
+        let loader = file("import 'a.utree'; let a = div;").with_file("a.utree", "let a = p;");
+        assert_eq!(load(&loader).unwrap_err(), "`a` is defined in both `a.utree` and `main.utree`");
+        assert_eq!(load(&file("let a = p; let a = b;")).unwrap_err(), "`main.utree`: `a` is defined twice at 1:16");
+        assert_eq!(load(&file("import 'b.utree';")).unwrap_err(), "Can't load `b.utree`: entity not found");
+        assert_eq!(load(&file("let a = div>a;")).unwrap_err(), "`main.utree`: Definition is recursive: a -> a at 1:13");
+
+        let error = Library::load("main.utree", &file("let a = p\nlet b = div;")).unwrap_err();
+        let LibraryError::Parse { error, .. } = error else {
+            panic!("Unexpected error: {}", error);
+        };
+        assert_eq!(error.location(), Some((2, 1)));
+    }
+
+    #[test]
+    fn recovering_file_parser() {
+        let outline = |source| {
+            let (definitions, diagnostics) = parse_file_recovering(source);
+            let definitions = definitions.iter().map(|d| format!("{} {}", d.name, d.offset));
+            let diagnostics = diagnostics.iter().map(|d| d.to_string());
+            definitions.chain(diagnostics).collect::<Vec<_>>().join("\n")
+        };
+        let source = "import 'a.utree';\nimport \"b.utree\"\n\nlet page = body>menu;\nlet card(t) = div>(let h = h2; h>{@t});";
+        assert_eq!(parse_file_recovering(source).1, []);
+        expect![[r#"
+            page 40
+            card 62"#]].assert_eq(&outline(source));
+
+        expect![[r#"
+            b 53
+            c 64
+            d 78
+            1:8: expected string
+            2:13: expected attribute name
+            3:1: Expected import or definition
+            4:1: Imports should precede the definitions
+            5:21: Unexpected `)` without matching `(`
+            6:10: expected attributes (`[...]`), arguments (`(a, b)`), `+`, or `>`"#]].assert_eq(&outline(
+            "import x;\nlet a = div[=1];\np>q\nimport 'c.utree';\nlet b = p; let c = i)+x; let d = p;\nlet e = p"
+        ));
+    }
+
+    #[test]
+    fn file_loader() {
+        let root = std::env::temp_dir().join(format!("utree-library-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("parts")).unwrap();
+        std::fs::write(root.join("index.utree"), "import 'parts/menu.utree'; let index = menu+main;").unwrap();
+        std::fs::write(root.join("parts/menu.utree"), "let menu = nav>(a)*2;").unwrap();
+
+        let library = Library::open(root.join("index.utree")).unwrap();
+        assert_eq!(library.get("index"), Some(&TreeNode::parse("(nav>(a)*2)+main").unwrap()));
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
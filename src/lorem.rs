@@ -0,0 +1,139 @@
+// Placeholder text generator for the `lorem` nodes and `{lorem 20}` text. Generator is seeded,
+// so the same seed always produces the same text; the first sentence it generates starts with
+// the classic "Lorem ipsum dolor sit amet".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lorem {
+    state: u64,
+    classic: bool,
+}
+
+// Amount of the generated text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoremAmount {
+    Words(usize),
+    Sentences(usize),
+    Paragraphs(usize),
+}
+
+// Words count of the `lorem` node without count, same as in Emmet
+pub const DEFAULT_WORDS: usize = 30;
+// Text is generated while parsing, so a bigger count is rejected as the invalid literal
+pub const MAX_COUNT: usize = 1000;
+
+const CLASSIC: [&str; 8] = ["lorem", "ipsum", "dolor", "sit", "amet", "consectetur", "adipiscing", "elit"];
+
+const WORDS: [&str; 96] = [
+    "a", "ac", "accumsan", "ad", "aenean", "aliquam", "aliquet", "ante", "arcu", "at", "auctor", "augue", "blandit",
+    "commodo", "consequat", "cras", "curabitur", "dapibus", "diam", "dictum", "dignissim", "donec", "dui", "duis",
+    "egestas", "eget", "eleifend", "elementum", "enim", "erat", "eros", "est", "et", "etiam", "eu", "euismod",
+    "facilisis", "faucibus", "felis", "fermentum", "feugiat", "fringilla", "fusce", "gravida", "hendrerit", "iaculis",
+    "id", "imperdiet", "in", "integer", "interdum", "justo", "lacinia", "lacus", "laoreet", "lectus", "leo", "libero",
+    "ligula", "lobortis", "luctus", "maecenas", "magna", "massa", "mattis", "mauris", "metus", "mi", "molestie",
+    "mollis", "morbi", "nam", "nec", "neque", "nibh", "nisi", "nisl", "non", "nulla", "nunc", "odio", "orci", "ornare",
+    "pellentesque", "pharetra", "porta", "posuere", "pretium", "quam", "quis", "rhoncus", "sed", "tempor", "ut",
+    "vitae", "vivamus",
+];
+
+impl Default for Lorem {
+    fn default() -> Self {
+        Lorem::new(0)
+    }
+}
+
+impl Lorem {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed, classic: true }
+    }
+
+    // Text of `count` words, split into sentences
+    pub fn words(&mut self, count: usize) -> String {
+        let mut sentences = vec![];
+        let mut left = count;
+        while left > 0 {
+            let mut length = self.sentence_length().min(left);
+            // No short sentence at the end
+            if left - length < 3 {
+                length = left;
+            }
+            sentences.push(self.sentence(length));
+            left -= length;
+        }
+        sentences.join(" ")
+    }
+
+    pub fn sentences(&mut self, count: usize) -> String {
+        let sentences = (0..count).map(|_| {
+            let length = self.sentence_length();
+            self.sentence(length)
+        });
+        sentences.collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn paragraphs(&mut self, count: usize) -> Vec<String> {
+        (0..count).map(|_| {
+            let sentences = 3 + self.below(4);
+            self.sentences(sentences)
+        }).collect()
+    }
+
+    fn sentence(&mut self, length: usize) -> String {
+        let mut sentence = String::new();
+        for i in 0..length {
+            let word = match CLASSIC.get(i).filter(|_| self.classic) {
+                Some(word) => word,
+                None => WORDS[self.below(WORDS.len())],
+            };
+            if i == 0 {
+                let mut chars = word.chars();
+                sentence.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+                sentence.push_str(chars.as_str());
+            } else {
+                // Comma after some of the words, but not right before the end
+                if i + 1 < length && i > 1 && !self.classic && self.below(8) == 0 {
+                    sentence.push(',');
+                }
+                sentence.push(' ');
+                sentence.push_str(word);
+            }
+        }
+        let question = !self.classic && self.below(10) == 0;
+        self.classic = false;
+        sentence.push(if question { '?' } else { '.' });
+        sentence
+    }
+
+    fn sentence_length(&mut self) -> usize {
+        4 + self.below(9)
+    }
+
+    // Random number in `0..n` (splitmix64)
+    fn below(&mut self, n: usize) -> usize {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use expect_test::expect;
+
+    #[test]
+    fn generate() {
+        let mut lorem = Lorem::default();
+        expect!["Lorem ipsum dolor sit amet."].assert_eq(&lorem.words(5));
+        expect!["Nunc nisl sed mauris? Felis neque dui cras gravida felis nisl iaculis nisl, lobortis curabitur ornare. In at lectus euismod."].assert_eq(&lorem.words(20));
+        expect!["At fusce, nulla, pretium lectus at. Dapibus dui pretium pharetra etiam?"].assert_eq(&lorem.sentences(2));
+        assert_eq!(lorem.paragraphs(2).len(), 2);
+
+        for count in 0..50 {
+            assert_eq!(Lorem::new(count as u64).words(count).split(' ').filter(|w| !w.is_empty()).count(), count);
+        }
+        assert_eq!(Lorem::new(7).sentences(3), Lorem::new(7).sentences(3));
+        assert_ne!(Lorem::new(7).words(30), Lorem::new(8).words(30));
+    }
+}
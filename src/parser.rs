@@ -1,10 +1,190 @@
+use pest::error::{ErrorVariant, InputLocation};
 use pest_derive::Parser;
+use std::fmt::Display;
 
 
 #[derive(Parser)]
 #[grammar = "syntax.pest"]
 pub struct SyntaxParser;
 
+pub(crate) const MULTIPLIED_CHILDREN_HINT: &str =
+    "children can't be added to the multiplied term, group them before multiplying: `(div>p)*5` instead of `div*5>p`";
+
+impl Rule {
+    // Plain-language description used in the syntax error messages
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rule::EOI => "end of input",
+            Rule::WHITESPACE => "whitespace",
+            Rule::COMMENT => "comment",
+            Rule::ident => "word",
+            Rule::number | Rule::integer | Rule::float => "number",
+            Rule::exponent => "exponent",
+            Rule::boolean => "`true` or `false`",
+            Rule::binding_part => "name",
+            Rule::binding => "binding (`@name`)",
+            Rule::node_binding => "component (`$name`)",
+            Rule::content => "text, number or binding",
+            Rule::loop_variable | Rule::loop_alias => "loop variable name",
+            Rule::loop_key => "key binding (`key @name`)",
+            Rule::multiplier => "number of copies or collection binding",
+            Rule::attr_name => "attribute name",
+            Rule::attr_value => "attribute value",
+            Rule::attr => "attribute",
+            Rule::id_prop | Rule::id_prop_prefixed => "id",
+            Rule::class_prop | Rule::class_prop_prefixed => "class name",
+            Rule::prop => "id, class or attributes",
+            Rule::attrs_prop => "attributes (`[...]`)",
+            Rule::string | Rule::string_inner | Rule::string_char => "string",
+            Rule::node_name | Rule::node => "element",
+            Rule::text_node => "text (`{...}`)",
+            Rule::raw_text_node => "raw text (`!{...}`)",
+            Rule::term | Rule::term_list | Rule::any_term | Rule::term_target | Rule::group => {
+                "element, text, component or group"
+            },
+            Rule::mul_suffix => "multiplier (`*3`)",
+            Rule::arguments => "arguments (`(a, b)`)",
+            Rule::call => "definition use",
+            Rule::let_keyword => "`let`",
+            Rule::definition_name => "definition name",
+            Rule::parameter => "parameter name",
+            Rule::parameters => "parameters (`(a, b)`)",
+            Rule::definition => "definition (`let name = ...;`)",
+            Rule::import_keyword => "`import`",
+            Rule::import => "import (`import \"file.utree\";`)",
+            Rule::file => "template file",
+            Rule::expr | Rule::tree => "expression",
+            Rule::sibling_op => "`+`",
+            Rule::child_op => "`>`",
+            Rule::binary_op => "`+` or `>`",
+        }
+    }
+}
+
+// Syntax error with the expected input described in plain language, and a hint for the common
+// mistakes. Displayed with the source snippet.
+#[derive(Debug)]
+pub struct SyntaxError {
+    error: Box<pest::error::Error<Rule>>,
+    hint: Option<String>,
+}
+
+impl SyntaxError {
+    pub fn new(mut error: pest::error::Error<Rule>, input: &str) -> Self {
+        let hint = hint(&error, input);
+        // Several rules may have the same description
+        if let ErrorVariant::ParsingError { positives, negatives } = &mut error.variant {
+            for rules in [positives, negatives] {
+                let mut seen = vec![];
+                rules.retain(|rule| {
+                    let description = rule.description();
+                    let is_new = !seen.contains(&description);
+                    seen.push(description);
+                    is_new
+                });
+            }
+        }
+        let error = error.renamed_rules(|rule| rule.description().to_owned());
+        Self { error: Box::new(error), hint }
+    }
+
+    // Line and column (1-based, in characters) where the error starts
+    pub fn location(&self) -> (usize, usize) {
+        match self.error.line_col {
+            pest::error::LineColLocation::Pos(pos) => pos,
+            pest::error::LineColLocation::Span(start, _) => start,
+        }
+    }
+
+    // Error description without location
+    pub fn message(&self) -> String {
+        self.error.variant.message().into_owned()
+    }
+
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.error)?;
+        match &self.hint {
+            Some(hint) => write!(f, "\n  = hint: {}", hint),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+// Hint for the common mistake which caused the error
+fn hint(error: &pest::error::Error<Rule>, input: &str) -> Option<String> {
+    let pos = match error.location {
+        InputLocation::Pos(pos) | InputLocation::Span((pos, _)) => pos,
+    };
+    let before = input[..pos].trim_end();
+    let next = input[pos..].chars().next();
+
+    if let ErrorVariant::ParsingError { negatives, .. } = &error.variant {
+        if negatives.contains(&Rule::child_op) {
+            return Some(MULTIPLIED_CHILDREN_HINT.to_owned());
+        }
+    }
+    if before.ends_with('*') || next == Some('*') {
+        return Some("`*` should be followed by the number of copies (`li*3`) or a collection binding (`li*@items`)".to_owned());
+    }
+    if next.is_some_and(|c| c.is_ascii_digit()) {
+        let name = &input[pos..];
+        let name = &name[..name.find(|c: char| !(c.is_ascii_alphanumeric() || "_-".contains(c))).unwrap_or(name.len())];
+        match before.chars().last() {
+            Some('#') => return Some(format!("id can't start with a digit, use the attribute instead: `[id='{}']`", name)),
+            Some('.') => return Some(format!("class name can't start with a digit, use the attribute instead: `[class='{}']`", name)),
+            _ => {},
+        }
+    }
+    unclosed_delimiter(input, pos).map(|open| format!("`{}` is not closed with `{}`", open, closing(open)))
+}
+
+// Innermost delimiter (or string quote) opened before `pos` and never closed
+fn unclosed_delimiter(input: &str, pos: usize) -> Option<char> {
+    let mut open = vec![];
+    let mut quote = None;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            },
+            // Strings can be closed with either quote
+            (Some(_), '"' | '\'') => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some((c, i)),
+            (None, '/') if input[i..].starts_with("//") => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            },
+            (None, '[' | '(' | '{') => open.push((c, i)),
+            (None, ']' | ')' | '}') => {
+                if let Some(index) = open.iter().rposition(|(o, _)| closing(*o) == c) {
+                    open.truncate(index);
+                }
+            },
+            _ => {},
+        }
+    }
+    let (open, _) = quote.into_iter().chain(open.into_iter().rev()).find(|(_, start)| *start <= pos)?;
+    Some(open)
+}
+
+fn closing(open: char) -> char {
+    match open {
+        '[' => ']',
+        '(' => ')',
+        '{' => '}',
+        quote => quote,
+    }
+}
+
 
 #[cfg(test)]
 mod tests
@@ -19,8 +199,7 @@ mod tests
                 let lines: Vec<_> = pairs.map(|pair| {
                     format_pair(pair, 0, true)
                 }).collect();
-                let lines = lines.join("\n");
-                format!("{}", lines)
+                lines.join("\n")
             }
             Err(error) => format!("{}", error),
         }
@@ -58,6 +237,80 @@ mod tests
         expect.assert_eq(&parse_input(s, rule));
     }
 
+    fn error_message(input: &str) -> String {
+        let error = SyntaxParser::parse(Rule::tree, input).expect_err("Input is invalid");
+        SyntaxError::new(error, input).to_string()
+    }
+
+    #[test]
+    fn error_hints() {
+        let errors = ["div[a=1>p", "ul>(li>{item}", "p>{'text}", "li*", "div#1a", "div*5>p", "div>", "$"]
+            .map(error_message)
+            .join("\n\n");
+        expect![[r#"
+             --> 1:8
+              |
+            1 | div[a=1>p
+              |        ^---
+              |
+              = expected attribute name
+              = hint: `[` is not closed with `]`
+
+             --> 1:14
+              |
+            1 | ul>(li>{item}
+              |              ^---
+              |
+              = expected `+` or `>`
+              = hint: `(` is not closed with `)`
+
+             --> 1:4
+              |
+            1 | p>{'text}
+              |    ^---
+              |
+              = expected word, number, `true` or `false`, binding (`@name`), or string
+              = hint: `'` is not closed with `'`
+
+             --> 1:3
+              |
+            1 | li*
+              |   ^---
+              |
+              = expected end of input, attributes (`[...]`), arguments (`(a, b)`), `+`, or `>`
+              = hint: `*` should be followed by the number of copies (`li*3`) or a collection binding (`li*@items`)
+
+             --> 1:5
+              |
+            1 | div#1a
+              |     ^---
+              |
+              = expected id
+              = hint: id can't start with a digit, use the attribute instead: `[id='1a']`
+
+             --> 1:6
+              |
+            1 | div*5>p
+              |      ^---
+              |
+              = unexpected `>`
+              = hint: children can't be added to the multiplied term, group them before multiplying: `(div>p)*5` instead of `div*5>p`
+
+             --> 1:5
+              |
+            1 | div>
+              |     ^---
+              |
+              = expected element, text, component or group
+
+             --> 1:2
+              |
+            1 | $
+              |  ^---
+              |
+              = expected name"#]].assert_eq(&errors);
+    }
+
     #[test]
     fn identifier() {
         assert_parsed(
@@ -126,12 +379,28 @@ mod tests
                   - term > text_node
                     - binding > binding_part: "test"
                     - ident: "wow"
-                    - number: "123"
+                    - integer: "123"
                     - string > string_inner: "hi"
                 - EOI: """#]]
         );
     }
 
+    #[test]
+    fn raw_content() {
+        assert_parsed(
+            Rule::tree,
+            "div>!{'<b>bold</b>' @html}",
+            expect![[r#"
+                - expr
+                  - term > node > node_name: "div"
+                  - child_op: ">"
+                  - term > raw_text_node
+                    - string > string_inner: "<b>bold</b>"
+                    - binding > binding_part: "html"
+                - EOI: """#]]
+        );
+    }
+
     #[test]
     fn groups() {
         assert_parsed(
@@ -166,7 +435,7 @@ mod tests
     fn mul() {
         assert_parsed(
             Rule::tree,
-            "html*3+(a>b)*@collection",
+            "html*3+(a>b)*@collection+p*@items as item key @item%id",
             expect![[r#"
                 - expr
                   - term_list
@@ -179,6 +448,15 @@ mod tests
                       - child_op: ">"
                       - term > node > node_name: "b"
                     - multiplier > binding > binding_part: "collection"
+                  - sibling_op: "+"
+                  - term_list
+                    - node > node_name: "p"
+                    - multiplier
+                      - binding > binding_part: "items"
+                      - loop_variable: "item"
+                      - binding
+                        - binding_part: "item"
+                        - binding_part: "id"
                 - EOI: """#]]
         );
     }
@@ -0,0 +1,673 @@
+use crate::eval::{evaluate_attributes, evaluate_value, resolve_collection, resolve_component, EvalError};
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, GenericValue, InnerContentTreeNode, NormalTreeNode,
+    PropertyBinding, RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+use crate::value::{Scope, Value, LOOP_VARIABLES};
+use crate::vdom::{
+    diff_attributes, element, element_attributes, item_key, longest_increasing, set_item_keys, Patch, VAttribute,
+    VNode,
+};
+use std::collections::{HashMap, VecDeque};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ReactiveError {
+    #[error("Path `{0}` is not found in data")]
+    PathNotFound(String),
+    #[error(transparent)]
+    Eval(#[from] EvalError),
+}
+
+// Template evaluated against the data store, which keeps track of the data each DOM node depends
+// on. Bindings are resolved to the data paths (`items%2%name` for `@items%name` inside of the
+// third item of `@items`, list items are addressed by index), and the update of the path
+// re-evaluates only the text nodes, attributes, components and collections bound to this path,
+// to its parents or to its children.
+#[derive(Debug, Clone)]
+pub struct ReactiveView {
+    template: TreeNode,
+    data: Value,
+    root: Instance,
+}
+
+// Evaluated template node; DOM nodes are not kept, but are produced from the instances
+#[derive(Debug, Clone)]
+enum Instance {
+    Root(Vec<Instance>),
+    Element { name: String, attributes: Vec<VAttribute>, children: Vec<Instance> },
+    // Missing for values bound to null
+    Text(Option<VNode>),
+    Subtree(Box<Instance>),
+    Collection { collection: PropertyBinding, items: Vec<Item> },
+}
+
+// Instances of the collection loop body for one item
+#[derive(Debug, Clone)]
+struct Item {
+    key: String,
+    nodes: Vec<Instance>,
+}
+
+impl ReactiveView {
+    pub fn new(template: TreeNode, data: Value) -> Result<Self, EvalError> {
+        let root = Context::new(&data, &[]).build(&template)?;
+        Ok(Self { template, data, root })
+    }
+
+    pub fn data(&self) -> &Value {
+        &self.data
+    }
+
+    // Current DOM; same as `vdom::evaluate` of the template against the current data
+    pub fn dom(&self) -> Vec<VNode> {
+        let mut nodes = vec![];
+        self.root.render(&mut nodes);
+        nodes
+    }
+
+    // Replaces the value at `%`-separated data `path` (e.g. `items%0%name`, or empty path for the
+    // whole data) and returns the change notifications as DOM patches (see `vdom::apply`).
+    // Missing object fields are added, but list items could only be replaced. Evaluation errors
+    // leave the view out of sync with the data, and it should be created again.
+    pub fn set(&mut self, path: &str, value: impl Into<Value>) -> Result<Vec<Patch>, ReactiveError> {
+        let path = match path {
+            "" => vec![],
+            path => path.split('%').map(str::to_owned).collect::<Vec<_>>(),
+        };
+        let target = value_at_mut(&mut self.data, &path).ok_or_else(|| ReactiveError::PathNotFound(path.join("%")))?;
+        *target = value.into();
+
+        let mut context = Context::new(&self.data, &path);
+        context.update(&self.template, &mut self.root, &mut vec![], 0)?;
+        Ok(context.patches)
+    }
+}
+
+impl Instance {
+    // Number of DOM nodes produced by the instance
+    fn dom_len(&self) -> usize {
+        match self {
+            Instance::Root(children) => children.iter().map(Instance::dom_len).sum(),
+            Instance::Element { .. } => 1,
+            Instance::Text(text) => text.is_some() as usize,
+            Instance::Subtree(inner) => inner.dom_len(),
+            Instance::Collection { items, .. } => items.iter().map(Item::dom_len).sum(),
+        }
+    }
+
+    fn render(&self, out: &mut Vec<VNode>) {
+        match self {
+            Instance::Root(children) => children.iter().for_each(|child| child.render(out)),
+            Instance::Element { name, attributes, children } => {
+                let mut element_children = vec![];
+                children.iter().for_each(|child| child.render(&mut element_children));
+                out.push(element(name, attributes.clone(), element_children));
+            },
+            Instance::Text(text) => out.extend(text.clone()),
+            Instance::Subtree(inner) => inner.render(out),
+            Instance::Collection { collection, items } => items.iter().for_each(|item| item.render(collection, out)),
+        }
+    }
+}
+
+impl Item {
+    fn dom_len(&self) -> usize {
+        self.nodes.iter().map(Instance::dom_len).sum()
+    }
+
+    fn render(&self, collection: &PropertyBinding, out: &mut Vec<VNode>) {
+        let start = out.len();
+        self.nodes.iter().for_each(|node| node.render(out));
+        set_item_keys(&mut out[start..], collection, &self.key);
+    }
+}
+
+// Evaluation state while the instances are built or updated
+struct Context<'a> {
+    scope: Scope<'a>,
+    // Binding path and data path of the collection items in scope
+    items: Vec<(&'a [String], Vec<String>)>,
+    // Updated data path
+    changed: Vec<String>,
+    patches: Vec<Patch>,
+}
+
+impl<'a> Context<'a> {
+    fn new(data: &'a Value, changed: &[String]) -> Self {
+        Self { scope: Scope::new(data), items: vec![], changed: changed.to_vec(), patches: vec![] }
+    }
+
+    // Data path the binding is resolved to, in the same way as `Scope::resolve` does. Loop
+    // variables, unless shadowed by the data, depend on the collection itself.
+    fn data_path(&self, binding: &PropertyBinding) -> Vec<String> {
+        match self.loop_path(binding) {
+            Some(path) if self.scope.resolve_data(binding).is_none() => path.to_vec(),
+            _ => self.field_path(binding),
+        }
+    }
+
+    // Path of the innermost collection, if the binding is named as the loop variable
+    fn loop_path(&self, binding: &PropertyBinding) -> Option<&[String]> {
+        match (self.items.last(), binding.parts()) {
+            (Some((_, item)), [name]) if LOOP_VARIABLES.contains(&name.as_str()) => Some(&item[..item.len() - 1]),
+            _ => None,
+        }
+    }
+
+    fn field_path(&self, binding: &PropertyBinding) -> Vec<String> {
+        let parts = binding.parts();
+        for (path, item) in self.items.iter().rev() {
+            if let Some(rest) = parts.strip_prefix(*path) {
+                return [item.as_slice(), rest].concat();
+            }
+        }
+        parts.to_vec()
+    }
+
+    // Whether the value at the data path could be changed by the update
+    fn affects(&self, path: &[String]) -> bool {
+        path.starts_with(&self.changed) || self.changed.starts_with(path)
+    }
+
+    // Field shadowing the loop variable could be added or removed by the update, so both paths
+    // are checked
+    fn affects_binding(&self, binding: &PropertyBinding) -> bool {
+        match self.loop_path(binding) {
+            Some(path) => self.affects(path) || self.affects(&self.field_path(binding)),
+            None => self.affects(&self.data_path(binding)),
+        }
+    }
+
+    fn push_item(&mut self, node: &'a CollectionTreeNode, path: &[String], items: &'a [Value], index: usize) {
+        self.scope.push_item(node.item_path(), &items[index], index, items.len());
+        let mut item_path = path.to_vec();
+        item_path.push(index.to_string());
+        self.items.push((node.item_path(), item_path));
+    }
+
+    fn pop_item(&mut self) {
+        self.scope.pop_item();
+        self.items.pop();
+    }
+
+    fn build(&mut self, node: &'a TreeNode) -> Result<Instance, EvalError> {
+        let instance = match node {
+            TreeNode::Root(RootTreeNode { children }) => Instance::Root(self.build_all(children)?),
+            TreeNode::Normal(NormalTreeNode { name, attributes, children }) => Instance::Element {
+                name: name.clone(),
+                attributes: self.attributes(attributes)?,
+                children: self.build_all(children)?,
+            },
+            TreeNode::InnerContent(InnerContentTreeNode { value, raw }) => Instance::Text(self.text(value, *raw)?),
+            TreeNode::Subtree(SubtreeTreeNode { property }) => {
+                let component = resolve_component(&self.scope, property)?;
+                self.scope.enter_component();
+                let instance = self.build(component)?;
+                self.scope.exit_component();
+                Instance::Subtree(Box::new(instance))
+            },
+            TreeNode::Collection(node) => {
+                let path = self.data_path(&node.collection);
+                let items = resolve_collection(&self.scope, &node.collection)?;
+                Instance::Collection {
+                    collection: node.collection.clone(),
+                    items: (0..items.len()).map(|index| self.build_item(node, &path, items, index)).collect::<Result<_, _>>()?,
+                }
+            },
+            TreeNode::Error(node) => return Err(node.into()),
+        };
+        Ok(instance)
+    }
+
+    fn build_all(&mut self, nodes: &'a [TreeNode]) -> Result<Vec<Instance>, EvalError> {
+        nodes.iter().map(|node| self.build(node)).collect()
+    }
+
+    fn build_item(
+        &mut self,
+        node: &'a CollectionTreeNode,
+        path: &[String],
+        items: &'a [Value],
+        index: usize,
+    ) -> Result<Item, EvalError> {
+        self.push_item(node, path, items, index);
+        let item = item_key(node, &self.scope, &items[index], index)
+            .and_then(|key| Ok(Item { key, nodes: self.build_all(&node.nodes)? }));
+        self.pop_item();
+        item
+    }
+
+    fn attributes(&self, attributes: &[Attribute]) -> Result<Vec<VAttribute>, EvalError> {
+        Ok(element_attributes(&evaluate_attributes(attributes, &self.scope)?).expect("Evaluated attributes don't have bindings"))
+    }
+
+    fn text(&self, value: &GenericValue, raw: bool) -> Result<Option<VNode>, EvalError> {
+        Ok(evaluate_value(value, &self.scope)?.map(|evaluated| VNode::Text {
+            text: evaluated.value.to_text().expect("Evaluated value is not a binding"),
+            raw: raw || evaluated.trusted,
+        }))
+    }
+
+    // Updates the instance, which DOM nodes start at `offset` among the children of `parent`
+    fn update(
+        &mut self,
+        node: &'a TreeNode,
+        instance: &mut Instance,
+        parent: &mut Vec<usize>,
+        offset: usize,
+    ) -> Result<(), EvalError> {
+        match (node, instance) {
+            (TreeNode::Root(RootTreeNode { children }), Instance::Root(instances)) => {
+                self.update_all(children, instances, parent, offset)?;
+            },
+            (
+                TreeNode::Normal(NormalTreeNode { attributes, children, .. }),
+                Instance::Element { attributes: current, children: instances, .. },
+            ) => {
+                parent.push(offset);
+                let bound = attributes.iter().flat_map(|a| attribute_values(&a.value)).any(|value| match value {
+                    GenericValue::Binding(binding) => self.affects_binding(binding),
+                    _ => false,
+                });
+                if bound {
+                    let attributes = self.attributes(attributes)?;
+                    diff_attributes(current, &attributes, parent, &mut self.patches);
+                    *current = attributes;
+                }
+                let result = self.update_all(children, instances, parent, 0);
+                parent.pop();
+                result?;
+            },
+            (TreeNode::InnerContent(InnerContentTreeNode { value, raw }), Instance::Text(current)) => {
+                let GenericValue::Binding(binding) = value else {
+                    return Ok(());
+                };
+                if !self.affects_binding(binding) {
+                    return Ok(());
+                }
+                let text = self.text(value, *raw)?;
+                match (&*current, &text) {
+                    (Some(VNode::Text { raw: old_raw, .. }), Some(VNode::Text { text, raw })) if old_raw == raw => {
+                        if *current != Some(VNode::Text { text: text.clone(), raw: *raw }) {
+                            self.patches.push(Patch::SetText { path: child_path(parent, offset), text: text.clone() });
+                        }
+                    },
+                    _ => {
+                        if current.is_some() {
+                            self.patches.push(Patch::Remove { parent: parent.clone(), index: offset });
+                        }
+                        if let Some(node) = &text {
+                            self.patches.push(Patch::Insert { parent: parent.clone(), index: offset, node: node.clone() });
+                        }
+                    },
+                }
+                *current = text;
+            },
+            (TreeNode::Subtree(SubtreeTreeNode { property }), Instance::Subtree(inner)) => {
+                let component = resolve_component(&self.scope, property)?;
+                self.scope.enter_component();
+                if !self.affects_binding(property) {
+                    self.update(component, inner, parent, offset)?;
+                    self.scope.exit_component();
+                    return Ok(());
+                }
+                // Component is replaced as a whole
+                for _ in 0..inner.dom_len() {
+                    self.patches.push(Patch::Remove { parent: parent.clone(), index: offset });
+                }
+                **inner = self.build(component)?;
+                self.scope.exit_component();
+                let mut nodes = vec![];
+                inner.render(&mut nodes);
+                self.insert(nodes, parent, offset);
+            },
+            (TreeNode::Collection(node), Instance::Collection { items: instances, .. }) => {
+                self.update_collection(node, instances, parent, offset)?;
+            },
+            _ => unreachable!("Instance is built from the template node"),
+        }
+        Ok(())
+    }
+
+    fn update_all(
+        &mut self,
+        nodes: &'a [TreeNode],
+        instances: &mut [Instance],
+        parent: &mut Vec<usize>,
+        mut offset: usize,
+    ) -> Result<(), EvalError> {
+        for (node, instance) in nodes.iter().zip(instances) {
+            self.update(node, instance, parent, offset)?;
+            offset += instance.dom_len();
+        }
+        Ok(())
+    }
+
+    // Items are reconciled by key when the collection is replaced or the key of some item is
+    // changed, and the matched items are updated in place afterwards. Items matched at another
+    // index are bound to another data, so they are updated as a whole. Keys are checked on every
+    // update, as the key binding may refer to the data outside of the item.
+    fn update_collection(
+        &mut self,
+        node: &'a CollectionTreeNode,
+        instances: &mut Vec<Item>,
+        parent: &mut Vec<usize>,
+        offset: usize,
+    ) -> Result<(), EvalError> {
+        let path = self.data_path(&node.collection);
+        let items = resolve_collection(&self.scope, &node.collection)?;
+        let mut keys = Vec::with_capacity(items.len());
+        for index in 0..items.len() {
+            self.push_item(node, &path, items, index);
+            let key = item_key(node, &self.scope, &items[index], index);
+            self.pop_item();
+            keys.push(key?);
+        }
+        let replaced = self.affects(&path) && self.changed.len() <= path.len();
+        let mut matches = (0..items.len()).map(Some).collect::<Vec<_>>();
+        if replaced || !keys.iter().eq(instances.iter().map(|item| &item.key)) {
+            matches = self.reconcile(node, &path, items, keys, instances, parent, offset)?;
+        }
+
+        let mut item_offset = offset;
+        for (index, item) in instances.iter_mut().enumerate() {
+            if let Some(old_index) = matches[index] {
+                self.push_item(node, &path, items, index);
+                let changed = match old_index == index {
+                    true => None,
+                    false => Some(std::mem::replace(&mut self.changed, self.items.last().expect("Item is pushed").1.clone())),
+                };
+                let result = self.update_all(&node.nodes, &mut item.nodes, parent, item_offset);
+                if let Some(changed) = changed {
+                    self.changed = changed;
+                }
+                self.pop_item();
+                result?;
+            }
+            item_offset += item.dom_len();
+        }
+        Ok(())
+    }
+
+    // Removes, moves and inserts items in the same way as `vdom::diff` does for the children.
+    // Returns the old index of each item, which is missing for the items built from scratch.
+    #[allow(clippy::too_many_arguments)]
+    fn reconcile(
+        &mut self,
+        node: &'a CollectionTreeNode,
+        path: &[String],
+        items: &'a [Value],
+        keys: Vec<String>,
+        instances: &mut Vec<Item>,
+        parent: &[usize],
+        offset: usize,
+    ) -> Result<Vec<Option<usize>>, EvalError> {
+        let old = std::mem::take(instances);
+        let mut unmatched = HashMap::<&str, VecDeque<usize>>::new();
+        for (i, item) in old.iter().enumerate() {
+            unmatched.entry(&item.key).or_default().push_back(i);
+        }
+        let matches = keys
+            .iter()
+            .map(|key| unmatched.get_mut(key.as_str()).and_then(|candidates| candidates.pop_front()))
+            .collect::<Vec<_>>();
+
+        // Removed items, from the end so the offsets of the preceding ones stay valid
+        let mut removed = unmatched.into_values().flatten().collect::<Vec<_>>();
+        removed.sort_unstable();
+        for &i in removed.iter().rev() {
+            let start = offset + old[..i].iter().map(Item::dom_len).sum::<usize>();
+            for _ in 0..old[i].dom_len() {
+                self.patches.push(Patch::Remove { parent: parent.to_vec(), index: start });
+            }
+        }
+
+        // DOM items identified by the new item index, with their DOM node counts
+        let mut old_to_new = vec![None; old.len()];
+        for (j, i) in matches.iter().enumerate() {
+            if let Some(i) = i {
+                old_to_new[*i] = Some(j);
+            }
+        }
+        let mut placed = (0..items.len()).map(|_| None).collect::<Vec<_>>();
+        let mut lens = vec![0; items.len()];
+        for (i, item) in old.into_iter().enumerate() {
+            if let Some(j) = old_to_new[i] {
+                lens[j] = item.dom_len();
+                placed[j] = Some(item);
+            }
+        }
+        let mut current = old_to_new.into_iter().flatten().collect::<Vec<_>>();
+        let stable = longest_increasing(&current);
+        let start = |current: &[usize], lens: &[usize], position: usize| {
+            offset + current[..position].iter().map(|&j| lens[j]).sum::<usize>()
+        };
+
+        // Items are placed from the end, before the already placed next item
+        for j in (0..items.len()).rev() {
+            let anchor_position = match j + 1 {
+                next if next < items.len() => position(&current, next),
+                _ => current.len(),
+            };
+            let anchor = start(&current, &lens, anchor_position);
+            if matches[j].is_none() {
+                let item = self.build_item(node, path, items, j)?;
+                let mut nodes = vec![];
+                item.render(&node.collection, &mut nodes);
+                lens[j] = nodes.len();
+                self.insert(nodes, parent, anchor);
+                placed[j] = Some(item);
+                current.insert(anchor_position, j);
+            } else if !stable.contains(&j) {
+                let from_position = position(&current, j);
+                let from = start(&current, &lens, from_position);
+                current.remove(from_position);
+                let to_position = if from_position < anchor_position { anchor_position - 1 } else { anchor_position };
+                current.insert(to_position, j);
+                if from_position != to_position {
+                    // Item nodes are moved one by one, keeping their order
+                    for k in 0..lens[j] {
+                        let patch = match from < anchor {
+                            true => Patch::Move { parent: parent.to_vec(), from, to: anchor - 1 },
+                            false => Patch::Move { parent: parent.to_vec(), from: from + k, to: anchor + k },
+                        };
+                        self.patches.push(patch);
+                    }
+                }
+            }
+        }
+
+        *instances = placed.into_iter().map(|item| item.expect("Item is placed")).collect();
+        Ok(matches)
+    }
+
+    fn insert(&mut self, nodes: Vec<VNode>, parent: &[usize], offset: usize) {
+        for (k, node) in nodes.into_iter().enumerate() {
+            self.patches.push(Patch::Insert { parent: parent.to_vec(), index: offset + k, node });
+        }
+    }
+}
+
+fn attribute_values(value: &AttributeValue) -> &[GenericValue] {
+    match value {
+        AttributeValue::None => &[],
+        AttributeValue::Single(value) => std::slice::from_ref(value),
+        AttributeValue::Multiple(values) => values,
+    }
+}
+
+// Value at the data path; missing field of the object is added as null
+fn value_at_mut<'v>(value: &'v mut Value, path: &[String]) -> Option<&'v mut Value> {
+    let Some((key, rest)) = path.split_first() else {
+        return Some(value);
+    };
+    let next = match value {
+        Value::Object(fields) => match rest.is_empty() {
+            true => fields.entry(key.clone()).or_insert(Value::Null),
+            false => fields.get_mut(key)?,
+        },
+        Value::List(items) => items.get_mut(key.parse::<usize>().ok()?)?,
+        _ => return None,
+    };
+    value_at_mut(next, rest)
+}
+
+fn position(items: &[usize], j: usize) -> usize {
+    items.iter().position(|&i| i == j).expect("Item is placed")
+}
+
+fn child_path(parent: &[usize], index: usize) -> Vec<usize> {
+    let mut path = parent.to_vec();
+    path.push(index);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+    use crate::vdom::{apply, evaluate};
+    use expect_test::{expect, Expect};
+
+    fn assert_update(view: &mut ReactiveView, path: &str, value: Value, expect: Expect) {
+        let mut dom = view.dom();
+        let patches = view.set(path, value).unwrap();
+        apply(&mut dom, &patches).unwrap();
+        assert_eq!(dom, view.dom());
+        assert_eq!(dom, evaluate(&view.template, &view.data).unwrap());
+
+        let patches = patches.iter().map(|p| serde_json::to_string(p).unwrap()).collect::<Vec<_>>();
+        expect.assert_eq(&patches.join("\n"));
+    }
+
+    fn item(id: isize, name: &str) -> Value {
+        Value::object([("id", Value::from(id)), ("name", Value::from(name))])
+    }
+
+    #[test]
+    fn bound_updates() {
+        let template = TreeNode::parse("(h1[title=@title]>{@title})+(ul>(li[n=@index]>{@items%name}+{@user%name})*@items)+$footer").unwrap();
+        let data = Value::object([
+            ("title", Value::from("List")),
+            ("items", Value::from(vec![item(1, "a"), item(2, "b"), item(3, "c")])),
+            ("user", Value::object([("name", "x")])),
+            ("footer", TreeNode::parse("p>{@title}").unwrap().into()),
+        ]);
+        let mut view = ReactiveView::new(template, data).unwrap();
+
+        assert_update(&mut view, "title", "Todo".into(), expect![[r#"
+            {"op":"set-attribute","path":[0],"name":"title","value":"Todo"}
+            {"op":"set-text","path":[0,0],"text":"Todo"}
+            {"op":"set-text","path":[2,0],"text":"Todo"}"#]]);
+        assert_update(&mut view, "items%1%name", "B".into(), expect![[r#"{"op":"set-text","path":[1,1,0],"text":"B"}"#]]);
+        assert_update(&mut view, "items", vec![item(3, "c"), item(1, "a"), item(4, "d")].into(), expect![[r#"
+            {"op":"remove","parent":[1],"index":1}
+            {"op":"insert","parent":[1],"index":2,"node":{"type":"element","name":"li","attributes":[{"name":"n","value":"2"}],"key":"@items:4","children":[{"type":"text","text":"d"},{"type":"text","text":"x"}]}}
+            {"op":"move","parent":[1],"from":0,"to":1}
+            {"op":"set-attribute","path":[1,0],"name":"n","value":"0"}
+            {"op":"set-attribute","path":[1,1],"name":"n","value":"1"}"#]]);
+        assert_update(&mut view, "user%name", Value::Null, expect![[r#"
+            {"op":"remove","parent":[1,0],"index":1}
+            {"op":"remove","parent":[1,1],"index":1}
+            {"op":"remove","parent":[1,2],"index":1}"#]]);
+        assert_update(&mut view, "footer", TreeNode::parse("hr+p").unwrap().into(), expect![[r#"
+            {"op":"remove","parent":[],"index":2}
+            {"op":"insert","parent":[],"index":2,"node":{"type":"element","name":"hr","attributes":[],"children":[]}}
+            {"op":"insert","parent":[],"index":3,"node":{"type":"element","name":"p","attributes":[],"children":[]}}"#]]);
+        assert_update(&mut view, "unused", "x".into(), expect![[""]]);
+
+        assert_eq!(view.set("items%9%name", "x").unwrap_err().to_string(), "Path `items%9%name` is not found in data");
+        assert_eq!(view.set("title%text", "x").unwrap_err().to_string(), "Path `title%text` is not found in data");
+    }
+
+    #[test]
+    fn shadowed_loop_variables() {
+        let template = TreeNode::parse("ul>(li>{@first @last})*@items").unwrap();
+        let data = Value::object([("first", Value::from("F")), ("items", Value::from(vec!["a", "b"]))]);
+        let mut view = ReactiveView::new(template, data).unwrap();
+
+        assert_update(&mut view, "first", "G".into(), expect![[r#"
+            {"op":"set-text","path":[0,0,0],"text":"G"}
+            {"op":"set-text","path":[0,1,0],"text":"G"}"#]]);
+        assert_update(&mut view, "items", vec!["b"].into(), expect![[r#"{"op":"remove","parent":[0],"index":0}"#]]);
+    }
+
+    #[test]
+    fn keyed_items() {
+        let coded = |items: &[(&str, &str)]| {
+            Value::List(items.iter().map(|(code, name)| Value::object([("code", *code), ("name", *name)])).collect())
+        };
+        let template = TreeNode::parse("ul>((li>{@item%name})+hr)*@items as item key @item%code").unwrap();
+        let data = Value::object([("items", coded(&[("x", "a"), ("y", "b"), ("z", "c")]))]);
+        let mut view = ReactiveView::new(template, data).unwrap();
+
+        // Item nodes are moved together, and the moved item is updated in place
+        assert_update(&mut view, "items", coded(&[("z", "c"), ("x", "a"), ("y", "B")]), expect![[r#"
+            {"op":"move","parent":[0],"from":4,"to":0}
+            {"op":"move","parent":[0],"from":5,"to":1}
+            {"op":"set-text","path":[0,4,0],"text":"B"}"#]]);
+        assert_update(&mut view, "items%0%code", "w".into(), expect![[r#"
+            {"op":"remove","parent":[0],"index":0}
+            {"op":"remove","parent":[0],"index":0}
+            {"op":"insert","parent":[0],"index":0,"node":{"type":"element","name":"li","attributes":[],"key":"@items:w","children":[{"type":"text","text":"c"}]}}
+            {"op":"insert","parent":[0],"index":1,"node":{"type":"element","name":"hr","attributes":[],"key":"@items:w#1","children":[]}}"#]]);
+    }
+
+    #[test]
+    fn randomized_updates() {
+        let mut random = Random::new(0x2545_f491_4f6c_dd1d);
+        // Empty name stands for null
+        let names = ["a", "b", "c", "d", ""];
+        let value = |random: &mut Random| match names[random.next(names.len())] {
+            "" => Value::Null,
+            name => Value::from(name),
+        };
+        let items = |random: &mut Random| {
+            let mut items = (1..=6)
+                .map(|id| {
+                    let tags = (0..random.next(3)).map(|_| value(&mut *random)).collect::<Vec<_>>();
+                    Value::object([("id", Value::from(id)), ("name", value(&mut *random)), ("tags", Value::List(tags))])
+                })
+                .collect::<Vec<_>>();
+            items.retain(|_| random.next(4) != 0);
+            for i in (1..items.len()).rev() {
+                items.swap(i, random.next(i + 1));
+            }
+            Value::List(items)
+        };
+
+        // Items are keyed by the implicit `id`, and by the name which changes along with the item
+        let templates = [
+            "(h1>{@title})+ul>((li[n=@index last=@last]>{@items%name}+(b[t=@items%tags]>{@title})*@items%tags)+hr)*@items",
+            "(h1>{@title})+ul>((li[n=@index]>{@item%id}+(b[t=@tag]>{@item%name})*@item%tags as tag)+hr)*@items as item key @item%name",
+        ];
+        for template in templates {
+            let data = Value::object([("title", value(&mut random)), ("items", items(&mut random))]);
+            let mut view = ReactiveView::new(TreeNode::parse(template).unwrap(), data).unwrap();
+            for _ in 0..500 {
+                let len = match view.data().get("items") {
+                    Some(Value::List(items)) => items.len(),
+                    _ => unreachable!(),
+                };
+                let (path, value) = match random.next(5) {
+                    0 => ("title".to_owned(), value(&mut random)),
+                    1 => ("items".to_owned(), items(&mut random)),
+                    _ if len == 0 => continue,
+                    2 => (format!("items%{}%name", random.next(len)), value(&mut random)),
+                    3 => (format!("items%{}%id", random.next(len)), Value::from(random.next(10) as isize)),
+                    _ => {
+                        let tags = (0..random.next(3)).map(|_| value(&mut random)).collect::<Vec<_>>();
+                        (format!("items%{}%tags", random.next(len)), Value::List(tags))
+                    },
+                };
+
+                let mut dom = view.dom();
+                let patches = view.set(&path, value).unwrap();
+                apply(&mut dom, &patches).unwrap();
+                assert_eq!(dom, evaluate(&view.template, view.data()).unwrap());
+                assert_eq!(dom, view.dom());
+            }
+        }
+    }
+}
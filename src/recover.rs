@@ -0,0 +1,564 @@
+use crate::definition::DefinitionScope;
+use crate::parser::{Rule, SyntaxError, SyntaxParser, MULTIPLIED_CHILDREN_HINT};
+use crate::tree::{apply_multiplier, ErrorTreeNode, ParseError, RootTreeNode, TreeNode};
+use pest::Parser;
+use std::fmt::Display;
+use std::ops::Range;
+
+// Parse error reported by the recovering parser; line and column are 1-based, in characters
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseDiagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    // Suggestion how to fix the error
+    pub hint: Option<String>,
+}
+
+impl Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Sibling,
+    Child,
+}
+
+// Parsed nodes of the term with the operator following it
+struct ParsedTerm {
+    nodes: Vec<TreeNode>,
+    // Term has `*` multiplier, so it can't have children
+    multiplied: bool,
+    operator: Option<(Operator, usize)>,
+}
+
+// Top-level term of the expression with the operator following it (and its offset)
+struct Term {
+    start: usize,
+    end: usize,
+    operator: Option<(Operator, usize)>,
+}
+
+// Parses expression, recovering from errors at `+`, `>` and `)` boundaries. Every term which
+// failed to parse is replaced with the error node, unbalanced parentheses are reported and
+// ignored. Definitions are skipped when splitting the expression, and terms are parsed in the
+// scope of the definitions before them. Valid expression is parsed into the same tree as
+// `TreeNode::parse` produces.
+pub(crate) fn parse(input: &str) -> (TreeNode, Vec<ParseDiagnostic>) {
+    let mut parser = RecoveringParser { input: input.to_owned(), diagnostics: vec![] };
+    parser.balance_parentheses();
+    let children = parser.expression(0, input.len(), &[]);
+    parser.diagnostics.sort_by_key(|d| (d.line, d.column));
+    // Errors of the definitions are reported by every term using them
+    parser.diagnostics.dedup();
+    (RootTreeNode { children }.into(), parser.diagnostics)
+}
+
+// Parses template file (imports followed by definitions), recovering from errors at the import
+// and definition boundaries. Returns names of the valid definitions with their offsets.
+pub(crate) fn parse_file(input: &str) -> (Vec<(String, usize)>, Vec<ParseDiagnostic>) {
+    let mut parser = RecoveringParser { input: input.to_owned(), diagnostics: vec![] };
+    parser.balance_parentheses();
+    let mut definitions = vec![];
+    let mut after_definition = false;
+    let mut start = 0;
+    let end = input.len();
+    loop {
+        start = skip_trivia(&parser.input, start, end);
+        if start == end {
+            break;
+        }
+        if let Some((definition, valid)) = parser.definition(start, end) {
+            if valid {
+                definitions.push(definition_name(&parser.input[definition.clone()], definition.start));
+            }
+            after_definition = true;
+            start = definition.end;
+            continue;
+        }
+
+        // Invalid part of the file is skipped until `;` or the end of the line
+        let mut statement_end = None;
+        scan(&parser.input, start, end, |pos, c| {
+            if statement_end.is_none() && (c == ';' || c == '\n') {
+                statement_end = Some(pos + 1);
+            }
+        });
+        let statement_end = statement_end.unwrap_or(end);
+        let rest = parser.input[start..end].to_owned();
+        if starts_with_keyword(&rest, "let") {
+            // Unterminated definition takes the rest of the file
+            if let Err(e) = SyntaxParser::parse(Rule::definition, &rest) {
+                parser.report_error(&ParseError::from(SyntaxError::new(e, &rest)), &rest, start);
+            }
+            break;
+        }
+        if !starts_with_keyword(&rest, "import") {
+            parser.report(start, "Expected import or definition".to_owned(), None);
+            start = statement_end;
+            continue;
+        }
+        if after_definition {
+            parser.report(start, "Imports should precede the definitions".to_owned(), None);
+            start = statement_end;
+            continue;
+        }
+        match SyntaxParser::parse(Rule::import, &rest) {
+            Ok(mut pairs) => start += pairs.next().expect("Import is parsed").as_str().len(),
+            Err(e) => {
+                parser.report_error(&ParseError::from(SyntaxError::new(e, &rest)), &rest, start);
+                start = statement_end;
+            },
+        }
+    }
+    parser.diagnostics.sort_by_key(|d| (d.line, d.column));
+    (definitions, parser.diagnostics)
+}
+
+// Name of the valid definition and its offset, `offset` is the offset of the definition
+fn definition_name(source: &str, offset: usize) -> (String, usize) {
+    let definition = SyntaxParser::parse(Rule::definition, source).expect("Definition is valid");
+    let name = definition.flatten().find(|pair| pair.as_rule() == Rule::definition_name).expect("Definition has name");
+    (name.as_str().to_owned(), offset + name.as_span().start())
+}
+
+struct RecoveringParser {
+    // Input with unbalanced parentheses replaced by spaces
+    input: String,
+    diagnostics: Vec<ParseDiagnostic>,
+}
+
+impl RecoveringParser {
+    fn balance_parentheses(&mut self) {
+        let mut open = vec![];
+        let mut unbalanced = vec![];
+        scan(&self.input, 0, self.input.len(), |pos, c| match c {
+            '(' => open.push(pos),
+            ')' if open.pop().is_none() => unbalanced.push((pos, "Unexpected `)` without matching `(`")),
+            _ => {},
+        });
+        unbalanced.extend(open.into_iter().map(|pos| (pos, "Unclosed `(`")));
+        for (pos, message) in unbalanced {
+            self.report(pos, message.to_owned(), None);
+            self.input.replace_range(pos..pos + 1, " ");
+        }
+    }
+
+    // Parses `start..end` part of the input; `scope` are the parts which it sees: definitions of
+    // the enclosing expressions and parentheses of the enclosing groups
+    fn expression(&mut self, start: usize, end: usize, scope: &[Range<usize>]) -> Vec<TreeNode> {
+        if let (_, Ok(TreeNode::Root(RootTreeNode { children }))) = self.parse_in_scope(start..end, scope) {
+            return children;
+        }
+
+        let mut scope = scope.to_vec();
+        let start = self.definitions(start, end, &mut scope);
+        let mut terms = vec![];
+        let mut term_start = start;
+        let mut depth = 0;
+        scan(&self.input, start, end, |pos, c| match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '+' | '>' if depth == 0 => {
+                let operator = if c == '+' { Operator::Sibling } else { Operator::Child };
+                terms.push(Term { start: term_start, end: pos, operator: Some((operator, pos)) });
+                term_start = pos + 1;
+            },
+            _ => {},
+        });
+        terms.push(Term { start: term_start, end, operator: None });
+
+        let terms = terms
+            .into_iter()
+            .map(|term| {
+                let (nodes, multiplied) = self.term(&term, &scope);
+                ParsedTerm { nodes, multiplied, operator: term.operator }
+            })
+            .collect::<Vec<_>>();
+        self.assemble(terms)
+    }
+
+    // Skips definitions at the start of the expression, valid ones are added to the `scope`.
+    // Returns the offset where the terms of the expression start.
+    fn definitions(&mut self, start: usize, end: usize, scope: &mut Vec<Range<usize>>) -> usize {
+        let mut start = start;
+        while let Some((definition, valid)) = self.definition(start, end) {
+            if valid {
+                scope.push(definition.clone());
+            }
+            start = definition.end;
+        }
+        start
+    }
+
+    // Span of the definition which starts at `start` (after whitespace and comments), and
+    // whether the definition is valid; errors of the invalid one are reported. Unterminated
+    // definition is not recognized, so it's recovered as the term.
+    fn definition(&mut self, start: usize, end: usize) -> Option<(Range<usize>, bool)> {
+        let definition_start = skip_trivia(&self.input, start, end);
+        if !starts_with_keyword(&self.input[definition_start..end], "let") {
+            return None;
+        }
+
+        // Definition ends with `;`, semicolons of the nested definitions are in the groups
+        let mut definition_end = None;
+        let mut depth = 0;
+        scan(&self.input, definition_start, end, |pos, c| match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 && definition_end.is_none() => definition_end = Some(pos + 1),
+            _ => {},
+        });
+        let definition_end = definition_end?;
+        let source = self.input[definition_start..definition_end].to_owned();
+        let valid = match SyntaxParser::parse(Rule::definition, &source) {
+            Ok(_) => true,
+            Err(e) => {
+                let error = ParseError::from(SyntaxError::new(e, &source));
+                self.report_error(&error, &source, definition_start);
+                false
+            },
+        };
+        Some((definition_start..definition_end, valid))
+    }
+
+    // Returns nodes of the term, and whether the term is multiplied
+    fn term(&mut self, term: &Term, scope: &[Range<usize>]) -> (Vec<TreeNode>, bool) {
+        let text = &self.input[term.start..term.end];
+        let start = term.start + text.len() - text.trim_start().len();
+        let source = text.trim().to_owned();
+        if source.is_empty() {
+            let message = "Expected term".to_owned();
+            self.report(term.operator.map(|(_, pos)| pos).unwrap_or(term.end), message.clone(), None);
+            return (vec![error_node(source, message)], false);
+        }
+
+        if !source.starts_with('(') {
+            return match self.parse_in_scope(start..start + source.len(), scope) {
+                (_, Ok(TreeNode::Root(RootTreeNode { children }))) => {
+                    let mut multiplied = false;
+                    scan(&source, 0, source.len(), |_, c| multiplied |= c == '*');
+                    (children, multiplied)
+                },
+                (_, Ok(node)) => (vec![node], false),
+                (masked, Err(e)) => {
+                    let message = self.report_error(&e, &masked, 0);
+                    (vec![error_node(source, message)], false)
+                },
+            };
+        }
+
+        // Parentheses are balanced, so the group is always closed
+        let mut close = None;
+        let mut depth = 0;
+        scan(&self.input, start, term.end, |pos, c| match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 && close.is_none() {
+                    close = Some(pos);
+                }
+            },
+            _ => {},
+        });
+        let close = close.expect("Group is closed");
+        let group_scope = [scope, &[start..start + 1, close..close + 1]].concat();
+        let nodes = self.expression(start + 1, close, &group_scope);
+
+        let suffix = &self.input[close + 1..term.end];
+        let suffix_start = close + 1 + suffix.len() - suffix.trim_start().len();
+        let suffix = suffix.trim();
+        if suffix.is_empty() {
+            return (nodes, false);
+        }
+        let Some(multiplier) = suffix.strip_prefix('*') else {
+            let message = "Expected `*` multiplier after the group".to_owned();
+            self.report(suffix_start, message.clone(), None);
+            return (vec![error_node(source, message)], false);
+        };
+        let multiplier_start = suffix_start + 1 + multiplier.len() - multiplier.trim_start().len();
+        let multiplier = multiplier.trim().to_owned();
+        let result = match SyntaxParser::parse(Rule::multiplier, &multiplier) {
+            Ok(mut pairs) => {
+                let pair = pairs.next().expect("Multiplier is parsed");
+                if pair.as_str().len() == multiplier.len() {
+                    apply_multiplier(nodes, pair, &DefinitionScope::default()).map_err(ParseError::from)
+                } else {
+                    let message = "Unexpected input after multiplier".to_owned();
+                    self.report(multiplier_start + pair.as_str().len(), message.clone(), None);
+                    return (vec![error_node(source, message)], false);
+                }
+            },
+            Err(e) => Err(ParseError::from(SyntaxError::new(e, &multiplier))),
+        };
+        match result {
+            Ok(nodes) => (nodes, true),
+            Err(e) => {
+                let message = self.report_error(&e, &multiplier, multiplier_start);
+                (vec![error_node(source, message)], false)
+            },
+        }
+    }
+
+    // Joins terms in the same way as the strict parser does: `>` makes the rest of the
+    // expression children of the last node of the term
+    fn assemble(&mut self, terms: Vec<ParsedTerm>) -> Vec<TreeNode> {
+        let mut nodes = vec![];
+        let mut terms = terms.into_iter();
+        while let Some(ParsedTerm { nodes: mut term_nodes, multiplied, operator }) = terms.next() {
+            let Some((Operator::Child, pos)) = operator else {
+                nodes.append(&mut term_nodes);
+                continue;
+            };
+            let children = self.assemble(terms.collect());
+            // Children of the invalid parent are kept as siblings
+            match term_nodes.last_mut().map(|node| node.children_mut()) {
+                _ if multiplied => {
+                    let message = "Multiplied term can't have children".to_owned();
+                    self.report(pos, message, Some(MULTIPLIED_CHILDREN_HINT.to_owned()));
+                    term_nodes.extend(children);
+                },
+                Some(Ok(node_children)) => node_children.extend(children),
+                _ => {
+                    self.report(pos, "Leaf node can't have any children".to_owned(), None);
+                    term_nodes.extend(children);
+                },
+            }
+            nodes.append(&mut term_nodes);
+            break;
+        }
+        nodes
+    }
+
+    // Parses `range` of the input, which sees only `scope` parts of the input before it. Rest of
+    // the input is replaced with spaces, so offsets in the parsed input match the original ones.
+    // Returns the parsed input along with the result.
+    fn parse_in_scope(&self, range: Range<usize>, scope: &[Range<usize>]) -> (String, Result<TreeNode, ParseError>) {
+        let input = self.input.as_bytes();
+        let mut masked = input.iter().map(|&c| if c == b'\n' { c } else { b' ' }).collect::<Vec<_>>();
+        for part in scope.iter().chain([&range]) {
+            masked[part.clone()].copy_from_slice(&input[part.clone()]);
+        }
+        let masked = String::from_utf8(masked).expect("Input is masked at the character boundaries");
+        let result = TreeNode::parse(&masked);
+        (masked, result)
+    }
+
+    // Reports error of the `source` which starts at `offset` of the input, returns error message
+    fn report_error(&mut self, error: &ParseError, source: &str, offset: usize) -> String {
+        let position = error.location().map_or(0, |(line, column)| position_offset(source, line, column));
+        let (line, column) = line_col(&self.input, offset + position);
+        let message = error.message();
+        let hint = error.hint().map(str::to_owned);
+        self.diagnostics.push(ParseDiagnostic { line, column, message: message.clone(), hint });
+        message
+    }
+
+    fn report(&mut self, offset: usize, message: String, hint: Option<String>) {
+        let (line, column) = line_col(&self.input, offset);
+        self.diagnostics.push(ParseDiagnostic { line, column, message, hint });
+    }
+}
+
+fn error_node(source: String, message: String) -> TreeNode {
+    ErrorTreeNode { source, message, children: vec![] }.into()
+}
+
+// Calls `f` for each character of `input[start..end]` outside of strings and comments
+fn scan(input: &str, start: usize, end: usize, mut f: impl FnMut(usize, char)) {
+    let mut chars = input[start..end].char_indices().map(|(i, c)| (start + i, c)).peekable();
+    let mut quote = None;
+    while let Some((pos, c)) = chars.next() {
+        match (quote, c) {
+            (Some(_), '\\') => {
+                chars.next();
+            },
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '/') if input[pos..end].starts_with("//") => {
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+            },
+            (None, c) => f(pos, c),
+        }
+    }
+}
+
+fn starts_with_keyword(input: &str, keyword: &str) -> bool {
+    input.strip_prefix(keyword)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+}
+
+// Skips whitespace and comments
+fn skip_trivia(input: &str, start: usize, end: usize) -> usize {
+    let mut start = start;
+    loop {
+        let rest = &input[start..end];
+        let trimmed = rest.trim_start();
+        start += rest.len() - trimmed.len();
+        if !trimmed.starts_with("//") {
+            return start;
+        }
+        start += trimmed.find('\n').unwrap_or(trimmed.len());
+    }
+}
+
+// Byte offset of the 1-based line and column (in characters)
+fn position_offset(input: &str, line: usize, column: usize) -> usize {
+    let line_start = input.split_inclusive('\n').take(line - 1).map(str::len).sum::<usize>();
+    let line_text = &input[line_start..];
+    line_start + line_text.char_indices().nth(column - 1).map_or(line_text.len(), |(i, _)| i)
+}
+
+fn line_col(input: &str, offset: usize) -> (usize, usize) {
+    let before = &input[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::snapshot;
+    use expect_test::expect;
+
+    fn assert_recovered(input: &str, expect: expect_test::Expect) {
+        let (tree, diagnostics) = TreeNode::parse_recovering(input);
+        let diagnostics = diagnostics
+            .iter()
+            .map(|d| match &d.hint {
+                Some(hint) => format!("{} ({})", d, hint),
+                None => d.to_string(),
+            })
+            .collect::<Vec<_>>();
+        expect.assert_eq(&format!("{}\n{}", diagnostics.join("\n"), snapshot(&tree)));
+    }
+
+    #[test]
+    fn valid_expression() {
+        let input = "ul>(li.a[k=1]>{@x})*@items as item+(p>{'a+b>c)'})*2 // comment (";
+        let (tree, diagnostics) = TreeNode::parse_recovering(input);
+        assert_eq!(diagnostics, []);
+        assert_eq!(tree, TreeNode::parse(input).unwrap());
+
+        let input = "let a(x) = p>{@x};\nul>(let b = li>a(1); b+a(@y))*2+a('é')";
+        let (tree, diagnostics) = TreeNode::parse_recovering(input);
+        assert_eq!(diagnostics, []);
+        assert_eq!(tree, TreeNode::parse(input).unwrap());
+    }
+
+    #[test]
+    fn recover_definitions() {
+        assert_recovered(
+            "let t(x) = p>{@x%name}; t('lit')",
+            expect![[r#"
+                1:15: `@x%name` is used as a binding, but `"lit"` is given
+                - [ERROR] `t('lit')`: `@x%name` is used as a binding, but `"lit"` is given
+            "#]],
+        );
+
+        assert_recovered(
+            "let a = p>q; a+b[=1]",
+            expect![[r#"
+                1:18: expected attribute name
+                - p
+                  - q
+                - [ERROR] `b[=1]`: expected attribute name
+            "#]],
+        );
+
+        // Groups see the definitions of the enclosing expressions, and may have their own
+        assert_recovered(
+            "let a = p>{'é'};\ndiv>(let b = i>a; b+c[=1])+a+b",
+            expect![[r#"
+                2:23: expected attribute name
+                - div
+                  - i
+                    - p
+                      - [CONTENT] "é"
+                  - [ERROR] `c[=1]`: expected attribute name
+                  - p
+                    - [CONTENT] "é"
+                  - b
+            "#]],
+        );
+
+        // Invalid definition is skipped, so the name is not defined for the rest of the expression
+        assert_recovered(
+            "let a = p[=1]; let b = q>r;\n a+b",
+            expect![[r#"
+                1:11: expected attribute name
+                - a
+                - q
+                  - r
+            "#]],
+        );
+        assert_recovered(
+            "let c = i\n a+b",
+            expect![[r#"
+                2:2: expected attributes (`[...]`), `+`, or `>`
+                - [ERROR] `let c = i
+                 a`: expected attributes (`[...]`), `+`, or `>`
+                - b
+            "#]],
+        );
+    }
+
+    #[test]
+    fn recover_errors() {
+        assert_recovered(
+            "div>(p[=1]+span>{ok})+ul>li*99999999999999999999+\n  {x}>b+(a)*@x>c+(i)x+(em)*@y z+li*2>a+",
+            expect![[r#"
+                1:8: expected attribute name
+                1:29: Invalid number literal `99999999999999999999`
+                2:6: Leaf node can't have any children
+                2:15: Multiplied term can't have children (children can't be added to the multiplied term, group them before multiplying: `(div>p)*5` instead of `div*5>p`)
+                2:21: Expected `*` multiplier after the group
+                2:31: Unexpected input after multiplier
+                2:37: Multiplied term can't have children (children can't be added to the multiplied term, group them before multiplying: `(div>p)*5` instead of `div*5>p`)
+                2:40: Expected term
+                - div
+                  - [ERROR] `p[=1]`: expected attribute name
+                  - span
+                    - [CONTENT] "ok"
+                  - ul
+                    - [ERROR] `li*99999999999999999999`: Invalid number literal `99999999999999999999`
+                    - [CONTENT] "x"
+                    - b
+                    - [COLLECTION] @x
+                      - a
+                    - c
+                    - [ERROR] `(i)x`: Expected `*` multiplier after the group
+                    - [ERROR] `(em)*@y z`: Unexpected input after multiplier
+                    - li
+                    - li
+                    - a
+                    - [ERROR] ``: Expected term
+            "#]],
+        );
+
+        assert_recovered(
+            "a)+(b>c+d x",
+            expect![[r#"
+                1:2: Unexpected `)` without matching `(`
+                1:4: Unclosed `(`
+                1:11: expected end of input, attributes (`[...]`), `+`, or `>`
+                - a
+                - b
+                  - c
+                  - [ERROR] `d x`: expected end of input, attributes (`[...]`), `+`, or `>`
+            "#]],
+        );
+
+        let (tree, diagnostics) = TreeNode::parse_recovering("bad name>p>{text}");
+        assert_eq!(diagnostics.len(), 1);
+        expect![[r#"
+            - [ERROR] `bad name`: expected end of input, attributes (`[...]`), `+`, or `>`
+              - p
+                - [CONTENT] "text"
+        "#]].assert_eq(&snapshot(&tree));
+    }
+}
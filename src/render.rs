@@ -0,0 +1,653 @@
+use crate::escape::{write_escaped, EscapeContext};
+use crate::eval::EvalError;
+use crate::html::is_void_element;
+use crate::sanitize::{static_attribute_value, SanitizePolicy, SanitizeViolation, Sanitized};
+use crate::tree::{
+    Attribute, AttributeValue, GenericValue, InnerContentTreeNode, NormalTreeNode,
+    PropertyBinding, RootTreeNode, SubtreeTreeNode, CollectionTreeNode, TreeNode,
+};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("Binding {0} should be evaluated before rendering")]
+    UnresolvedBinding(PropertyBinding),
+    #[error(transparent)]
+    Eval(#[from] EvalError),
+    #[error(transparent)]
+    Sanitize(#[from] SanitizeViolation),
+    #[error(transparent)]
+    Format(#[from] std::fmt::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Markup {
+    // Elements are closed with the end tag, except for the void ones (`br`, `img`, etc.);
+    // valueless attributes are rendered as is
+    Html,
+    // Empty elements are self-closed, valueless attributes are rendered with empty value
+    Xml,
+}
+
+// Elements kept on the same line with the surrounding content by the pretty-printer
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "cite", "code", "data", "dfn", "em", "i", "img", "kbd",
+    "label", "mark", "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+];
+
+// Elements where whitespace is significant, so their content is never re-indented
+pub(crate) const PREFORMATTED_ELEMENTS: &[&str] = &["pre", "textarea", "script", "style"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Indent {
+    Spaces(usize),
+    Tabs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+// Output layout options, shared by the tree renderer and compiled templates. Default options
+// produce minified output. In pretty mode each block element starts on its own line, and its
+// content is indented, unless all of it is inline (text or inline elements), e.g. `<b>text</b>`
+// stays on one line. Each line, including the last one, is terminated with the line ending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    // `None` produces minified output
+    indent: Option<Indent>,
+    line_ending: LineEnding,
+    inline_elements: BTreeSet<String>,
+    // Attributes are placed on separate lines when opening tag doesn't fit into this width
+    max_line_width: Option<usize>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self::minified()
+    }
+}
+
+impl RenderOptions {
+    pub fn minified() -> Self {
+        Self {
+            indent: None,
+            line_ending: LineEnding::Lf,
+            inline_elements: INLINE_ELEMENTS.iter().map(|s| s.to_string()).collect(),
+            max_line_width: None,
+        }
+    }
+
+    // Pretty-printed output indented with 2 spaces
+    pub fn pretty() -> Self {
+        Self { indent: Some(Indent::Spaces(2)), ..Self::minified() }
+    }
+
+    pub fn with_indent(mut self, indent: Indent) -> Self {
+        self.indent = Some(indent);
+        self
+    }
+
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn with_inline_element(mut self, name: &str) -> Self {
+        self.inline_elements.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    pub fn with_block_element(mut self, name: &str) -> Self {
+        self.inline_elements.remove(&name.to_ascii_lowercase());
+        self
+    }
+
+    // Wraps attributes of the opening tags longer than `width` columns; tabs are counted as
+    // 4 columns
+    pub fn with_max_line_width(mut self, width: usize) -> Self {
+        self.max_line_width = Some(width);
+        self
+    }
+
+    pub fn is_pretty(&self) -> bool {
+        self.indent.is_some()
+    }
+
+    pub(crate) fn indent(&self) -> Option<Indent> {
+        self.indent
+    }
+
+    pub(crate) fn line_ending(&self) -> &'static str {
+        self.line_ending.as_str()
+    }
+
+    // Whether node could be placed on the same line with its siblings. Collections are inline
+    // only if all of their nodes are; subtrees are not known until evaluation.
+    pub(crate) fn is_inline(&self, node: &TreeNode) -> bool {
+        match node {
+            TreeNode::InnerContent(_) => true,
+            TreeNode::Normal(NormalTreeNode { name, .. }) => {
+                self.inline_elements.contains(&name.to_ascii_lowercase())
+            },
+            TreeNode::Collection(CollectionTreeNode { nodes, .. }) => nodes.iter().all(|n| self.is_inline(n)),
+            TreeNode::Root(_) | TreeNode::Subtree(_) | TreeNode::Error(_) => false,
+        }
+    }
+
+    pub(crate) fn root_layout(&self) -> Layout {
+        if self.is_pretty() {
+            Layout::block(0)
+        } else {
+            Layout::inline(0)
+        }
+    }
+
+    // Whether children of the block element should be placed on separate lines
+    pub(crate) fn is_multiline(&self, name: &str, children: &[TreeNode]) -> bool {
+        self.is_pretty()
+            && !PREFORMATTED_ELEMENTS.contains(&name.to_ascii_lowercase().as_str())
+            && !children.iter().all(|child| self.is_inline(child))
+    }
+
+    // Layout of the element children at `depth`. Consecutive inline children of the multiline
+    // element share a single line (parser splits text into separate words).
+    pub(crate) fn children_layout(&self, children: &[TreeNode], depth: usize, multiline: bool) -> Vec<Layout> {
+        if !multiline {
+            return vec![Layout::inline(depth); children.len()];
+        }
+        let inline = children.iter().map(|child| self.is_inline(child)).collect::<Vec<_>>();
+        (0..children.len()).map(|i| {
+            if !inline[i] {
+                return Layout::block(depth);
+            }
+            Layout {
+                depth,
+                block: false,
+                line_start: i == 0 || !inline[i - 1],
+                line_end: i + 1 == children.len() || !inline[i + 1],
+            }
+        }).collect()
+    }
+
+    pub(crate) fn write_indent(&self, out: &mut impl Write, depth: usize) -> std::fmt::Result {
+        match self.indent {
+            None => Ok(()),
+            Some(Indent::Spaces(n)) => write!(out, "{:1$}", "", n * depth),
+            Some(Indent::Tabs) => (0..depth).try_for_each(|_| out.write_char('\t')),
+        }
+    }
+
+    // Whether `count` attributes of the opening tag with the given length should be wrapped
+    pub(crate) fn wraps_attributes(&self, depth: usize, tag_length: usize, count: usize) -> bool {
+        let indent_width = match self.indent {
+            None => return false,
+            Some(Indent::Spaces(n)) => n * depth,
+            Some(Indent::Tabs) => 4 * depth,
+        };
+        count > 1 && self.max_line_width.is_some_and(|width| indent_width + tag_length > width)
+    }
+}
+
+// Placement of the node in the pretty-printed output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Layout {
+    pub(crate) depth: usize,
+    // Block node is placed on its own line, and its children could be placed on separate lines
+    pub(crate) block: bool,
+    // Node starts a new line, so it is indented
+    pub(crate) line_start: bool,
+    // Node is followed by the line ending
+    pub(crate) line_end: bool,
+}
+
+impl Layout {
+    fn inline(depth: usize) -> Self {
+        Self { depth, block: false, line_start: false, line_end: false }
+    }
+
+    fn block(depth: usize) -> Self {
+        Self { depth, block: true, line_start: true, line_end: true }
+    }
+}
+
+// Streaming renderer for the evaluated tree (see `eval::evaluate`). Tree is walked iteratively,
+// so deeply nested trees can't overflow the stack. Text and attribute values are escaped
+// according to their position in the document, unless content is marked as raw.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    markup: Markup,
+    policy: Option<SanitizePolicy>,
+    options: RenderOptions,
+}
+
+enum Step<'a> {
+    Enter { node: &'a TreeNode, layout: Layout },
+    Close { name: &'a str, layout: Layout, multiline: bool },
+}
+
+impl Renderer {
+    pub fn new(markup: Markup) -> Self {
+        Self { markup, policy: None, options: RenderOptions::default() }
+    }
+
+    // Applies sanitization policy to the rendered attributes. Attributes of the evaluated tree
+    // remember whether their values came from the bindings, so bound event handlers are caught
+    // the same way as by `Template::compile_with_policy`.
+    pub fn with_policy(mut self, policy: SanitizePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn with_options(mut self, options: RenderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn render(&self, tree: &TreeNode) -> Result<String, RenderError> {
+        let mut out = String::new();
+        self.render_to(tree, &mut out)?;
+        Ok(out)
+    }
+
+    pub fn render_to(&self, tree: &TreeNode, out: &mut impl Write) -> Result<(), RenderError> {
+        let line_ending = self.options.line_ending();
+        let mut stack = vec![Step::Enter { node: tree, layout: self.options.root_layout() }];
+        // Names of currently open elements
+        let mut open = vec![];
+
+        while let Some(step) = stack.pop() {
+            let (node, layout) = match step {
+                Step::Enter { node, layout } => (node, layout),
+                Step::Close { name, layout, multiline } => {
+                    if multiline {
+                        self.options.write_indent(out, layout.depth)?;
+                    }
+                    write!(out, "</{}>", name)?;
+                    if layout.line_end {
+                        out.write_str(line_ending)?;
+                    }
+                    open.pop();
+                    continue;
+                },
+            };
+
+            if layout.line_start && !matches!(node, TreeNode::Root(_)) {
+                self.options.write_indent(out, layout.depth)?;
+            }
+            let children_layout = match node {
+                TreeNode::Root(RootTreeNode { children }) => {
+                    self.options.children_layout(children, layout.depth, layout.block)
+                },
+                TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+                    self.write_open_tag(name, attributes, layout, out)?;
+                    // Void elements can't have content (see `html::validate`), so their children
+                    // are not rendered
+                    if self.markup == Markup::Html && is_void_element(name) {
+                        out.write_char('>')?;
+                        if layout.line_end {
+                            out.write_str(line_ending)?;
+                        }
+                        continue;
+                    }
+                    if children.is_empty() {
+                        match self.markup {
+                            Markup::Html => write!(out, "></{}>", name)?,
+                            Markup::Xml => out.write_str("/>")?,
+                        }
+                        if layout.line_end {
+                            out.write_str(line_ending)?;
+                        }
+                        continue;
+                    }
+                    let multiline = layout.block && self.options.is_multiline(name, children);
+                    out.write_char('>')?;
+                    if multiline {
+                        out.write_str(line_ending)?;
+                    }
+                    stack.push(Step::Close { name, layout, multiline });
+                    open.push(name.as_str());
+                    self.options.children_layout(children, layout.depth + 1, multiline)
+                },
+                TreeNode::InnerContent(InnerContentTreeNode { value, raw }) => {
+                    let context = match self.markup {
+                        _ if *raw => EscapeContext::Trusted,
+                        Markup::Html => EscapeContext::for_text(open.last().copied()),
+                        Markup::Xml => EscapeContext::Text,
+                    };
+                    write_value(value, context, out)?;
+                    if layout.line_end {
+                        out.write_str(line_ending)?;
+                    }
+                    continue;
+                },
+                TreeNode::Subtree(SubtreeTreeNode { property }) => {
+                    return Err(RenderError::UnresolvedBinding(property.clone()));
+                },
+                TreeNode::Collection(CollectionTreeNode { collection, .. }) => {
+                    return Err(RenderError::UnresolvedBinding(collection.clone()));
+                },
+                TreeNode::Error(node) => return Err(EvalError::from(node).into()),
+            };
+
+            let children = node.children().iter().zip(children_layout).rev();
+            stack.extend(children.map(|(node, layout)| Step::Enter { node, layout }));
+        }
+
+        Ok(())
+    }
+
+    pub fn render_to_io(&self, tree: &TreeNode, out: &mut impl std::io::Write) -> Result<(), RenderError> {
+        let mut adapter = IoAdapter::new(out);
+        let result = self.render_to(tree, &mut adapter);
+        adapter.finish(result)
+    }
+
+    fn write_open_tag(
+        &self,
+        name: &str,
+        attributes: &[Attribute],
+        layout: Layout,
+        out: &mut impl Write,
+    ) -> Result<(), RenderError> {
+        write!(out, "<{}", name)?;
+        if !layout.block || self.options.max_line_width.is_none() {
+            for attribute in attributes {
+                self.write_attribute(attribute, out)?;
+            }
+            return Ok(());
+        }
+
+        // Attributes are rendered beforehand to find out the tag length
+        let mut rendered = vec![];
+        for attribute in attributes {
+            let mut attribute_out = String::new();
+            self.write_attribute(attribute, &mut attribute_out)?;
+            if !attribute_out.is_empty() {
+                rendered.push(attribute_out);
+            }
+        }
+        let tag_length = name.chars().count() + 2 + rendered.iter().map(|a| a.chars().count()).sum::<usize>();
+        if !self.options.wraps_attributes(layout.depth, tag_length, rendered.len()) {
+            rendered.iter().try_for_each(|attribute| out.write_str(attribute))?;
+            return Ok(());
+        }
+        for attribute in rendered {
+            out.write_str(self.options.line_ending())?;
+            self.options.write_indent(out, layout.depth + 1)?;
+            // Skip leading separator
+            out.write_str(&attribute[1..])?;
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&self, attribute: &Attribute, out: &mut impl Write) -> Result<(), RenderError> {
+        if let Some(policy) = &self.policy {
+            let (value, bound) = static_attribute_value(attribute);
+            match policy.sanitize_attribute(&attribute.name, value.as_deref(), bound)? {
+                Sanitized::Keep => {},
+                Sanitized::Drop => return Ok(()),
+                Sanitized::Replace(value) => {
+                    write!(out, " {}=\"", attribute.name)?;
+                    write_escaped(out, value, EscapeContext::Attribute)?;
+                    out.write_char('"')?;
+                    return Ok(());
+                },
+            }
+        }
+
+        write!(out, " {}", attribute.name)?;
+        let values = match &attribute.value {
+            AttributeValue::None if self.markup == Markup::Html => return Ok(()),
+            AttributeValue::None => &[],
+            AttributeValue::Single(value) => std::slice::from_ref(value),
+            AttributeValue::Multiple(values) => values.as_slice(),
+        };
+
+        let context = match self.markup {
+            Markup::Html => EscapeContext::for_attribute(&attribute.name),
+            Markup::Xml => EscapeContext::Attribute,
+        };
+
+        out.write_str("=\"")?;
+        for (i, value) in values.iter().enumerate() {
+            if i != 0 {
+                out.write_char(' ')?;
+            }
+            write_value(value, context, out)?;
+        }
+        out.write_char('"')?;
+        Ok(())
+    }
+}
+
+// Renders evaluated tree as HTML document
+pub fn render_html(tree: &TreeNode) -> Result<String, RenderError> {
+    Renderer::new(Markup::Html).render(tree)
+}
+
+// Renders evaluated tree as XML document
+pub fn render_xml(tree: &TreeNode) -> Result<String, RenderError> {
+    Renderer::new(Markup::Xml).render(tree)
+}
+
+fn write_value(value: &GenericValue, context: EscapeContext, out: &mut impl Write) -> Result<(), RenderError> {
+    match value {
+        GenericValue::Text(s) => write_escaped(out, s, context)?,
+        GenericValue::Number(n) => write!(out, "{}", n)?,
+        GenericValue::Float(n) => out.write_str(&n.to_text())?,
+        GenericValue::Bool(b) => write!(out, "{}", b)?,
+        GenericValue::Binding(binding) => return Err(RenderError::UnresolvedBinding(binding.clone())),
+    }
+    Ok(())
+}
+
+// Allows streaming `fmt::Write`-based renderers into `io::Write` sinks, preserving original
+// IO error instead of opaque `fmt::Error`
+pub(crate) struct IoAdapter<'a, W: std::io::Write> {
+    inner: &'a mut W,
+    error: Option<std::io::Error>,
+}
+
+impl<'a, W: std::io::Write> IoAdapter<'a, W> {
+    pub(crate) fn new(inner: &'a mut W) -> Self {
+        Self { inner, error: None }
+    }
+
+    pub(crate) fn finish(self, result: Result<(), RenderError>) -> Result<(), RenderError> {
+        match (result, self.error) {
+            (Err(RenderError::Format(_)), Some(e)) => Err(e.into()),
+            (result, _) => result,
+        }
+    }
+}
+
+impl<W: std::io::Write> Write for IoAdapter<'_, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            std::fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escape::SafeHtml;
+    use crate::eval::evaluate;
+    use crate::sanitize::ViolationAction;
+    use crate::value::Value;
+    use expect_test::expect;
+
+    #[test]
+    fn render_tree() {
+        let tree = TreeNode::parse(
+            "div#button1.btn.alert[onclick='press_callback()' size=2]>b>{text 42}",
+        ).unwrap();
+        expect![[r#"<div id="button1" class="btn alert" onclick="press_callback()" size="2"><b>text 42</b></div>"#]]
+            .assert_eq(&render_html(&tree).unwrap());
+
+        let tree = TreeNode::parse("form>br+input[type=text]+img>{dropped}").unwrap();
+        assert_eq!(render_html(&tree).unwrap(), r#"<form><br><input type="text"><img></form>"#);
+        assert_eq!(render_xml(&tree).unwrap(), r#"<form><br/><input type="text"/><img>dropped</img></form>"#);
+
+        let tree = TreeNode::parse("p[size=1e3 ratio=0.5 big=-2.5e20]>{1.0 1e-7}").unwrap();
+        assert_eq!(render_html(&tree).unwrap(), r#"<p size="1000" ratio="0.5" big="-2.5e20">1 1e-7</p>"#);
+
+        let tree = TreeNode::parse("input[type=checkbox checked]").unwrap();
+        assert_eq!(render_html(&tree).unwrap(), r#"<input type="checkbox" checked>"#);
+        assert_eq!(render_xml(&tree).unwrap(), r#"<input type="checkbox" checked=""/>"#);
+
+        let tree = TreeNode::parse("div>{@text}").unwrap();
+        assert_eq!(
+            render_html(&tree).unwrap_err().to_string(),
+            "Binding @text should be evaluated before rendering"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let tree = TreeNode::parse(r#"
+            div[title=@title href=@url]>{@text}+!{@html '<i>!</i>'}+{@html}+script>{@script}
+        "#).unwrap();
+        let data = Value::object([
+            ("title", Value::from(r#""quoted" <b>"#)),
+            ("url", Value::from(r#"/a b?c="d""#)),
+            ("text", Value::from("1 < 2 & 3")),
+            ("html", Value::from(SafeHtml::new("<b>bold</b>"))),
+            ("script", Value::from(r#"if (a < b) { x = "</script>"; }"#)),
+        ]);
+        let tree = evaluate(&tree, &data).unwrap();
+
+        expect![[r#"<div title="&quot;quoted&quot; &lt;b&gt;" href="/a%20b?c=%22d%22">1 &lt; 2 &amp; 3<b>bold</b> <i>!</i><b>bold</b><script>if (a < b) { x = "<\/script>"; }</script></div>"#]]
+            .assert_eq(&render_html(&tree).unwrap());
+
+        expect![[r#"<div title="&quot;quoted&quot; &lt;b&gt;" href="/a b?c=&quot;d&quot;">1 &lt; 2 &amp; 3<b>bold</b> <i>!</i><b>bold</b><script>if (a &lt; b) { x = "&lt;/script&gt;"; }</script></div>"#]]
+            .assert_eq(&render_xml(&tree).unwrap());
+    }
+
+    #[test]
+    fn sanitization() {
+        let tree = TreeNode::parse(
+            "a[href=@url onclick='press()' title=@title]+img[src=@image onerror=@title]",
+        ).unwrap();
+        let data = Value::object([
+            ("url", Value::from(" JavaScript:alert(1)")),
+            ("title", Value::from("alert(1)")),
+            ("image", Value::from("https://example.com/a.png")),
+        ]);
+        let tree = evaluate(&tree, &data).unwrap();
+
+        let renderer = Renderer::new(Markup::Html).with_policy(SanitizePolicy::default());
+        expect![[r#"<a href="about:invalid#utree-sanitized" onclick="press()" title="alert(1)"></a><img src="https://example.com/a.png">"#]]
+            .assert_eq(&renderer.render(&tree).unwrap());
+        let renderer = Renderer::new(Markup::Html)
+            .with_policy(SanitizePolicy::default().with_action(ViolationAction::Reject));
+        assert_eq!(
+            renderer.render(&tree).unwrap_err().to_string(),
+            "Attribute `href` contains URL with disallowed scheme: ` JavaScript:alert(1)`"
+        );
+
+        let renderer = Renderer::new(Markup::Html).with_policy(SanitizePolicy::strict());
+        assert_eq!(
+            renderer.render(&tree).unwrap_err().to_string(),
+            "Attribute `href` contains URL with disallowed scheme: ` JavaScript:alert(1)`"
+        );
+
+        // Bound event handler is rejected, static one is kept
+        let tree = evaluate(&TreeNode::parse("img[onerror=@title onload='init()']").unwrap(), &data).unwrap();
+        let renderer = Renderer::new(Markup::Html)
+            .with_policy(SanitizePolicy::default().with_action(ViolationAction::Reject));
+        assert_eq!(renderer.render(&tree).unwrap_err().to_string(), "Event handler attribute `onerror` can't use bindings");
+        let tree = evaluate(&TreeNode::parse("img[onload='init()']").unwrap(), &data).unwrap();
+        assert_eq!(renderer.render(&tree).unwrap(), r#"<img onload="init()">"#);
+    }
+
+    #[test]
+    fn render_xml_tree() {
+        let tree = TreeNode::parse("config>(item[name=a]+item[enabled=1]>{text})+empty").unwrap();
+        expect![[r#"<config><item name="a"/><item enabled="1">text</item><empty/></config>"#]]
+            .assert_eq(&render_xml(&tree).unwrap());
+    }
+
+    #[test]
+    fn pretty_print() {
+        let tree = TreeNode::parse(
+            "(div#button1.btn.alert[onclick='press_callback()']>b>{text})+ul>(li#item1>div.list_icon+{My name is one})+li>b>{hi}",
+        ).unwrap();
+        let renderer = Renderer::new(Markup::Html).with_options(RenderOptions::pretty().with_max_line_width(40));
+        expect![[r#"
+            <div
+              id="button1"
+              class="btn alert"
+              onclick="press_callback()"><b>text</b></div>
+            <ul>
+              <li id="item1">
+                <div class="list_icon"></div>
+                My name is one
+              </li>
+              <li><b>hi</b></li>
+            </ul>
+        "#]].assert_eq(&renderer.render(&tree).unwrap());
+
+        let options = RenderOptions::pretty()
+            .with_indent(Indent::Spaces(4))
+            .with_line_ending(LineEnding::CrLf)
+            .with_block_element("b");
+        let tree = TreeNode::parse("config>item[name=a]+pre>b>{x}").unwrap();
+        assert_eq!(
+            Renderer::new(Markup::Xml).with_options(options).render(&tree).unwrap(),
+            "<config>\r\n    <item name=\"a\"/>\r\n    <pre><b>x</b></pre>\r\n</config>\r\n"
+        );
+    }
+
+    #[test]
+    fn render_to_io() {
+        let tree = TreeNode::parse("ul>li*2").unwrap();
+        let mut out = vec![];
+        Renderer::new(Markup::Html).render_to_io(&tree, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "<ul><li></li><li></li></ul>");
+
+        let mut sink = [0u8; 4];
+        let error = Renderer::new(Markup::Html)
+            .render_to_io(&tree, &mut sink.as_mut_slice())
+            .unwrap_err();
+        assert!(matches!(error, RenderError::Io(_)), "{:?}", error);
+    }
+
+    #[test]
+    fn deeply_nested_tree() {
+        let depth = 100_000;
+        let mut tree = TreeNode::parse("{leaf}").unwrap();
+        for _ in 0..depth {
+            tree = NormalTreeNode { name: "a".to_owned(), attributes: vec![], children: vec![tree] }.into();
+        }
+
+        let rendered = render_html(&tree).unwrap();
+        assert_eq!(rendered.len(), depth * "<a></a>".len() + "leaf".len());
+
+        // Default drop implementation is recursive, so dismantle the tree manually
+        let mut nodes = vec![tree];
+        while let Some(mut node) = nodes.pop() {
+            if let Ok(children) = node.children_mut() {
+                nodes.append(children);
+            }
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use crate::escape::EscapeContext;
+use crate::tree::{Attribute, AttributeValue, TreeNode};
+use std::collections::BTreeSet;
+use thiserror::Error;
+
+// Replacement for the rejected URLs when policy neutralizes violations
+pub const NEUTRALIZED_URL: &str = "about:invalid#utree-sanitized";
+
+// Attributes allowed by the strict policy; `*` suffix matches any attribute with the given prefix
+const SAFE_ATTRIBUTES: &[&str] = &[
+    "abbr", "accept", "align", "alt", "aria-*", "autocomplete", "checked", "cite", "class",
+    "colspan", "cols", "data-*", "datetime", "dir", "disabled", "download", "for", "headers",
+    "height", "hidden", "href", "hreflang", "id", "label", "lang", "max", "maxlength", "media",
+    "method", "min", "minlength", "multiple", "name", "pattern", "placeholder", "readonly", "rel",
+    "required", "role", "rows", "rowspan", "scope", "selected", "size", "span", "src", "srcset",
+    "start", "step", "tabindex", "target", "title", "type", "value", "width",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SanitizeViolation {
+    #[error("Attribute `{0}` is not allowed")]
+    UnknownAttribute(String),
+    #[error("Attribute `{name}` contains URL with disallowed scheme: `{url}`")]
+    UnsafeUrl { name: String, url: String },
+    #[error("Event handler attribute `{0}` can't use bindings")]
+    BoundEventHandler(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ViolationAction {
+    // Fail the rendering with `SanitizeViolation` error
+    Reject,
+    // Drop offending attribute, or replace unsafe URL with `NEUTRALIZED_URL`
+    Neutralize,
+}
+
+// Policy decision for the single attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sanitized {
+    Keep,
+    Drop,
+    Replace(&'static str),
+}
+
+// Attribute sanitization policy, applied by the renderers to each rendered attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizePolicy {
+    action: ViolationAction,
+    // `None` allows any attribute
+    allowed_attributes: Option<BTreeSet<String>>,
+    allowed_url_schemes: BTreeSet<String>,
+    allow_bound_event_handlers: bool,
+}
+
+impl Default for SanitizePolicy {
+    // Any attribute is allowed, however URLs are restricted to the safe schemes and event
+    // handlers can't be bound to data. Violations are neutralized.
+    fn default() -> Self {
+        Self {
+            action: ViolationAction::Neutralize,
+            allowed_attributes: None,
+            allowed_url_schemes: ["http", "https", "mailto", "tel"].iter().map(|s| s.to_string()).collect(),
+            allow_bound_event_handlers: false,
+        }
+    }
+}
+
+impl SanitizePolicy {
+    // Only known safe attributes are allowed (no event handlers or inline styles), all violations
+    // are rejected
+    pub fn strict() -> Self {
+        Self {
+            action: ViolationAction::Reject,
+            allowed_attributes: Some(SAFE_ATTRIBUTES.iter().map(|s| s.to_string()).collect()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_action(mut self, action: ViolationAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    // Adds attribute to the allowlist; `*` suffix allows all attributes with the given prefix
+    pub fn allow_attribute(mut self, name: &str) -> Self {
+        if let Some(allowed) = &mut self.allowed_attributes {
+            allowed.insert(name.to_ascii_lowercase());
+        }
+        self
+    }
+
+    pub fn allow_any_attribute(mut self) -> Self {
+        self.allowed_attributes = None;
+        self
+    }
+
+    pub fn allow_url_scheme(mut self, scheme: &str) -> Self {
+        self.allowed_url_schemes.insert(scheme.to_ascii_lowercase());
+        self
+    }
+
+    pub fn allow_bound_event_handlers(mut self, allow: bool) -> Self {
+        self.allow_bound_event_handlers = allow;
+        self
+    }
+
+    pub fn action(&self) -> ViolationAction {
+        self.action
+    }
+
+    pub fn is_url_attribute(name: &str) -> bool {
+        // `srcset` is not escaped as URL, as it contains the list of URLs with descriptors
+        EscapeContext::for_attribute(name) == EscapeContext::UrlAttribute
+            || matches!(name.to_ascii_lowercase().as_str(), "srcset" | "xlink:href")
+    }
+
+    // Checks attribute against the policy. `value` could be omitted when it is not known yet
+    // (e.g. bound value during template compilation), then only attribute name is checked.
+    pub fn check_attribute(&self, name: &str, value: Option<&str>, bound: bool) -> Result<(), SanitizeViolation> {
+        let name_lowercase = name.to_ascii_lowercase();
+
+        if let Some(allowed) = &self.allowed_attributes {
+            let is_allowed = allowed.iter().any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name_lowercase.starts_with(prefix),
+                None => *pattern == name_lowercase,
+            });
+            if !is_allowed {
+                return Err(SanitizeViolation::UnknownAttribute(name.to_owned()));
+            }
+        }
+
+        if bound && !self.allow_bound_event_handlers && name_lowercase.starts_with("on") {
+            return Err(SanitizeViolation::BoundEventHandler(name.to_owned()));
+        }
+
+        if let Some(url) = value.filter(|_| Self::is_url_attribute(&name_lowercase)) {
+            // `srcset` contains multiple comma-separated URLs
+            let is_allowed = self.is_allowed_url(url)
+                && (name_lowercase != "srcset" || url.split(',').all(|c| self.is_allowed_url(c)));
+            if !is_allowed {
+                return Err(SanitizeViolation::UnsafeUrl { name: name.to_owned(), url: url.to_owned() });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_allowed_url(&self, url: &str) -> bool {
+        url_scheme(url).is_none_or(|scheme| self.allowed_url_schemes.contains(&scheme))
+    }
+
+    // Checks attribute and applies policy action on violation
+    pub fn sanitize_attribute(&self, name: &str, value: Option<&str>, bound: bool) -> Result<Sanitized, SanitizeViolation> {
+        match self.check_attribute(name, value, bound) {
+            Ok(()) => Ok(Sanitized::Keep),
+            Err(violation) if self.action == ViolationAction::Reject => Err(violation),
+            Err(SanitizeViolation::UnsafeUrl { .. }) => Ok(Sanitized::Replace(NEUTRALIZED_URL)),
+            Err(_) => Ok(Sanitized::Drop),
+        }
+    }
+
+    // Statically checks the whole template before evaluation, including the parts which are not
+    // rendered for the current data (e.g. items of empty collections), and collects all violations
+    // instead of rejecting or neutralizing them during rendering. Values of the bound attributes
+    // are not known yet, so only their names are checked.
+    pub fn check_template(&self, tree: &TreeNode) -> Vec<SanitizeViolation> {
+        let mut violations = vec![];
+        let mut stack = vec![tree];
+        while let Some(node) = stack.pop() {
+            if let TreeNode::Normal(node) = node {
+                for attribute in &node.attributes {
+                    let (value, bound) = static_attribute_value(attribute);
+                    if let Err(violation) = self.check_attribute(&attribute.name, value.as_deref(), bound) {
+                        violations.push(violation);
+                    }
+                }
+            }
+            stack.extend(node.children().iter().rev());
+        }
+        violations
+    }
+}
+
+// Returns attribute value if it doesn't contain bindings, and flag whether it is bound (either
+// contains bindings, or was evaluated from them)
+pub(crate) fn static_attribute_value(attribute: &Attribute) -> (Option<String>, bool) {
+    let values = match &attribute.value {
+        AttributeValue::None => return (None, attribute.bound),
+        AttributeValue::Single(value) => std::slice::from_ref(value),
+        AttributeValue::Multiple(values) => values.as_slice(),
+    };
+
+    let mut text = vec![];
+    for value in values {
+        match value.to_text() {
+            Some(value) => text.push(value),
+            None => return (None, true),
+        }
+    }
+    (Some(text.join(" ")), attribute.bound)
+}
+
+// Extracts lowercase URL scheme the same way browsers do: leading whitespace and control
+// characters are ignored, as well as tabs and newlines anywhere in the URL
+fn url_scheme(url: &str) -> Option<String> {
+    let url = url.trim_start_matches(|c: char| c.is_whitespace() || c.is_control());
+    let mut scheme = String::new();
+    for c in url.chars().filter(|c| !matches!(c, '\t' | '\n' | '\r')) {
+        match c {
+            ':' => return Some(scheme.to_ascii_lowercase()),
+            c if c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.') => scheme.push(c),
+            // Relative URL
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XSS_URLS: &[&str] = &[
+        "javascript:alert(1)",
+        "JaVaScRiPt:alert(1)",
+        "  javascript:alert(1)",
+        "\u{1}javascript:alert(1)",
+        "java\tscript:alert(1)",
+        "java\nscript:alert(1)",
+        "vbscript:msgbox(1)",
+        "data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==",
+    ];
+
+    const SAFE_URLS: &[&str] = &[
+        "https://example.com/?q=javascript:alert(1)",
+        "/relative/path:with:colons",
+        "page.html#javascript:",
+        "?a=b",
+        "mailto:user@example.com",
+        "&#106;avascript:alert(1)",
+    ];
+
+    #[test]
+    fn urls() {
+        let policy = SanitizePolicy::default().with_action(ViolationAction::Reject);
+        for url in XSS_URLS {
+            for name in ["href", "SRC", "formaction"] {
+                assert!(policy.check_attribute(name, Some(url), true).is_err(), "{} {:?}", name, url);
+            }
+        }
+        for url in SAFE_URLS {
+            assert_eq!(policy.check_attribute("href", Some(url), true), Ok(()), "{:?}", url);
+        }
+
+        assert!(policy.check_attribute("srcset", Some("a.png 1x, javascript:alert(1) 2x"), false).is_err());
+        assert_eq!(policy.check_attribute("title", Some("javascript:alert(1)"), true), Ok(()));
+        assert_eq!(
+            policy.clone().allow_url_scheme("data").check_attribute("src", Some(XSS_URLS[7]), true),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn event_handlers() {
+        let policy = SanitizePolicy::default();
+        assert_eq!(policy.check_attribute("onclick", Some("press_callback()"), false), Ok(()));
+        assert_eq!(
+            policy.sanitize_attribute("onClick", Some("alert(1)"), true),
+            Ok(Sanitized::Drop)
+        );
+        assert_eq!(
+            policy.clone().allow_bound_event_handlers(true).sanitize_attribute("onclick", None, true),
+            Ok(Sanitized::Keep)
+        );
+        assert_eq!(
+            SanitizePolicy::strict().check_attribute("onclick", Some("press_callback()"), false),
+            Err(SanitizeViolation::UnknownAttribute("onclick".to_owned()))
+        );
+    }
+
+    #[test]
+    fn allowlist() {
+        let policy = SanitizePolicy::strict().allow_attribute("x-custom-*");
+        assert_eq!(policy.check_attribute("data-id", Some("1"), true), Ok(()));
+        assert_eq!(policy.check_attribute("x-custom-flag", None, false), Ok(()));
+        assert_eq!(
+            policy.sanitize_attribute("style", Some("background:url(javascript:alert(1))"), true),
+            Err(SanitizeViolation::UnknownAttribute("style".to_owned()))
+        );
+        assert_eq!(
+            policy.with_action(ViolationAction::Neutralize).sanitize_attribute("href", Some("javascript:alert(1)"), true),
+            Ok(Sanitized::Replace(NEUTRALIZED_URL))
+        );
+    }
+
+    #[test]
+    fn template_check() {
+        let tree = TreeNode::parse(
+            "div[onclick='press()']>a[href=@url onmouseover=@handler]+img[src='javascript:alert(1)' style=x]",
+        ).unwrap();
+        let violations = SanitizePolicy::default()
+            .check_template(&tree)
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<String>>();
+        assert_eq!(violations, [
+            "Event handler attribute `onmouseover` can't use bindings",
+            "Attribute `src` contains URL with disallowed scheme: `javascript:alert(1)`",
+        ]);
+    }
+}
@@ -0,0 +1,472 @@
+use crate::diff::{format_path, NodePath};
+use crate::incremental::IncrementalTree;
+use crate::tree::{AttributeValue, GenericValue, NormalTreeNode, ParseError, TreeNode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use thiserror::Error;
+
+// Type of the attribute value. Bound values are not known until evaluation, so they are
+// accepted by any type.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    #[default]
+    Text,
+    Number,
+    Boolean,
+    Enum(Vec<String>),
+}
+
+impl ValueType {
+    fn accepts(&self, value: &GenericValue) -> bool {
+        match (self, value) {
+            (_, GenericValue::Binding(_)) | (ValueType::Text, _) => true,
+            (ValueType::Number, GenericValue::Number(_)) => true,
+            // `f64` parsing also accepts "NaN" and "inf", which aren't numbers of the document
+            (ValueType::Number, GenericValue::Float(n)) => n.0.is_finite(),
+            (ValueType::Number, GenericValue::Text(s)) => s.parse::<f64>().is_ok_and(f64::is_finite),
+            (ValueType::Boolean, GenericValue::Bool(_)) => true,
+            (ValueType::Boolean, GenericValue::Text(s)) => matches!(s.as_str(), "true" | "false"),
+            (ValueType::Number | ValueType::Boolean, _) => false,
+            (ValueType::Enum(variants), value) => value.to_text().is_some_and(|text| variants.contains(&text)),
+        }
+    }
+}
+
+impl Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueType::Text => f.write_str("text"),
+            ValueType::Number => f.write_str("number"),
+            ValueType::Boolean => f.write_str("boolean"),
+            ValueType::Enum(variants) => write!(f, "one of [{}]", variants.join(", ")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttributeSchema {
+    pub required: bool,
+    #[serde(rename = "type")]
+    pub value_type: ValueType,
+}
+
+// Allowed number of the child elements with the given name; `max: None` means unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cardinality {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Display for Cardinality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "exactly {}", max),
+            Some(max) => write!(f, "{}..{}", self.min, max),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ElementSchema {
+    attributes: BTreeMap<String, AttributeSchema>,
+    children: BTreeMap<String, Cardinality>,
+    // Whether element could contain text content
+    text: bool,
+}
+
+impl ElementSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attribute(mut self, name: &str, value_type: ValueType) -> Self {
+        self.attributes.insert(name.to_owned(), AttributeSchema { required: false, value_type });
+        self
+    }
+
+    pub fn required_attribute(mut self, name: &str, value_type: ValueType) -> Self {
+        self.attributes.insert(name.to_owned(), AttributeSchema { required: true, value_type });
+        self
+    }
+
+    pub fn child(mut self, name: &str, min: usize, max: Option<usize>) -> Self {
+        self.children.insert(name.to_owned(), Cardinality { min, max });
+        self
+    }
+
+    pub fn with_text(mut self) -> Self {
+        self.text = true;
+        self
+    }
+}
+
+// Schema of the XML-like dialect: allowed elements, their attributes and content. Could be
+// defined with the builder methods or loaded from JSON, e.g.
+// `{"root": ["config"], "elements": {"config": {"children": {"item": {"min": 1}}}}}`
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schema {
+    // Elements allowed at the top level; empty list allows any known element
+    root: Vec<String>,
+    elements: BTreeMap<String, ElementSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SchemaError {
+    #[error("{}: unknown element `{name}`", format_path(path))]
+    UnknownElement { path: NodePath, name: String },
+    #[error("{}: element `{name}` is not allowed at the top level", format_path(path))]
+    UnexpectedRoot { path: NodePath, name: String },
+    #[error("{}: element `{element}` has unknown attribute `{attribute}`", format_path(path))]
+    UnknownAttribute { path: NodePath, element: String, attribute: String },
+    #[error("{}: element `{element}` is missing required attribute `{attribute}`", format_path(path))]
+    MissingAttribute { path: NodePath, element: String, attribute: String },
+    #[error(
+        "{}: attribute `{attribute}` of element `{element}` should be {expected}, got {value}",
+        format_path(path)
+    )]
+    InvalidAttributeValue { path: NodePath, element: String, attribute: String, expected: ValueType, value: String },
+    #[error("{}: element `{child}` is not allowed inside of `{element}`", format_path(path))]
+    UnexpectedChild { path: NodePath, element: String, child: String },
+    #[error("{}: element `{element}` can't contain text", format_path(path))]
+    UnexpectedText { path: NodePath, element: String },
+    #[error("{}: element `{element}` should contain {expected} `{child}`, got {count}", format_path(path))]
+    ChildCount { path: NodePath, element: String, child: String, expected: Cardinality, count: usize },
+}
+
+impl SchemaError {
+    // Path of the element the error is about
+    pub fn path(&self) -> &NodePath {
+        match self {
+            SchemaError::UnknownElement { path, .. }
+            | SchemaError::UnexpectedRoot { path, .. }
+            | SchemaError::UnknownAttribute { path, .. }
+            | SchemaError::MissingAttribute { path, .. }
+            | SchemaError::InvalidAttributeValue { path, .. }
+            | SchemaError::UnexpectedChild { path, .. }
+            | SchemaError::UnexpectedText { path, .. }
+            | SchemaError::ChildCount { path, .. } => path,
+        }
+    }
+}
+
+// Schema error with the line and column (1-based) of the term which produced the element, if known
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{}{error}", location.map(|(line, column)| format!("{}:{}: ", line, column)).unwrap_or_default())]
+pub struct LocatedSchemaError {
+    pub error: SchemaError,
+    pub location: Option<(usize, usize)>,
+}
+
+// Element child with collections and nested roots flattened
+struct Child<'a> {
+    node: &'a TreeNode,
+    path: NodePath,
+    // Node is repeated by the collection, so its number is not known until evaluation
+    repeated: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    pub fn root(mut self, name: &str) -> Self {
+        self.root.push(name.to_owned());
+        self
+    }
+
+    pub fn element(mut self, name: &str, element: ElementSchema) -> Self {
+        self.elements.insert(name.to_owned(), element);
+        self
+    }
+
+    // Validates the template tree. Only definite violations are reported: bound attribute values
+    // are accepted by any type, elements repeated by collections count towards neither minimal
+    // nor maximal number of children, and element with component subtrees may have less
+    // children than required. Content of the components should be validated separately.
+    pub fn validate(&self, tree: &TreeNode) -> Vec<SchemaError> {
+        let mut errors = vec![];
+        let mut stack = vec![];
+
+        let top_level = flatten_children(tree, &[]);
+        for child in &top_level {
+            if let TreeNode::Normal(NormalTreeNode { name, .. }) = child.node {
+                if !self.root.is_empty() && !self.root.contains(name) {
+                    errors.push(SchemaError::UnexpectedRoot { path: child.path.clone(), name: name.clone() });
+                }
+            }
+        }
+        stack.extend(top_level.into_iter().rev());
+
+        while let Some(Child { node, path, .. }) = stack.pop() {
+            let NormalTreeNode { name, attributes, .. } = match node {
+                TreeNode::Normal(node) => node,
+                _ => continue,
+            };
+            let element = match self.elements.get(name) {
+                Some(element) => element,
+                None => {
+                    errors.push(SchemaError::UnknownElement { path, name: name.clone() });
+                    continue;
+                },
+            };
+
+            for attribute in attributes {
+                let schema = match element.attributes.get(&attribute.name) {
+                    Some(schema) => schema,
+                    None => {
+                        errors.push(SchemaError::UnknownAttribute {
+                            path: path.clone(),
+                            element: name.clone(),
+                            attribute: attribute.name.clone(),
+                        });
+                        continue;
+                    },
+                };
+                let values = match &attribute.value {
+                    AttributeValue::None => &[GenericValue::Text(String::new())][..],
+                    AttributeValue::Single(value) => std::slice::from_ref(value),
+                    AttributeValue::Multiple(values) => values.as_slice(),
+                };
+                for value in values.iter().filter(|value| !schema.value_type.accepts(value)) {
+                    errors.push(SchemaError::InvalidAttributeValue {
+                        path: path.clone(),
+                        element: name.clone(),
+                        attribute: attribute.name.clone(),
+                        expected: schema.value_type.clone(),
+                        value: value.to_string(),
+                    });
+                }
+            }
+            for (attribute, _) in element.attributes.iter().filter(|(_, schema)| schema.required) {
+                if !attributes.iter().any(|a| a.name == *attribute) {
+                    errors.push(SchemaError::MissingAttribute {
+                        path: path.clone(),
+                        element: name.clone(),
+                        attribute: attribute.clone(),
+                    });
+                }
+            }
+
+            let children = flatten_children(node, &path);
+            self.validate_children(name, element, &path, &children, &mut errors);
+            stack.extend(children.into_iter().rev());
+        }
+
+        errors
+    }
+
+    // Validates the expression source, errors are located at the terms which produced the
+    // elements (see `IncrementalTree::node_span`). Source with definitions or generated text is
+    // validated without locations.
+    pub fn validate_source(&self, source: &str) -> Result<Vec<LocatedSchemaError>, ParseError> {
+        let parsed = IncrementalTree::parse(source)?;
+        let tree = parsed.tree().expect("Parsed source has the tree");
+        let errors = self.validate(tree).into_iter().map(|error| {
+            let location = parsed.node_span(error.path()).map(|span| {
+                pest::Position::new(source, span.start).expect("Span is in the source").line_col()
+            });
+            LocatedSchemaError { error, location }
+        });
+        Ok(errors.collect())
+    }
+
+    fn validate_children(
+        &self,
+        name: &str,
+        element: &ElementSchema,
+        path: &NodePath,
+        children: &[Child],
+        errors: &mut Vec<SchemaError>,
+    ) {
+        // Number of the static children, and whether there are repeated ones
+        let mut counts: BTreeMap<&str, (usize, bool)> = BTreeMap::new();
+        let mut has_subtrees = false;
+
+        for child in children {
+            match child.node {
+                TreeNode::Normal(NormalTreeNode { name: child_name, .. }) => {
+                    if !element.children.contains_key(child_name) {
+                        errors.push(SchemaError::UnexpectedChild {
+                            path: child.path.clone(),
+                            element: name.to_owned(),
+                            child: child_name.clone(),
+                        });
+                    }
+                    let count = counts.entry(child_name).or_default();
+                    if child.repeated {
+                        count.1 = true;
+                    } else {
+                        count.0 += 1;
+                    }
+                },
+                TreeNode::InnerContent(_) if !element.text => {
+                    errors.push(SchemaError::UnexpectedText { path: child.path.clone(), element: name.to_owned() });
+                },
+                // Content of the invalid expression part is not known as well
+                TreeNode::Subtree(_) | TreeNode::Error(_) => has_subtrees = true,
+                _ => {},
+            }
+        }
+
+        for (child, cardinality) in &element.children {
+            let (count, repeated) = counts.get(child.as_str()).copied().unwrap_or_default();
+            let too_few = count < cardinality.min && !repeated && !has_subtrees;
+            let too_many = cardinality.max.is_some_and(|max| count > max);
+            if too_few || too_many {
+                errors.push(SchemaError::ChildCount {
+                    path: path.clone(),
+                    element: name.to_owned(),
+                    child: child.clone(),
+                    expected: *cardinality,
+                    count,
+                });
+            }
+        }
+    }
+}
+
+fn flatten_children<'a>(node: &'a TreeNode, path: &[usize]) -> Vec<Child<'a>> {
+    let mut children = vec![];
+    let mut stack = vec![];
+    for (i, child) in node.children().iter().enumerate().rev() {
+        stack.push((child, [path, &[i]].concat(), false));
+    }
+
+    while let Some((node, path, repeated)) = stack.pop() {
+        let repeated = repeated || matches!(node, TreeNode::Collection(_));
+        match node {
+            TreeNode::Root(_) | TreeNode::Collection(_) => {
+                for (i, child) in node.children().iter().enumerate().rev() {
+                    stack.push((child, [path.as_slice(), &[i]].concat(), repeated));
+                }
+            },
+            node => children.push(Child { node, path, repeated }),
+        }
+    }
+
+    children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate_str(schema: &Schema, input: &str) -> Vec<String> {
+        schema.validate(&TreeNode::parse(input).unwrap()).iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn validate_tree() {
+        let schema = Schema::new()
+            .root("config")
+            .element("config", ElementSchema::new()
+                .required_attribute("version", ValueType::Number)
+                .child("item", 1, None)
+                .child("meta", 0, Some(1)))
+            .element("item", ElementSchema::new()
+                .required_attribute("name", ValueType::Text)
+                .attribute("mode", ValueType::Enum(vec!["fast".to_owned(), "safe".to_owned()]))
+                .with_text())
+            .element("meta", ElementSchema::new());
+
+        assert!(validate_str(&schema, "config[version=2]>item[name=a mode=fast]>{text}").is_empty());
+        assert!(validate_str(&schema, "config[version=@v]>(item[name=@items%name])*@items").is_empty());
+
+        assert_eq!(
+            validate_str(
+                &schema,
+                "(config[version=x]>item[mode=slow]+(meta*2)+data)+config[version=1 extra=1]>meta>{text}+$other",
+            ),
+            [
+                "/0: attribute `version` of element `config` should be number, got \"x\"",
+                "/0/3: element `data` is not allowed inside of `config`",
+                "/0: element `config` should contain 0..1 `meta`, got 2",
+                "/0/0: attribute `mode` of element `item` should be one of [fast, safe], got \"slow\"",
+                "/0/0: element `item` is missing required attribute `name`",
+                "/0/3: unknown element `data`",
+                "/1: element `config` has unknown attribute `extra`",
+                "/1: element `config` should contain at least 1 `item`, got 0",
+                "/1/0/0: element `meta` can't contain text",
+            ]
+        );
+    }
+
+    #[test]
+    fn load_from_json() {
+        let schema = Schema::from_json(r#"{
+            "root": ["feed"],
+            "elements": {
+                "feed": { "children": { "entry": { "max": 2 } } },
+                "entry": {
+                    "attributes": {
+                        "id": { "required": true, "type": "number" },
+                        "kind": { "type": { "enum": ["post", "note"] } }
+                    },
+                    "text": true
+                }
+            }
+        }"#).unwrap();
+
+        assert!(validate_str(&schema, "feed>entry[id=1 kind=post]>{hello}").is_empty());
+        assert_eq!(validate_str(&schema, "feed>(entry[id=@id])*@entries+entry[kind=page]+entry*2"), [
+            "/0: element `feed` should contain 0..2 `entry`, got 3",
+            "/0/1: attribute `kind` of element `entry` should be one of [post, note], got \"page\"",
+            "/0/1: element `entry` is missing required attribute `id`",
+            "/0/2: element `entry` is missing required attribute `id`",
+            "/0/3: element `entry` is missing required attribute `id`",
+        ]);
+        assert!(Schema::from_json(r#"{"elements": {"a": {"unknown": 1}}}"#).is_err());
+    }
+
+    #[test]
+    fn non_finite_numbers() {
+        let schema = Schema::new().element("a", ElementSchema::new().attribute("n", ValueType::Number));
+        assert!(validate_str(&schema, "a[n=1]+a[n=-2.5]+a[n='1e3']").is_empty());
+        assert_eq!(validate_str(&schema, "a[n=NaN]+a[n=inf]+a[n='-infinity']+a[n='1e999']"), [
+            "/0: attribute `n` of element `a` should be number, got \"NaN\"",
+            "/1: attribute `n` of element `a` should be number, got \"inf\"",
+            "/2: attribute `n` of element `a` should be number, got \"-infinity\"",
+            "/3: attribute `n` of element `a` should be number, got \"1e999\"",
+        ]);
+        let tree = crate::builder::root([crate::builder::node("a").attr("n", f64::NAN)]);
+        assert_eq!(schema.validate(&tree).len(), 1);
+    }
+
+    #[test]
+    fn error_locations() {
+        let schema = Schema::new()
+            .root("list")
+            .element("list", ElementSchema::new().child("item", 1, None))
+            .element("item", ElementSchema::new().required_attribute("id", ValueType::Number));
+        let errors = |source: &str| {
+            schema.validate_source(source).unwrap().iter().map(|e| e.to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(errors("list>item[id=1]+\n  (item[id=x])*2+\n  (item>{@text})*@items"), [
+            "2:4: /0/1: attribute `id` of element `item` should be number, got \"x\"",
+            "2:4: /0/2: attribute `id` of element `item` should be number, got \"x\"",
+            "3:4: /0/3/0: element `item` is missing required attribute `id`",
+            "3:9: /0/3/0/0: element `item` can't contain text",
+        ]);
+        assert_eq!(errors("list+\nitems"), [
+            "2:1: /1: element `items` is not allowed at the top level",
+            "1:1: /0: element `list` should contain at least 1 `item`, got 0",
+            "2:1: /1: unknown element `items`",
+        ]);
+        // Terms of the expression with definitions are not kept
+        assert_eq!(errors("let x = item; list>x"), ["/0/0: element `item` is missing required attribute `id`"]);
+        assert!(schema.validate_source("list>").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::tree::{BindingRole, BindingUsage, LoopScope, PropertyBinding, TreeNode};
+use crate::value::LOOP_VARIABLES;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use thiserror::Error;
+
+// Declared shape of the template data, used to check bindings before rendering
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    // Value of any shape, bindings into it are not checked
+    Any,
+    // Text, number, boolean or safe markup
+    Scalar,
+    Object(BTreeMap<String, Shape>),
+    List(Box<Shape>),
+    // Component tree for the `$binding` nodes
+    Component,
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shape::Any => f.write_str("any value"),
+            Shape::Scalar => f.write_str("scalar"),
+            Shape::Object(_) => f.write_str("object"),
+            Shape::List(_) => f.write_str("list"),
+            Shape::Component => f.write_str("component"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ShapeError {
+    #[error("Binding {binding} refers to undeclared field `{field}`")]
+    UnknownField { binding: PropertyBinding, field: String },
+    #[error("Binding {binding} can't access field `{field}` of {shape}")]
+    NotAnObject { binding: PropertyBinding, field: String, shape: String },
+    #[error("Binding {binding} is used as {role}, but declared as {shape}")]
+    RoleMismatch { binding: PropertyBinding, role: BindingRole, shape: String },
+}
+
+impl Shape {
+    pub fn object<K: Into<String>>(fields: impl IntoIterator<Item = (K, Shape)>) -> Shape {
+        Shape::Object(fields.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    pub fn list(item: Shape) -> Shape {
+        Shape::List(Box::new(item))
+    }
+
+    // Checks all bindings of the template against this shape of the root data object
+    pub fn check(&self, tree: &TreeNode) -> Vec<ShapeError> {
+        tree.bindings().iter().filter_map(|usage| self.check_usage(usage).err()).collect()
+    }
+
+    pub fn check_usage(&self, usage: &BindingUsage) -> Result<(), ShapeError> {
+        let shape = self.resolve(&usage.binding, &usage.collections)?;
+        let matches = match (&usage.role, shape) {
+            (_, Shape::Any) => true,
+            (BindingRole::Text | BindingRole::Attribute(_) | BindingRole::Key, shape) => *shape == Shape::Scalar,
+            (BindingRole::Collection, shape) => matches!(shape, Shape::List(_)),
+            (BindingRole::Subtree, shape) => *shape == Shape::Component,
+        };
+        if !matches {
+            return Err(ShapeError::RoleMismatch {
+                binding: usage.binding.clone(),
+                role: usage.role.clone(),
+                shape: shape.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Resolves binding the same way as `value::Scope` does: bindings starting with the item path
+    // of the enclosing collection are resolved against its item, and implicit loop variables are
+    // scalars unless shadowed by the declared field
+    fn resolve(&self, binding: &PropertyBinding, collections: &[LoopScope]) -> Result<&Shape, ShapeError> {
+        let resolved = self.resolve_data(binding, collections);
+        match binding.parts() {
+            [name] if resolved.is_err() && !collections.is_empty() && LOOP_VARIABLES.contains(&name.as_str()) => {
+                Ok(&Shape::Scalar)
+            },
+            _ => resolved,
+        }
+    }
+
+    fn resolve_data(&self, binding: &PropertyBinding, collections: &[LoopScope]) -> Result<&Shape, ShapeError> {
+        let parts = binding.parts();
+        for (i, scope) in collections.iter().enumerate().rev() {
+            if let Some(rest) = parts.strip_prefix(scope.item_path()) {
+                let item = match self.resolve(&scope.collection, &collections[..i])? {
+                    Shape::List(item) => item,
+                    // Mismatch is reported for the collection binding itself
+                    _ => return Ok(&Shape::Any),
+                };
+                return item.resolve_path(binding, rest);
+            }
+        }
+        self.resolve_path(binding, parts)
+    }
+
+    fn resolve_path(&self, binding: &PropertyBinding, path: &[String]) -> Result<&Shape, ShapeError> {
+        let mut shape = self;
+        for field in path {
+            shape = match shape {
+                Shape::Any => return Ok(shape),
+                Shape::Object(fields) => fields.get(field).ok_or_else(|| ShapeError::UnknownField {
+                    binding: binding.clone(),
+                    field: field.clone(),
+                })?,
+                shape => return Err(ShapeError::NotAnObject {
+                    binding: binding.clone(),
+                    field: field.clone(),
+                    shape: shape.to_string(),
+                }),
+            };
+        }
+        Ok(shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_bindings() {
+        let shape = Shape::object([
+            ("title", Shape::Scalar),
+            ("items", Shape::list(Shape::object([
+                ("id", Shape::Scalar),
+                ("name", Shape::Scalar),
+                ("tags", Shape::list(Shape::Scalar)),
+                ("extra", Shape::Any),
+            ]))),
+            ("footer", Shape::Component),
+        ]);
+
+        let tree = TreeNode::parse(
+            "h1[title=@title]>(li[id=@items%id]>{@items%name @items%extra%x}+(i>{@items%tags})*@items%tags)*@items+$footer",
+        ).unwrap();
+        assert_eq!(shape.check(&tree), []);
+
+        let tree = TreeNode::parse(
+            "h1>{@items%name @title%x}+(li>{@itmes%name}+$items%name+{@footer})*@items+div*@title",
+        ).unwrap();
+        let errors = shape.check(&tree).iter().map(|e| e.to_string()).collect::<Vec<String>>();
+        assert_eq!(errors, [
+            "Binding @items%name can't access field `name` of list",
+            "Binding @title%x can't access field `x` of scalar",
+            "Binding @itmes%name refers to undeclared field `itmes`",
+            "Binding @items%name is used as subtree, but declared as scalar",
+            "Binding @footer is used as text, but declared as component",
+            "Binding @title is used as collection, but declared as scalar",
+        ]);
+
+        let tree = TreeNode::parse(
+            "(li[id=@item%id]>{@index @item%name}+(i[even=@even]>{@tag @item%nam})*@item%tags as tag)*@items as item+{@index}",
+        ).unwrap();
+        let errors = shape.check(&tree).iter().map(|e| e.to_string()).collect::<Vec<String>>();
+        assert_eq!(errors, [
+            "Binding @item%nam refers to undeclared field `nam`",
+            "Binding @index refers to undeclared field `index`",
+        ]);
+
+        // Declared field shadows the loop variable
+        let shape = Shape::object([("first", Shape::list(Shape::Scalar)), ("items", Shape::list(Shape::Scalar))]);
+        let tree = TreeNode::parse("(li>{@first @last})*@items").unwrap();
+        let errors = shape.check(&tree).iter().map(|e| e.to_string()).collect::<Vec<String>>();
+        assert_eq!(errors, ["Binding @first is used as text, but declared as list"]);
+    }
+}
@@ -0,0 +1,555 @@
+use crate::escape::{escape, write_escaped, EscapeContext};
+use crate::eval::{resolve_collection, resolve_component, resolve_scalar, EvalError};
+use crate::html::is_void_element;
+use crate::render::{IoAdapter, Layout, RenderError, RenderOptions};
+use crate::sanitize::{static_attribute_value, SanitizePolicy, SanitizeViolation, Sanitized};
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, ErrorTreeNode, GenericValue, InnerContentTreeNode,
+    NormalTreeNode, ParseError, PropertyBinding, RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+use crate::value::{Scope, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Instruction {
+    // Static HTML chunk
+    Static(String),
+    // Scalar value slot, escaped according to its position in the document
+    Value { binding: PropertyBinding, context: EscapeContext },
+    // Attribute with bindings, which is dropped if all of them are null and could be checked by
+    // the sanitization policy only during rendering; `separator` precedes it in the opening tag
+    Attribute { attribute: Attribute, separator: String },
+    // Component tree slot with the layout of its position
+    Subtree { binding: PropertyBinding, layout: Layout },
+    // Start of the collection loop body; `end` is index of the matching `EndCollection`,
+    // `item_path` is the binding path referring to the current item
+    BeginCollection { collection: PropertyBinding, item_path: Vec<String>, end: usize },
+    // End of the collection loop body; `begin` is index of the matching `BeginCollection`
+    EndCollection { begin: usize },
+    // Invalid expression part, which fails the rendering
+    Error(ErrorTreeNode),
+}
+
+// Template compiled into the flat instruction list, which could be rendered multiple times
+// with the different data without walking the tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    instructions: Vec<Instruction>,
+    policy: Option<SanitizePolicy>,
+    options: RenderOptions,
+}
+
+// Components compiled during the render, so every component is compiled once, no matter how many
+// times it is rendered. Components are identified by their tree in the rendered data and layout.
+#[derive(Default)]
+struct Components<'a> {
+    compiled: RefCell<HashMap<(*const TreeNode, Layout), Rc<Template>>>,
+    data: PhantomData<&'a TreeNode>,
+}
+
+impl<'a> Components<'a> {
+    fn compile(&self, tree: &'a TreeNode, layout: Layout, parent: &Template) -> Result<Rc<Template>, SanitizeViolation> {
+        if let Some(compiled) = self.compiled.borrow().get(&(tree as *const TreeNode, layout)) {
+            return Ok(compiled.clone());
+        }
+        let compiled = Template::compile_at(tree, parent.options.clone(), parent.policy.clone(), layout)?;
+        let compiled = Rc::new(compiled);
+        self.compiled.borrow_mut().insert((tree, layout), compiled.clone());
+        Ok(compiled)
+    }
+}
+
+struct CollectionLoop<'a> {
+    begin: usize,
+    item_path: &'a [String],
+    items: &'a [Value],
+    index: usize,
+}
+
+impl Template {
+    pub fn parse(input: &str) -> Result<Template, ParseError> {
+        Ok(Template::compile(&TreeNode::parse(input)?))
+    }
+
+    pub fn compile(tree: &TreeNode) -> Template {
+        Template::compile_with(tree, RenderOptions::default(), None)
+            .expect("Template without policy can't have violations")
+    }
+
+    // Compiles template which applies sanitization policy to the rendered attributes. Static
+    // attributes are checked during compilation, bound ones - during rendering.
+    pub fn compile_with_policy(tree: &TreeNode, policy: SanitizePolicy) -> Result<Template, SanitizeViolation> {
+        Template::compile_with(tree, RenderOptions::default(), Some(policy))
+    }
+
+    // Compiles template with the given output layout and optional sanitization policy. Layout
+    // is decided during compilation: component subtrees are always placed on separate lines, and
+    // attribute wrapping only takes static attribute values into account.
+    pub fn compile_with(
+        tree: &TreeNode,
+        options: RenderOptions,
+        policy: Option<SanitizePolicy>,
+    ) -> Result<Template, SanitizeViolation> {
+        let layout = options.root_layout();
+        Template::compile_at(tree, options, policy, layout)
+    }
+
+    fn compile_at(
+        tree: &TreeNode,
+        options: RenderOptions,
+        policy: Option<SanitizePolicy>,
+        layout: Layout,
+    ) -> Result<Template, SanitizeViolation> {
+        let mut template = Template { instructions: vec![], policy, options };
+        template.compile_node(tree, None, layout)?;
+        Ok(template)
+    }
+
+    pub fn render(&self, data: &Value) -> Result<String, RenderError> {
+        let mut out = String::new();
+        self.render_to(&mut out, data)?;
+        Ok(out)
+    }
+
+    pub fn render_to(&self, out: &mut impl Write, data: &Value) -> Result<(), RenderError> {
+        self.render_in_scope(out, &mut Scope::new(data), &Components::default())
+    }
+
+    pub fn render_to_io(&self, out: &mut impl std::io::Write, data: &Value) -> Result<(), RenderError> {
+        let mut adapter = IoAdapter::new(out);
+        let result = self.render_to(&mut adapter, data);
+        adapter.finish(result)
+    }
+
+    fn render_in_scope<'a>(
+        &'a self,
+        out: &mut impl Write,
+        scope: &mut Scope<'a>,
+        components: &Components<'a>,
+    ) -> Result<(), RenderError> {
+        let mut loops: Vec<CollectionLoop> = vec![];
+        let mut ip = 0;
+
+        while let Some(instruction) = self.instructions.get(ip) {
+            match instruction {
+                Instruction::Static(s) => out.write_str(s)?,
+                Instruction::Value { binding, context } => match &*resolve_scalar(scope, binding)? {
+                    Value::Text(s) => write_escaped(out, s, *context)?,
+                    Value::SafeHtml(s) if matches!(context, EscapeContext::Text | EscapeContext::RawText) => {
+                        out.write_str(s.as_str())?
+                    },
+                    Value::SafeHtml(s) => write_escaped(out, s.as_str(), *context)?,
+                    value => write!(out, "{}", value)?,
+                },
+                Instruction::Attribute { attribute, separator } => {
+                    self.render_attribute(attribute, separator, out, scope)?;
+                },
+                Instruction::Error(node) => return Err(EvalError::from(node).into()),
+                Instruction::Subtree { binding, layout } => {
+                    let component = resolve_component(scope, binding)?;
+                    let component = components.compile(component, *layout, self)?;
+                    component.render_in_scope(out, &mut Scope::nested(scope), components)?;
+                },
+                Instruction::BeginCollection { collection, item_path, end } => {
+                    let items = resolve_collection(scope, collection)?;
+                    match items.first() {
+                        Some(item) => {
+                            scope.push_item(item_path, item, 0, items.len());
+                            loops.push(CollectionLoop { begin: ip, item_path, items, index: 0 });
+                        },
+                        None => {
+                            ip = *end;
+                        },
+                    }
+                },
+                Instruction::EndCollection { begin } => {
+                    let current = loops.last_mut().expect("Collection loop is not started");
+                    assert_eq!(current.begin, *begin);
+                    scope.pop_item();
+                    current.index += 1;
+                    if let Some(item) = current.items.get(current.index) {
+                        scope.push_item(current.item_path, item, current.index, current.items.len());
+                        ip = *begin;
+                    } else {
+                        loops.pop();
+                    }
+                },
+            }
+            ip += 1;
+        }
+
+        Ok(())
+    }
+
+    fn render_attribute(
+        &self,
+        attribute: &Attribute,
+        separator: &str,
+        out: &mut impl Write,
+        scope: &Scope,
+    ) -> Result<(), RenderError> {
+        let values = match &attribute.value {
+            AttributeValue::None => &[],
+            AttributeValue::Single(value) => std::slice::from_ref(value),
+            AttributeValue::Multiple(values) => values.as_slice(),
+        };
+
+        let mut text = vec![];
+        for value in values {
+            match value {
+                GenericValue::Binding(binding) => match &*resolve_scalar(scope, binding)? {
+                    Value::Null => {},
+                    value => text.push(value.to_string()),
+                },
+                value => text.extend(value.to_text()),
+            }
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+        let text = text.join(" ");
+
+        let text = match &self.policy {
+            Some(policy) => match policy.sanitize_attribute(&attribute.name, Some(&text), true)? {
+                Sanitized::Keep => text.as_str(),
+                Sanitized::Drop => return Ok(()),
+                Sanitized::Replace(replacement) => replacement,
+            },
+            None => text.as_str(),
+        };
+
+        write!(out, "{}{}=\"", separator, attribute.name)?;
+        write_escaped(out, text, EscapeContext::for_attribute(&attribute.name))?;
+        out.write_char('"')?;
+        Ok(())
+    }
+
+    fn push_static(&mut self, s: &str) {
+        if let Some(Instruction::Static(last)) = self.instructions.last_mut() {
+            last.push_str(s);
+        } else {
+            self.instructions.push(Instruction::Static(s.to_owned()));
+        }
+    }
+
+    fn push_value(&mut self, value: &GenericValue, context: EscapeContext) {
+        match value {
+            GenericValue::Text(s) => self.push_static(&escape(s, context)),
+            GenericValue::Binding(binding) => {
+                self.instructions.push(Instruction::Value { binding: binding.clone(), context });
+            },
+            value => self.push_static(&value.to_text().unwrap_or_default()),
+        }
+    }
+
+    fn push_indent(&mut self, depth: usize) {
+        let mut indent = String::new();
+        self.options.write_indent(&mut indent, depth).expect("Write to string can't fail");
+        self.push_static(&indent);
+    }
+
+    fn push_line_ending(&mut self) {
+        self.push_static(self.options.line_ending());
+    }
+
+    fn compile_attribute(&mut self, attribute: &Attribute, separator: &str) -> Result<(), SanitizeViolation> {
+        let (value, bound) = static_attribute_value(attribute);
+        if let Some(policy) = &self.policy {
+            match policy.sanitize_attribute(&attribute.name, value.as_deref(), bound)? {
+                Sanitized::Keep => {},
+                Sanitized::Drop => return Ok(()),
+                Sanitized::Replace(replacement) => {
+                    let replacement = escape(replacement, EscapeContext::Attribute);
+                    self.push_static(&format!("{}{}=\"{}\"", separator, attribute.name, replacement));
+                    return Ok(());
+                },
+            }
+        }
+        if bound {
+            // Values bound to null are dropped and URL is checked by the policy, so the attribute
+            // is known only during rendering
+            self.instructions.push(Instruction::Attribute {
+                attribute: attribute.clone(),
+                separator: separator.to_owned(),
+            });
+            return Ok(());
+        }
+
+        self.push_static(&format!("{}{}", separator, attribute.name));
+        if let Some(value) = value {
+            let value = escape(&value, EscapeContext::for_attribute(&attribute.name));
+            self.push_static(&format!("=\"{}\"", value));
+        }
+        Ok(())
+    }
+
+    // Separator placed before each attribute of the opening tag, wrapping attributes to the
+    // separate lines if the tag is too long. Bound values are not known yet, so they are not
+    // counted in the tag length.
+    fn attribute_separator(&self, name: &str, attributes: &[Attribute], depth: usize) -> String {
+        let tag_length = name.chars().count() + 2 + attributes.iter().map(|attribute| {
+            let (value, _) = static_attribute_value(attribute);
+            let value_length = value.map_or(0, |v| v.chars().count() + 3);
+            attribute.name.chars().count() + 1 + value_length
+        }).sum::<usize>();
+
+        if !self.options.wraps_attributes(depth, tag_length, attributes.len()) {
+            return " ".to_owned();
+        }
+        let mut separator = self.options.line_ending().to_owned();
+        self.options.write_indent(&mut separator, depth + 1).expect("Write to string can't fail");
+        separator
+    }
+
+    // `parent` is the name of the closest element, which defines how text content is escaped
+    fn compile_node(&mut self, node: &TreeNode, parent: Option<&str>, layout: Layout) -> Result<(), SanitizeViolation> {
+        match node {
+            TreeNode::Root(RootTreeNode { children }) => {
+                let children_layout = self.options.children_layout(children, layout.depth, layout.block);
+                for (child, layout) in children.iter().zip(children_layout) {
+                    self.compile_node(child, parent, layout)?;
+                }
+                return Ok(());
+            },
+            TreeNode::Collection(node @ CollectionTreeNode { nodes, collection, .. }) => {
+                // Collection on the shared line is surrounded by its line breaks, block collection
+                // lays out its nodes as if they were placed instead of it
+                if layout.line_start && !layout.block {
+                    self.push_indent(layout.depth);
+                }
+                let begin = self.instructions.len();
+                self.instructions.push(Instruction::BeginCollection {
+                    collection: collection.clone(),
+                    item_path: node.item_path().to_vec(),
+                    end: 0,
+                });
+                let nodes_layout = self.options.children_layout(nodes, layout.depth, layout.block);
+                for (node, layout) in nodes.iter().zip(nodes_layout) {
+                    self.compile_node(node, parent, layout)?;
+                }
+                let end = self.instructions.len();
+                self.instructions.push(Instruction::EndCollection { begin });
+                if let Instruction::BeginCollection { end: begin_end, .. } = &mut self.instructions[begin] {
+                    *begin_end = end;
+                }
+                if layout.line_end && !layout.block {
+                    self.push_line_ending();
+                }
+                return Ok(());
+            },
+            TreeNode::Subtree(SubtreeTreeNode { property }) => {
+                self.instructions.push(Instruction::Subtree { binding: property.clone(), layout });
+                return Ok(());
+            },
+            TreeNode::Error(node) => {
+                self.instructions.push(Instruction::Error(node.clone()));
+                return Ok(());
+            },
+            _ => {},
+        }
+
+        if layout.line_start {
+            self.push_indent(layout.depth);
+        }
+        match node {
+            TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+                self.push_static(&format!("<{}", name));
+                let separator = if layout.block {
+                    self.attribute_separator(name, attributes, layout.depth)
+                } else {
+                    " ".to_owned()
+                };
+                for attribute in attributes {
+                    self.compile_attribute(attribute, &separator)?;
+                }
+                if is_void_element(name) {
+                    self.push_static(">");
+                    if layout.line_end {
+                        self.push_line_ending();
+                    }
+                    return Ok(());
+                }
+                let multiline = layout.block && !children.is_empty() && self.options.is_multiline(name, children);
+                self.push_static(">");
+                if multiline {
+                    self.push_line_ending();
+                }
+                let children_layout = self.options.children_layout(children, layout.depth + 1, multiline);
+                for (child, layout) in children.iter().zip(children_layout) {
+                    self.compile_node(child, Some(name), layout)?;
+                }
+                if multiline {
+                    self.push_indent(layout.depth);
+                }
+                self.push_static(&format!("</{}>", name));
+            },
+            TreeNode::InnerContent(InnerContentTreeNode { value, raw }) => {
+                let context = if *raw {
+                    EscapeContext::Trusted
+                } else {
+                    EscapeContext::for_text(parent)
+                };
+                self.push_value(value, context);
+            },
+            _ => unreachable!("Only elements and text are placed on the line"),
+        }
+        if layout.line_end {
+            self.push_line_ending();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escape::SafeHtml;
+    use crate::eval::evaluate;
+    use crate::render::{render_html, Indent, Markup, Renderer};
+    use expect_test::expect;
+
+    fn data() -> Value {
+        Value::object([
+            ("title", Value::from("Items")),
+            ("items", Value::from(vec![
+                Value::object([
+                    ("id", Value::from(1)),
+                    ("name", Value::from("one")),
+                    ("tags", Value::from(vec!["a", "b"])),
+                ]),
+                Value::object([
+                    ("id", Value::from(2)),
+                    ("name", Value::from("two")),
+                    ("tags", Value::from(Vec::<Value>::new())),
+                ]),
+            ])),
+            ("empty", Value::List(vec![])),
+            ("null", Value::Null),
+            ("url", Value::from("/a b?c=\"d\"")),
+            ("html", Value::from(SafeHtml::new("<b>bold</b>"))),
+            ("text", Value::from("</script> & <b>")),
+            ("footer", TreeNode::parse("p>{@title}").unwrap().into()),
+            ("row", TreeNode::parse("li[n=@index]>{@items%name}+$footer").unwrap().into()),
+        ])
+    }
+
+    #[test]
+    fn render_template() {
+        let template = Template::parse(
+            "h1>{@title}+ul>(li#item[class=@items%name n=@items%id]>{@items%name}+(i>{@items%tags})*@items%tags)*@items+$footer",
+        ).unwrap();
+
+        expect![[r#"<h1>Items<ul><li id="item" class="one" n="1">one<i>a</i><i>b</i></li><li id="item" class="two" n="2">two</li><p>Items</p></ul></h1>"#]]
+            .assert_eq(&template.render(&data()).unwrap());
+    }
+
+    #[test]
+    fn same_as_tree_walk() {
+        let inputs = [
+            "div#button1.btn.alert[onclick='press_callback()']>b>{text}",
+            "ul>(li[id=@items%id]>div.list_icon+{My name is @items%name})*@items",
+            "(ul>li*3)+ol>(li>b>{hello})*2+$footer",
+            "div*@empty+span",
+            "a[href=@url title=@html]>{@html}+!{@text}+script>{@text}",
+            "p>{line}+br+img[src=@url]>{ignored}",
+            "ul>(li[n=@index]>{@item%name}+(i[last=@last]>{@tag @item%id})*@item%tags as tag)*@items as item",
+            "ul>($row+b>{@index})*@items",
+            "a[title=@null]+a[title=@null title=@url]+a[title=@url title=@null]+a[title=@null title='x' disabled]",
+            "input[value=@null checked]+a[href=@url rel=@null]",
+        ];
+
+        let data = data();
+        for input in inputs {
+            let tree = TreeNode::parse(input).unwrap();
+            assert_eq!(
+                Template::compile(&tree).render(&data).unwrap(),
+                render_html(&evaluate(&tree, &data).unwrap()).unwrap(),
+                "{}",
+                input
+            );
+
+            let options = RenderOptions::pretty();
+            assert_eq!(
+                Template::compile_with(&tree, options.clone(), None).unwrap().render(&data).unwrap(),
+                Renderer::new(Markup::Html).with_options(options).render(&evaluate(&tree, &data).unwrap()).unwrap(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn recursive_component() {
+        let data = Value::object([("footer", TreeNode::parse("p+$footer").unwrap())]);
+        let error = Template::parse("div>$footer").unwrap().render(&data).unwrap_err();
+        assert_eq!(error.to_string(), "Component @footer is nested too deep, it is probably recursive");
+    }
+
+    #[test]
+    fn pretty_print() {
+        let template = Template::compile_with(
+            &TreeNode::parse("div>(ul>(li>{@items%name})*@items)+$footer").unwrap(),
+            RenderOptions::pretty().with_indent(Indent::Tabs),
+            None,
+        ).unwrap();
+        expect![[r#"
+            <div>
+            	<ul>
+            		<li>one</li>
+            		<li>two</li>
+            	</ul>
+            	<p>Items</p>
+            </div>
+        "#]].assert_eq(&template.render(&data()).unwrap());
+
+        // Bound values are not known during compilation, so only static ones are measured
+        let template = Template::compile_with(
+            &TreeNode::parse("div>section[title=@url]+form[action=@url class='long class name']").unwrap(),
+            RenderOptions::pretty().with_max_line_width(30),
+            None,
+        ).unwrap();
+        expect![[r#"
+            <div>
+              <section title="/a b?c=&quot;d&quot;"></section>
+              <form
+                action="/a%20b?c=%22d%22"
+                class="long class name"></form>
+            </div>
+        "#]].assert_eq(&template.render(&data()).unwrap());
+    }
+
+    #[test]
+    fn sanitization() {
+        let tree = TreeNode::parse(
+            "a[href=@url onclick='press()' onmouseover=@handler]+img[src='javascript:alert(1)']+$footer",
+        ).unwrap();
+        let data = Value::object([
+            ("url", Value::from("javascript:alert(1)")),
+            ("handler", Value::from("alert(1)")),
+            ("footer", TreeNode::parse("a[href='https://example.com' style=x]").unwrap().into()),
+        ]);
+
+        let template = Template::compile_with_policy(&tree, SanitizePolicy::default()).unwrap();
+        expect![[r#"<a href="about:invalid#utree-sanitized" onclick="press()"></a><img src="about:invalid#utree-sanitized"><a href="https://example.com" style="x"></a>"#]]
+            .assert_eq(&template.render(&data).unwrap());
+
+        let policy = SanitizePolicy::strict().allow_attribute("on*").allow_bound_event_handlers(true);
+        let template = Template::compile_with_policy(&TreeNode::parse("a[href=@url onclick=@handler]").unwrap(), policy).unwrap();
+        assert_eq!(
+            template.render(&data).unwrap_err().to_string(),
+            "Attribute `href` contains URL with disallowed scheme: `javascript:alert(1)`"
+        );
+        assert_eq!(
+            template.render(&Value::object([("url", "/a b"), ("handler", "go()")])).unwrap(),
+            r#"<a href="/a%20b" onclick="go()"></a>"#
+        );
+
+        let error = Template::compile_with_policy(&tree, SanitizePolicy::strict()).unwrap_err();
+        assert_eq!(error, SanitizeViolation::UnknownAttribute("onclick".to_owned()));
+    }
+}
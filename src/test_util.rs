@@ -0,0 +1,18 @@
+// Helpers shared by the tests of different modules
+
+// Deterministic xorshift generator, so failures of the randomized tests can be reproduced
+pub(crate) struct Random(u64);
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    // Random number in `0..bound`
+    pub(crate) fn next(&mut self, bound: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % bound as u64) as usize
+    }
+}
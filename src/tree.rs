@@ -1,10 +1,16 @@
 use thiserror::Error;
 use pest::iterators::{Pairs, Pair};
-use crate::parser::Rule;
+use crate::definition::{DefinitionError, DefinitionScope};
+use crate::lorem::{Lorem, LoremAmount, DEFAULT_WORDS, MAX_COUNT};
+use crate::parser::{Rule, SyntaxError, SyntaxParser};
+use crate::recover::ParseDiagnostic;
+use pest::Parser;
+use std::cmp::Ordering;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum PropertyBinding {
     RootIdentifier(String),
     NestedIdentifier(Vec<String>),
@@ -19,10 +25,94 @@ impl Display for PropertyBinding {
     }
 }
 
-#[derive(Debug, Clone)]
+impl PropertyBinding {
+    // Path components of the binding, e.g. ["items", "name"] for @items%name; `None` if there are
+    // no components or some of them are empty
+    pub fn from_parts(mut parts: Vec<String>) -> Option<Self> {
+        if parts.iter().any(String::is_empty) {
+            return None;
+        }
+        match parts.len() {
+            0 => None,
+            1 => parts.pop().map(PropertyBinding::RootIdentifier),
+            _ => Some(PropertyBinding::NestedIdentifier(parts)),
+        }
+    }
+
+    pub fn parts(&self) -> &[String] {
+        match self {
+            PropertyBinding::RootIdentifier(ident) => std::slice::from_ref(ident),
+            PropertyBinding::NestedIdentifier(idents) => idents,
+        }
+    }
+}
+
+impl From<&str> for PropertyBinding {
+    // Creates binding from `%`-separated path, e.g. "items%name"
+    // Panics if the path has empty components, e.g. "" or "items%", see `builder::binding`
+    fn from(path: &str) -> Self {
+        match PropertyBinding::from_parts(path.split('%').map(str::to_owned).collect()) {
+            Some(binding) => binding,
+            None => panic!("Invalid binding path `{}`", path),
+        }
+    }
+}
+
+// Floating-point literal. Values are compared by their bit representation (see `f64::total_cmp`),
+// so trees containing floats still could be compared, ordered and hashed.
+#[derive(Debug, Clone, Copy)]
+pub struct Float(pub f64);
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for Float {}
+
+impl std::hash::Hash for Float {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Display for Float {
+    // Debug representation keeps the fractional part of the whole numbers (`1.0` instead of `1`)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl Float {
+    // Text for the rendered output, whole numbers don't have the fractional part (`1e3` is `1000`)
+    pub fn to_text(self) -> String {
+        let text = format!("{:?}", self.0);
+        match text.strip_suffix(".0") {
+            Some(whole) => whole.to_owned(),
+            None => text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum GenericValue {
     Text(String),
     Number(isize),
+    Float(Float),
+    Bool(bool),
     Binding(PropertyBinding),
 }
 
@@ -31,12 +121,69 @@ impl Display for GenericValue {
         match self {
             GenericValue::Text(s) => write!(f, "\"{}\"", s),
             GenericValue::Number(n) => write!(f, "{}", n),
+            GenericValue::Float(n) => write!(f, "{}", n),
+            GenericValue::Bool(b) => write!(f, "{}", b),
             GenericValue::Binding(b) => write!(f, "{}", b),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl GenericValue {
+    // Text representation of the literal value, `None` for bindings
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            GenericValue::Text(s) => Some(s.clone()),
+            GenericValue::Number(n) => Some(n.to_string()),
+            GenericValue::Float(n) => Some(n.to_text()),
+            GenericValue::Bool(b) => Some(b.to_string()),
+            GenericValue::Binding(_) => None,
+        }
+    }
+}
+
+impl From<&str> for GenericValue {
+    fn from(value: &str) -> Self {
+        GenericValue::Text(value.to_owned())
+    }
+}
+
+impl From<String> for GenericValue {
+    fn from(value: String) -> Self {
+        GenericValue::Text(value)
+    }
+}
+
+impl From<isize> for GenericValue {
+    fn from(value: isize) -> Self {
+        GenericValue::Number(value)
+    }
+}
+
+impl From<i32> for GenericValue {
+    fn from(value: i32) -> Self {
+        GenericValue::Number(value as isize)
+    }
+}
+
+impl From<f64> for GenericValue {
+    fn from(value: f64) -> Self {
+        GenericValue::Float(Float(value))
+    }
+}
+
+impl From<bool> for GenericValue {
+    fn from(value: bool) -> Self {
+        GenericValue::Bool(value)
+    }
+}
+
+impl From<PropertyBinding> for GenericValue {
+    fn from(value: PropertyBinding) -> Self {
+        GenericValue::Binding(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum AttributeValue {
     None,
     Single(GenericValue),
@@ -74,10 +221,41 @@ impl AttributeValue {
     }
 }
 
+// Attributes are compared by name and value only, `bound` doesn't change the rendered attribute
 #[derive(Debug, Clone)]
 pub struct Attribute {
     pub name: String,
     pub value: AttributeValue,
+    // Value of the evaluated attribute came from the data, so the sanitization policy treats it as
+    // bound (see `eval::evaluate`); always false in the parsed tree
+    pub bound: bool,
+}
+
+impl PartialEq for Attribute {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.name, &self.value) == (&other.name, &other.value)
+    }
+}
+
+impl Eq for Attribute {}
+
+impl Hash for Attribute {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.value.hash(state);
+    }
+}
+
+impl PartialOrd for Attribute {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Attribute {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.name, &self.value).cmp(&(&other.name, &other.value))
+    }
 }
 
 impl Display for Attribute {
@@ -88,18 +266,70 @@ impl Display for Attribute {
 
 #[derive(Debug, Error)]
 pub enum TreeBuildError {
-    #[error("Leaf node can't have any children")]
-    LeafNodeCantHaveChildren,
-    #[error("Invalid number")]
-    InvalidNumLiteral,
+    // Location is known for the nodes of the parsed expression only
+    #[error("Leaf node can't have any children{}", location_suffix(.location))]
+    LeafNodeCantHaveChildren { location: Option<(usize, usize)> },
+    #[error("Invalid number literal `{literal}` at {line}:{column}")]
+    InvalidNumLiteral { literal: String, line: usize, column: usize },
+    #[error("{error} at {line}:{column}")]
+    Definition { error: DefinitionError, line: usize, column: usize },
 }
 
-#[derive(Debug, Clone)]
+fn location_suffix(location: &Option<(usize, usize)>) -> String {
+    match location {
+        Some((line, column)) => format!(" at {}:{}", line, column),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error(transparent)]
+    Syntax(#[from] SyntaxError),
+    #[error(transparent)]
+    Build(#[from] TreeBuildError),
+}
+
+impl ParseError {
+    // Line and column (1-based, in characters) where the error starts, if known
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::Syntax(e) => Some(e.location()),
+            ParseError::Build(TreeBuildError::LeafNodeCantHaveChildren { location }) => *location,
+            ParseError::Build(TreeBuildError::InvalidNumLiteral { line, column, .. })
+            | ParseError::Build(TreeBuildError::Definition { line, column, .. }) => Some((*line, *column)),
+        }
+    }
+
+    // Error description without location
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::Syntax(e) => e.message(),
+            ParseError::Build(TreeBuildError::LeafNodeCantHaveChildren { .. }) => {
+                TreeBuildError::LeafNodeCantHaveChildren { location: None }.to_string()
+            },
+            ParseError::Build(TreeBuildError::InvalidNumLiteral { literal, .. }) => {
+                format!("Invalid number literal `{}`", literal)
+            },
+            ParseError::Build(TreeBuildError::Definition { error, .. }) => error.to_string(),
+        }
+    }
+
+    // Suggestion how to fix the error
+    pub fn hint(&self) -> Option<&str> {
+        match self {
+            ParseError::Syntax(e) => e.hint(),
+            ParseError::Build(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct RootTreeNode {
     pub children: Vec<TreeNode>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct NormalTreeNode {
     pub name: String,
     pub attributes: Vec<Attribute>,
@@ -107,35 +337,118 @@ pub struct NormalTreeNode {
 }
 
 impl NormalTreeNode {
+    // Adds valueless attribute, unless attribute with such name is already present
+    pub fn append_flag(&mut self, name: &str) {
+        if !self.attributes.iter().any(|a| a.name == name) {
+            self.attributes.push(Attribute { name: name.to_owned(), value: AttributeValue::None, bound: false });
+        }
+    }
+
     pub fn append_attribute(&mut self, name: &str, value: GenericValue) {
         if let Some(existing) = self.attributes.iter_mut().find(|a| a.name == name) {
             existing.value.append(value);
         } else {
             self.attributes.push(Attribute {
                 name: name.to_owned(),
-                value: AttributeValue::Single(value)
+                value: AttributeValue::Single(value),
+                bound: false,
             })
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct InnerContentTreeNode {
     pub value: GenericValue,
+    // Content is trusted markup and should be rendered without escaping
+    pub raw: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct SubtreeTreeNode {
     pub property: PropertyBinding,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct CollectionTreeNode {
     pub nodes: Vec<TreeNode>,
     pub collection: PropertyBinding,
+    // Loop variable name (`* @items as item`), which refers to the current item
+    pub alias: Option<String>,
+    // Binding identifying the item between renders (`* @items key @items%id`), resolved against
+    // the current item
+    pub key: Option<PropertyBinding>,
 }
 
-#[derive(Debug, Clone, from_variants::FromVariants)]
+impl CollectionTreeNode {
+    // Binding path prefix which refers to the current collection item
+    pub fn item_path(&self) -> &[String] {
+        item_path(&self.collection, &self.alias)
+    }
+}
+
+// Collection loop enclosing the binding
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct LoopScope {
+    pub collection: PropertyBinding,
+    pub alias: Option<String>,
+}
+
+impl LoopScope {
+    pub fn item_path(&self) -> &[String] {
+        item_path(&self.collection, &self.alias)
+    }
+}
+
+fn item_path<'a>(collection: &'a PropertyBinding, alias: &'a Option<String>) -> &'a [String] {
+    match alias {
+        Some(alias) => std::slice::from_ref(alias),
+        None => collection.parts(),
+    }
+}
+
+// Position where the binding is used in the template
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum BindingRole {
+    Text,
+    Attribute(String),
+    // Multiplier of the collection node
+    Collection,
+    // Item key of the collection node
+    Key,
+    Subtree,
+}
+
+impl Display for BindingRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingRole::Text => f.write_str("text"),
+            BindingRole::Attribute(name) => write!(f, "attribute `{}`", name),
+            BindingRole::Collection => f.write_str("collection"),
+            BindingRole::Key => f.write_str("collection key"),
+            BindingRole::Subtree => f.write_str("subtree"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BindingUsage {
+    pub binding: PropertyBinding,
+    pub role: BindingRole,
+    // Collections enclosing the usage, outermost first; binding is resolved against their items
+    pub collections: Vec<LoopScope>,
+}
+
+// Part of the expression which failed to parse (see `TreeNode::parse_recovering`). Nodes
+// following the `>` operator after the invalid part are kept as its children.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ErrorTreeNode {
+    pub source: String,
+    pub message: String,
+    pub children: Vec<TreeNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, from_variants::FromVariants)]
 pub enum TreeNode {
     // Pseudo-node to hold all tree
     Root(RootTreeNode),
@@ -146,159 +459,343 @@ pub enum TreeNode {
     // Subtree bound to the given property
     Subtree(SubtreeTreeNode),
     // Special kind of tree node depresenting highly coupled nodes list (usually, created when bound to collection property)
-    Collection(CollectionTreeNode)
+    Collection(CollectionTreeNode),
+    // Invalid expression part, produced only by the recovering parser
+    Error(ErrorTreeNode),
 }
 
 impl Display for TreeNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut result = String::new();
-        self.as_string_impl(&mut result, "");
-        f.write_str(&result)
+        self.write_debug_tree(f)
     }
 }
 
+// expect-test strips the common indentation of the snapshots, including the leading space of
+// every debug tree line, so it's stripped from the compared tree as well
+#[cfg(test)]
+pub(crate) fn snapshot(node: &TreeNode) -> String {
+    node.to_string()
+        .split_inclusive('\n')
+        .map(|line| match line.trim_start_matches(' ').starts_with("- ") {
+            true => &line[1..],
+            false => line,
+        })
+        .collect()
+}
+
 impl TreeNode {
     pub fn children_mut(&mut self) -> Result<&mut Vec<TreeNode>, TreeBuildError> {
         match self {
             TreeNode::Root(n) => Ok(&mut n.children),
             TreeNode::Normal(n) => Ok(&mut n.children),
-            _ => Err(TreeBuildError::LeafNodeCantHaveChildren),
+            TreeNode::Error(n) => Ok(&mut n.children),
+            _ => Err(TreeBuildError::LeafNodeCantHaveChildren { location: None }),
         }
     }
 
-    fn as_string_impl(&self, result: &mut String, ident: &str) {
+    // Nested nodes, including collection nodes
+    pub fn children(&self) -> &[TreeNode] {
         match self {
-            TreeNode::Root(RootTreeNode { children }) => {
-                // Do not display root node
-                for child in children {
-                    child.as_string_impl(result, "");
-                }
-            },
-            TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
-                let mut node = format!("{} - {}", ident, name.clone());
-                if !attributes.is_empty() {
-                    let attrs = attributes
-                        .iter()
-                        .map(|a| format!("{}={}", a.name, a.value))
-                        .collect::<Vec<String>>().join(" ");
-                    node.push_str(&format!("[{}]", attrs))
-                }
-                node.push('\n');
-                result.push_str(&node);
-                for child in children {
-                    child.as_string_impl(result, &format!("{}  ", ident));
-                }
-            },
-            TreeNode::InnerContent(InnerContentTreeNode { value }) => {
-                result.push_str(&format!("{} - [CONTENT] {}\n", ident, value));
-            },
-            TreeNode::Subtree(SubtreeTreeNode { property }) => {
-                result.push_str(&format!("{} - [SUBTREE] {}\n", ident, property));
-            },
-            TreeNode::Collection(CollectionTreeNode { nodes, collection }) => {
-                result.push_str(&format!("{} - [COLLECTION] {}\n", ident, collection));
-                for node in nodes {
-                    node.as_string_impl(result, &format!("{}  ", ident));
+            TreeNode::Root(n) => &n.children,
+            TreeNode::Normal(n) => &n.children,
+            TreeNode::Collection(n) => &n.nodes,
+            TreeNode::Error(n) => &n.children,
+            _ => &[],
+        }
+    }
+
+    // Writes debug tree representation. Tree is walked iteratively, so deeply nested trees
+    // can't overflow the stack.
+    pub fn write_debug_tree(&self, out: &mut impl std::fmt::Write) -> std::fmt::Result {
+        // Root node is not displayed, so its children start at zero depth
+        let mut stack = match self {
+            TreeNode::Root(RootTreeNode { children }) => children.iter().rev().map(|c| (c, 0)).collect(),
+            node => vec![(node, 0)],
+        };
+
+        while let Some((node, depth)) = stack.pop() {
+            for _ in 0..depth {
+                out.write_str("  ")?;
+            }
+            match node {
+                TreeNode::Root(_) => out.write_str(" - [ROOT]")?,
+                TreeNode::Normal(NormalTreeNode { name, attributes, .. }) => {
+                    write!(out, " - {}", name)?;
+                    for (i, attribute) in attributes.iter().enumerate() {
+                        out.write_str(if i == 0 { "[" } else { " " })?;
+                        write!(out, "{}", attribute)?;
+                    }
+                    if !attributes.is_empty() {
+                        out.write_str("]")?;
+                    }
+                },
+                TreeNode::InnerContent(InnerContentTreeNode { value, raw: false }) => {
+                    write!(out, " - [CONTENT] {}", value)?;
+                },
+                TreeNode::InnerContent(InnerContentTreeNode { value, raw: true }) => {
+                    write!(out, " - [RAW] {}", value)?;
+                },
+                TreeNode::Subtree(SubtreeTreeNode { property }) => {
+                    write!(out, " - [SUBTREE] {}", property)?;
+                },
+                TreeNode::Collection(CollectionTreeNode { collection, alias, key, .. }) => {
+                    write!(out, " - [COLLECTION] {}", collection)?;
+                    if let Some(alias) = alias {
+                        write!(out, " as {}", alias)?;
+                    }
+                    if let Some(key) = key {
+                        write!(out, " key {}", key)?;
+                    }
+                },
+                TreeNode::Error(ErrorTreeNode { source, message, .. }) => {
+                    write!(out, " - [ERROR] `{}`: {}", source, message)?;
+                },
+            }
+            out.write_str("\n")?;
+            stack.extend(node.children().iter().rev().map(|c| (c, depth + 1)));
+        }
+
+        Ok(())
+    }
+}
+
+impl TreeNode {
+    // All bindings used in the tree in document order
+    pub fn bindings(&self) -> Vec<BindingUsage> {
+        let mut usages = vec![];
+        let mut stack = vec![(self, vec![])];
+
+        while let Some((node, collections)) = stack.pop() {
+            let mut push_value = |value: &GenericValue, role: BindingRole| {
+                if let GenericValue::Binding(binding) = value {
+                    usages.push(BindingUsage { binding: binding.clone(), role, collections: collections.clone() });
                 }
-            },
+            };
+
+            let mut children_collections = None;
+            match node {
+                TreeNode::Root(_) | TreeNode::Error(_) => {},
+                TreeNode::Normal(NormalTreeNode { attributes, .. }) => {
+                    for Attribute { name, value, .. } in attributes {
+                        match value {
+                            AttributeValue::None => {},
+                            AttributeValue::Single(value) => push_value(value, BindingRole::Attribute(name.clone())),
+                            AttributeValue::Multiple(values) => {
+                                for value in values {
+                                    push_value(value, BindingRole::Attribute(name.clone()));
+                                }
+                            },
+                        }
+                    }
+                },
+                TreeNode::InnerContent(InnerContentTreeNode { value, .. }) => push_value(value, BindingRole::Text),
+                TreeNode::Subtree(SubtreeTreeNode { property }) => {
+                    push_value(&property.clone().into(), BindingRole::Subtree);
+                },
+                TreeNode::Collection(CollectionTreeNode { collection, alias, key, .. }) => {
+                    push_value(&collection.clone().into(), BindingRole::Collection);
+                    let mut nested = collections.clone();
+                    nested.push(LoopScope { collection: collection.clone(), alias: alias.clone() });
+                    if let Some(key) = key {
+                        usages.push(BindingUsage { binding: key.clone(), role: BindingRole::Key, collections: nested.clone() });
+                    }
+                    children_collections = Some(nested);
+                },
+            }
+
+            let children_collections = children_collections.unwrap_or(collections);
+            stack.extend(node.children().iter().rev().map(|child| (child, children_collections.clone())));
         }
+
+        usages
     }
 }
 
 impl TreeNode {
-    pub fn from_pest_pairs(mut pairs: Pairs<Rule>) -> Result<TreeNode, TreeBuildError> {
-        let children = parse_expression(pairs.next().expect("Expression is empty"))?;
+    pub fn parse(input: &str) -> Result<TreeNode, ParseError> {
+        let pairs = SyntaxParser::parse(Rule::tree, input).map_err(|e| SyntaxError::new(e, input))?;
+        Ok(TreeNode::from_pest_pairs(pairs)?)
+    }
+
+    // Parses expression with the seed of the generated `lorem` text; `parse` uses zero seed
+    pub fn parse_seeded(input: &str, seed: u64) -> Result<TreeNode, ParseError> {
+        let pairs = SyntaxParser::parse(Rule::tree, input).map_err(|e| SyntaxError::new(e, input))?;
+        Ok(TreeNode::from_pest_pairs_seeded(pairs, seed)?)
+    }
+
+    // Parses expression without stopping at the first error: invalid parts are replaced with
+    // the error nodes, and all errors are returned as diagnostics (see `recover` module)
+    pub fn parse_recovering(input: &str) -> (TreeNode, Vec<ParseDiagnostic>) {
+        crate::recover::parse(input)
+    }
+
+    pub fn from_pest_pairs(pairs: Pairs<Rule>) -> Result<TreeNode, TreeBuildError> {
+        TreeNode::from_pest_pairs_seeded(pairs, 0)
+    }
+
+    // Same as `from_pest_pairs`, with the seed of the generated `lorem` text
+    pub fn from_pest_pairs_seeded(mut pairs: Pairs<Rule>, seed: u64) -> Result<TreeNode, TreeBuildError> {
+        let scope = DefinitionScope::new(Lorem::new(seed));
+        let children = parse_expression(pairs.next().expect("Expression is empty"), &scope)?;
         Ok(RootTreeNode { children }.into())
     }
 }
 
-// Returns children nodes generated from expression
-fn parse_expression(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
-    let pairs = pair.into_inner();
-    parse_tail_expression(pairs)
-}
+// Returns children nodes generated from expression; definitions at the start of the expression
+// are visible in the rest of it
+pub(crate) fn parse_expression<'i>(pair: Pair<'i, Rule>, scope: &DefinitionScope<'i>) -> Result<Vec<TreeNode>, TreeBuildError> {
+    let input = pair.as_span().get_input();
+    let mut pairs = pair.into_inner().peekable();
+    let mut definitions = vec![];
+    while let Some(definition) = pairs.next_if(|pair| pair.as_rule() == Rule::definition) {
+        definitions.push(definition);
+    }
+    let scope = scope.with_definitions(definitions)?;
 
-// Returns children nodes generated from expression
-fn parse_tail_expression(mut pairs: Pairs<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
-    let pair = if let Some(pair) = pairs.next() {
-        pair
-    } else {
-        unreachable!("Empty expression is not possible");
-    };
+    let mut terms = vec![];
+    while let Some(term) = pairs.next() {
+        let nodes = parse_term_any(term, &scope)?;
+        terms.push(ExpressionTerm { nodes, operator: pairs.next().map(Operator::from_pair) });
+    }
+    join_terms(terms, input)
+}
 
-    let mut nodes = vec![];
+// Operator following the expression term; child operator keeps its offset for error location
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Sibling,
+    Child(usize),
+}
 
-    // Current node which we are processing
-    let mut new_nodes = parse_term_any(pair)?;
+impl Operator {
+    pub(crate) fn from_pair(pair: Pair<Rule>) -> Self {
+        match pair.as_rule() {
+            Rule::sibling_op => Operator::Sibling,
+            Rule::child_op => Operator::Child(pair.as_span().start()),
+            r => unreachable!("Invalid expression operator {:?}", r),
+        }
+    }
+}
 
-    let mut current_node = new_nodes.pop().expect("term contain at least one node");
-    // push all processed nodes in term
-    nodes.extend(new_nodes);
+pub(crate) struct ExpressionTerm {
+    pub nodes: Vec<TreeNode>,
+    pub operator: Option<Operator>,
+}
 
-    loop {
-        match pairs.next().map(|p| p.as_rule()) {
-            None => {
-                nodes.push(current_node);
-                break;
-            },
-            Some(Rule::sibling_op) => {
-                let mut siblings =  parse_term_any(pairs.next().expect("Expression operator is missing"))?;
-                let new_current_node = siblings.pop().expect("At least on sibling expected");
-                nodes.push(std::mem::replace(&mut current_node, new_current_node));
-                nodes.extend(siblings);
-            }
-            Some(Rule::child_op) => {
-                let current_node_children = current_node.children_mut()?;
-                let children = parse_tail_expression(pairs)?;
-                current_node_children.extend(children);
-                nodes.push(current_node);
-                break;
+// Joins nodes of the expression terms: `>` makes the rest of the expression children of the
+// last node of the term. Terms are joined from the end, so the nesting depth doesn't matter.
+pub(crate) fn join_terms(mut terms: Vec<ExpressionTerm>, input: &str) -> Result<Vec<TreeNode>, TreeBuildError> {
+    // Parents are checked in the source order, so the first invalid one is reported
+    for ExpressionTerm { nodes, operator } in &mut terms {
+        if let Some(Operator::Child(offset)) = operator {
+            if !matches!(nodes.last_mut().map(|node| node.children_mut()), Some(Ok(_))) {
+                let (line, column) = pest::Position::new(input, *offset).expect("Operator is in input").line_col();
+                return Err(TreeBuildError::LeafNodeCantHaveChildren { location: Some((line, column)) });
             }
-            r => unreachable!("Invalid expression operator {:?}", r),
         }
     }
 
-
-    Ok(nodes)
+    let mut tail = vec![];
+    for ExpressionTerm { mut nodes, operator } in terms.into_iter().rev() {
+        if let Some(Operator::Child(_)) = operator {
+            let children = nodes.last_mut().and_then(|node| node.children_mut().ok());
+            children.expect("Parent is checked before joining").append(&mut tail);
+        }
+        nodes.append(&mut tail);
+        tail = nodes;
+    }
+    Ok(tail)
 }
 
-fn parse_term_any(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
+pub(crate) fn parse_term_any<'i>(pair: Pair<'i, Rule>, scope: &DefinitionScope<'i>) -> Result<Vec<TreeNode>, TreeBuildError> {
     let tree_node = match pair.as_rule() {
-        Rule::term => parse_term(pair)?,
-        Rule::term_list => parse_term_list(pair)?,
+        Rule::term => parse_term(pair, scope)?,
+        Rule::term_list => parse_term_list(pair, scope)?,
         e => unreachable!("expression produce only term or term_list, got {:?}", e),
     };
     Ok(tree_node)
 }
 
-fn parse_term(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
+fn parse_term<'i>(pair: Pair<'i, Rule>, scope: &DefinitionScope<'i>) -> Result<Vec<TreeNode>, TreeBuildError> {
     let pairs = pair.into_inner().next().expect("Term have exact one pair");
-    parse_term_content(pairs)
+    parse_term_content(pairs, scope)
 }
 
-fn parse_term_content(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
+fn parse_term_content<'i>(pair: Pair<'i, Rule>, scope: &DefinitionScope<'i>) -> Result<Vec<TreeNode>, TreeBuildError> {
     match pair.as_rule() {
         Rule::node => {
-            Ok(vec![parse_node(pair)?])
-        }
-        Rule::text_node => Ok(parse_text_node(pair)?),
-        Rule::node_binding => Ok(vec![parse_node_binding(pair)?]),
-        Rule::expr => parse_expression(pair),
+            let mut inner = pair.clone().into_inner();
+            let name = inner.next().expect("Node always have name");
+            if !scope.is_defined(name.as_str()) {
+                return match lorem_node(&name, inner.next().is_none())? {
+                    Some(amount) => Ok(generate_lorem(amount, false, scope)),
+                    None => Ok(vec![parse_node(pair, scope)?]),
+                };
+            }
+            if inner.next().is_some() {
+                let (line, column) = name.as_span().start_pos().line_col();
+                let error = DefinitionError::UseWithProperties(name.as_str().to_owned());
+                return Err(TreeBuildError::Definition { error, line, column });
+            }
+            let (scope, body) = scope.expand(&name, vec![])?;
+            parse_expression(body, &scope)
+        },
+        Rule::call => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().expect("Call always have name");
+            let arguments = inner.next().expect("Call always have arguments").into_inner();
+            let arguments = arguments.map(|pair| parse_generic_value(pair, scope)).collect::<Result<_, _>>()?;
+            let (scope, body) = scope.expand(&name, arguments)?;
+            parse_expression(body, &scope)
+        },
+        Rule::text_node => Ok(parse_text_node(pair, false, scope)?),
+        Rule::raw_text_node => Ok(parse_text_node(pair, true, scope)?),
+        Rule::node_binding => Ok(vec![parse_node_binding(pair, scope)?]),
+        Rule::expr => parse_expression(pair, scope),
         e => unreachable!("Invalid term inner rule {:?}", e),
     }
 }
 
-fn parse_term_list(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
+fn parse_term_list<'i>(pair: Pair<'i, Rule>, scope: &DefinitionScope<'i>) -> Result<Vec<TreeNode>, TreeBuildError> {
     let mut pairs = pair.into_inner(); // inner term_list components
     let term = pairs.next().expect("Term list should have term inside");
     let multiplier = pairs.next().expect("Term list should have multiplier");
     assert_eq!(multiplier.as_rule(), Rule::multiplier);
 
-    let term_nodes = parse_term_content(term)?;
-    let multiplier = multiplier.into_inner().next().expect("Multiplayer can't be empty");
-    match multiplier.as_rule() {
+    let lorem = scope.lorem().clone();
+    let mut nodes = parse_term_content(term.clone(), scope)?;
+    // Copies of the term with generated text are built separately, so every copy has its own text
+    let copies = multiplier.clone().into_inner().next().filter(|value| value.as_rule() == Rule::number);
+    if *scope.lorem() != lorem {
+        if let Some(copies) = copies.map(parse_number::<usize>).transpose()?.filter(|&copies| copies > 0) {
+            for _ in 1..copies {
+                nodes.extend(parse_term_content(term.clone(), scope)?);
+            }
+            return Ok(nodes);
+        }
+    }
+    apply_multiplier(nodes, multiplier, scope)
+}
+
+// Repeats nodes of the term or wraps them into collection node
+pub(crate) fn apply_multiplier(
+    term_nodes: Vec<TreeNode>,
+    multiplier: Pair<Rule>,
+    scope: &DefinitionScope,
+) -> Result<Vec<TreeNode>, TreeBuildError> {
+    let mut multiplier = multiplier.into_inner();
+    let value = multiplier.next().expect("Multiplayer can't be empty");
+    let (mut alias, mut key) = (None, None);
+    for pair in multiplier {
+        match pair.as_rule() {
+            Rule::loop_variable => alias = Some(pair.as_str().to_owned()),
+            Rule::binding => key = Some(parse_scoped_binding(pair, scope)?),
+            r => unreachable!("Invalid multiplier rule: {:?}", r),
+        }
+    }
+    match value.as_rule() {
         Rule::number => {
-            let multiplier = parse_number(multiplier)?;
+            let multiplier: usize = parse_number(value)?;
             let mut all_nodes = vec![];
             for _ in 0..multiplier {
                 all_nodes.extend(term_nodes.clone());
@@ -306,11 +803,13 @@ fn parse_term_list(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
             Ok(all_nodes)
         }
         Rule::binding => {
-            let binding = parse_binding(multiplier)?;
+            let binding = parse_scoped_binding(value, scope)?;
             Ok(vec![
                 CollectionTreeNode {
                     nodes: term_nodes,
                     collection: binding,
+                    alias,
+                    key,
                 }.into()
             ])
         }
@@ -318,7 +817,10 @@ fn parse_term_list(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
     }
 }
 
-fn parse_text_node(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
+fn parse_text_node(pair: Pair<Rule>, raw: bool, scope: &DefinitionScope) -> Result<Vec<TreeNode>, TreeBuildError> {
+    if let Some(amount) = lorem_text(&pair)? {
+        return Ok(generate_lorem(amount, raw, scope));
+    }
     let mut nodes = vec![];
     let mut first = true;
     // TODO: Optimize, merge text/number nodes into one string
@@ -327,11 +829,13 @@ fn parse_text_node(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
             // Push implicit space between text
             nodes.push(InnerContentTreeNode {
                 value: GenericValue::Text(" ".to_owned()),
+                raw,
             }.into());
         }
 
         let node = InnerContentTreeNode {
-            value: parse_generic_value(pair)?
+            value: parse_generic_value(pair, scope)?,
+            raw,
         };
         nodes.push(node.into());
 
@@ -340,14 +844,83 @@ fn parse_text_node(pair: Pair<Rule>) -> Result<Vec<TreeNode>, TreeBuildError> {
     Ok(nodes)
 }
 
-fn parse_node_binding(pair: Pair<Rule>) -> Result<TreeNode, TreeBuildError> {
-    let binding = parse_binding(pair)?;
+// Amount of the text generated by `lorem` or `lorem20` node; node with properties is a plain
+// element
+fn lorem_node(name: &Pair<Rule>, without_properties: bool) -> Result<Option<LoremAmount>, TreeBuildError> {
+    let count = match name.as_str().strip_prefix("lorem") {
+        Some(count) if without_properties && count.bytes().all(|b| b.is_ascii_digit()) => count,
+        _ => return Ok(None),
+    };
+    if count.is_empty() {
+        return Ok(Some(LoremAmount::Words(DEFAULT_WORDS)));
+    }
+    match count.parse() {
+        Ok(count) if count <= MAX_COUNT => Ok(Some(LoremAmount::Words(count))),
+        _ => {
+            let (line, column) = name.as_span().start_pos().line_col();
+            Err(TreeBuildError::InvalidNumLiteral { literal: count.to_owned(), line, column: column + "lorem".len() })
+        },
+    }
+}
+
+// Amount of the text generated by `{lorem}`, `{lorem 20}` or `{lorem 3 sentences}` text; any
+// other text is literal
+fn lorem_text(pair: &Pair<Rule>) -> Result<Option<LoremAmount>, TreeBuildError> {
+    let content = pair.clone().into_inner().collect::<Vec<_>>();
+    let (count, unit) = match content.as_slice() {
+        [lorem, rest @ ..] if lorem.as_rule() == Rule::ident && lorem.as_str() == "lorem" => match rest {
+            [] => (None, None),
+            [count] if count.as_rule() == Rule::integer => (Some(count), None),
+            [count, unit] if count.as_rule() == Rule::integer && unit.as_rule() == Rule::ident => {
+                (Some(count), Some(unit.as_str()))
+            },
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let count = count.cloned().map(parse_lorem_count).transpose()?;
+    let amount = match unit {
+        None | Some("word" | "words") => LoremAmount::Words(count.unwrap_or(DEFAULT_WORDS)),
+        Some("sentence" | "sentences") => LoremAmount::Sentences(count.unwrap_or(1)),
+        Some("paragraph" | "paragraphs") => LoremAmount::Paragraphs(count.unwrap_or(1)),
+        Some(_) => return Ok(None),
+    };
+    Ok(Some(amount))
+}
+
+fn parse_lorem_count(pair: Pair<Rule>) -> Result<usize, TreeBuildError> {
+    let (line, column) = pair.as_span().start_pos().line_col();
+    let literal = pair.as_str().to_owned();
+    match parse_number::<usize>(pair)? {
+        count if count <= MAX_COUNT => Ok(count),
+        _ => Err(TreeBuildError::InvalidNumLiteral { literal, line, column }),
+    }
+}
+
+// Text content for words and sentences, `p` elements for paragraphs
+fn generate_lorem(amount: LoremAmount, raw: bool, scope: &DefinitionScope) -> Vec<TreeNode> {
+    let mut lorem = scope.lorem();
+    let text = |text| TreeNode::from(InnerContentTreeNode { value: GenericValue::Text(text), raw });
+    match amount {
+        LoremAmount::Words(count) => vec![text(lorem.words(count))],
+        LoremAmount::Sentences(count) => vec![text(lorem.sentences(count))],
+        LoremAmount::Paragraphs(count) => {
+            let paragraphs = lorem.paragraphs(count).into_iter().map(|paragraph| {
+                NormalTreeNode { name: "p".to_owned(), attributes: vec![], children: vec![text(paragraph)] }.into()
+            });
+            paragraphs.collect()
+        },
+    }
+}
+
+fn parse_node_binding(pair: Pair<Rule>, scope: &DefinitionScope) -> Result<TreeNode, TreeBuildError> {
+    let binding = parse_scoped_binding(pair, scope)?;
     Ok(SubtreeTreeNode {
         property: binding,
     }.into())
 }
 
-fn parse_node(pair: Pair<Rule>) -> Result<TreeNode, TreeBuildError> {
+fn parse_node(pair: Pair<Rule>, scope: &DefinitionScope) -> Result<TreeNode, TreeBuildError> {
     let mut pairs = pair.into_inner();
     let node_name = pairs.next().expect("Node always have name").as_str();
     let mut node = NormalTreeNode {
@@ -356,7 +929,7 @@ fn parse_node(pair: Pair<Rule>) -> Result<TreeNode, TreeBuildError> {
         children: vec![],
     };
 
-    while let Some(pair) = pairs.next() {
+    for pair in pairs {
         match pair.as_rule() {
             Rule::id_prop => {
                 node.append_attribute("id", GenericValue::Text(pair.as_str().to_owned()));
@@ -369,11 +942,16 @@ fn parse_node(pair: Pair<Rule>) -> Result<TreeNode, TreeBuildError> {
                 for attr_pair in pair.into_inner() {
                     let mut attr_parts = attr_pair.into_inner();
                     let name = attr_parts.next().expect("Attr name always exist").as_str();
-                    let value = parse_generic_value(
-                        attr_parts.next().expect("Attr always have value")
-                            .into_inner().next().expect("Attr value always have inner item")
-                    )?;
-                    node.append_attribute(name, value);
+                    match attr_parts.next() {
+                        Some(value) => {
+                            let value = parse_generic_value(
+                                value.into_inner().next().expect("Attr value always have inner item"),
+                                scope,
+                            )?;
+                            node.append_attribute(name, value);
+                        },
+                        None => node.append_flag(name),
+                    }
                 }
             }
             _ => unreachable!("Invalid node property"),
@@ -383,24 +961,56 @@ fn parse_node(pair: Pair<Rule>) -> Result<TreeNode, TreeBuildError> {
     Ok(node.into())
 }
 
-fn parse_generic_value(pair: Pair<Rule>) -> Result<GenericValue, TreeBuildError> {
+fn parse_generic_value(pair: Pair<Rule>, scope: &DefinitionScope) -> Result<GenericValue, TreeBuildError> {
     let value = match pair.as_rule() {
         Rule::ident => GenericValue::Text(pair.as_str().to_owned()),
-        Rule::number => GenericValue::Number(parse_number(pair)?),
+        Rule::integer => GenericValue::Number(parse_number(pair)?),
+        Rule::float => GenericValue::Float(Float(parse_float(pair)?)),
+        Rule::boolean => GenericValue::Bool(pair.as_str() == "true"),
         Rule::string => GenericValue::Text(parse_string(pair)?),
-        Rule::binding => GenericValue::Binding(parse_binding(pair)?),
+        Rule::binding => scope.substitute(parse_binding(pair.clone())?, &pair)?,
         _ => unreachable!("Invalid generic values")
     };
 
     Ok(value)
 }
 
-fn parse_number(pair: Pair<Rule>) -> Result<isize, TreeBuildError> {
-    pair.as_str().parse().map_err(|_| TreeBuildError::InvalidNumLiteral)
+fn parse_number<T: std::str::FromStr>(pair: Pair<Rule>) -> Result<T, TreeBuildError> {
+    pair.as_str().parse().map_err(|_| {
+        let (line, column) = pair.as_span().start_pos().line_col();
+        TreeBuildError::InvalidNumLiteral { literal: pair.as_str().to_owned(), line, column }
+    })
 }
 
-fn parse_string(pair: Pair<Rule>) -> Result<String, TreeBuildError> {
-    Ok(pair.into_inner().next().expect("String always have inner part").as_str().to_owned())
+// Literals too big for `f64` are parsed as infinity, which can't be written back
+fn parse_float(pair: Pair<Rule>) -> Result<f64, TreeBuildError> {
+    let (line, column) = pair.as_span().start_pos().line_col();
+    let literal = pair.as_str().to_owned();
+    match parse_number::<f64>(pair)? {
+        value if value.is_finite() => Ok(value),
+        _ => Err(TreeBuildError::InvalidNumLiteral { literal, line, column }),
+    }
+}
+
+pub(crate) fn parse_string(pair: Pair<Rule>) -> Result<String, TreeBuildError> {
+    let inner = pair.into_inner().next().expect("String always have inner part").as_str();
+    let mut value = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        // Grammar allows only valid escape sequences
+        value.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some(c) => c,
+            None => unreachable!("Escape sequence is not complete"),
+        });
+    }
+    Ok(value)
 }
 
 fn parse_binding(pair: Pair<Rule>) -> Result<PropertyBinding, TreeBuildError> {
@@ -410,27 +1020,23 @@ fn parse_binding(pair: Pair<Rule>) -> Result<PropertyBinding, TreeBuildError> {
         assert_eq!(pair.as_rule(), Rule::binding_part);
         parts.push(pair.as_str().to_owned());
     }
+    Ok(PropertyBinding::from_parts(parts).expect("Grammar requires non-empty binding parts"))
+}
 
-    let binding = if parts.len() == 1 {
-        PropertyBinding::RootIdentifier(parts.pop().unwrap())
-    } else {
-        PropertyBinding::NestedIdentifier(parts)
-    };
-
-    Ok(binding)
+// Binding with the definition parameters substituted, for the places which require binding
+fn parse_scoped_binding(pair: Pair<Rule>, scope: &DefinitionScope) -> Result<PropertyBinding, TreeBuildError> {
+    scope.substitute_binding(parse_binding(pair.clone())?, &pair)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use expect_test::{expect, Expect};
-    use crate::parser::SyntaxParser;
-    use pest::Parser;
 
     fn assert_parsed(input: &str, expect: Expect) {
         let pairs = SyntaxParser::parse(Rule::tree, input).unwrap();
         let tree = TreeNode::from_pest_pairs(pairs).unwrap();
-        expect.assert_eq(&tree.to_string());
+        expect.assert_eq(&snapshot(&tree));
     }
 
     #[test]
@@ -511,4 +1117,218 @@ mod tests {
         "#]]
     );
     }
+
+    #[test]
+    fn structural_equality() {
+        let parse = |input| TreeNode::parse(input).unwrap();
+
+        assert_eq!(parse("div#a>p+{@text}"), parse("div#a > p + { @text } // comment"));
+        assert_ne!(parse("div#a>p"), parse("div#b>p"));
+
+        let templates = ["li*2", "li+li", "li*3", "(li)*2"]
+            .iter()
+            .map(|t| parse(t))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(templates.len(), 2);
+
+        // Whether the value came from the data is not a part of the attribute identity
+        let attribute = Attribute { name: "title".to_owned(), value: AttributeValue::None, bound: false };
+        let bound = Attribute { bound: true, ..attribute.clone() };
+        assert_eq!(attribute, bound);
+        assert_eq!(attribute.cmp(&bound), Ordering::Equal);
+        assert_eq!([attribute, bound].into_iter().collect::<std::collections::HashSet<_>>().len(), 1);
+    }
+
+    #[test]
+    fn multiplied_children() {
+        // Children of the multiplied term used to be added to the last copy only
+        let error = TreeNode::parse("div*3>p").unwrap_err();
+        assert_eq!(error.location(), Some((1, 6)));
+        assert!(TreeNode::parse("ul>li*@items>a").is_err());
+
+        // Grouping is the migration path
+        expect![[r#"
+            - div
+              - p
+            - div
+              - p
+            - div
+              - p
+        "#]].assert_eq(&snapshot(&TreeNode::parse("(div>p)*3").unwrap()));
+        assert!(TreeNode::parse("ul>(li>a)*@items+p>li*2").is_ok());
+    }
+
+    #[test]
+    fn valueless_attributes_and_escapes() {
+        assert_parsed(r#"input[type=checkbox checked checked disabled=false]+p[title='a\'b\\c' x="1\n2\t3"]>{'line\n'}"#,
+            expect![[r#"
+                - input[type="checkbox" checked=<NONE> disabled=false]
+                - p[title="a'b\c" x="1
+                2	3"]
+                  - [CONTENT] "line
+                "
+            "#]]
+        );
+
+        // Strings without escapes and attributes with values are parsed as before
+        let tree = TreeNode::parse(r#"a[href='/a b' title="it" x=1]>{'a/b c'}"#).unwrap();
+        expect![[r#"
+            - a[href="/a b" title="it" x=1]
+              - [CONTENT] "a/b c"
+        "#]].assert_eq(&snapshot(&tree));
+        assert!(TreeNode::parse(r"p>{'a\qb'}").is_err());
+    }
+
+    #[test]
+    fn typed_literals() {
+        assert_parsed("div[opacity=0.5 offset=-3 scale=1.0 big=-2.5e10 enabled=true truest=false-ish]>{-1 false 1e3}",
+            expect![[r#"
+                - div[opacity=0.5 offset=-3 scale=1.0 big=-25000000000.0 enabled=true truest="false-ish"]
+                  - [CONTENT] -1
+                  - [CONTENT] " "
+                  - [CONTENT] false
+                  - [CONTENT] " "
+                  - [CONTENT] 1000.0
+            "#]]
+        );
+
+        let error = TreeNode::parse("ul>li[n=1]+\n  p[n=99999999999999999999]").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid number literal `99999999999999999999` at 2:7");
+        let error = TreeNode::parse("li*99999999999999999999").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid number literal `99999999999999999999` at 1:4");
+        assert_eq!(error.location(), Some((1, 4)));
+        let error = TreeNode::parse("p[size=1e999]").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid number literal `1e999` at 1:8");
+        let error = TreeNode::parse("p>{-1.5e400}").unwrap_err();
+        assert_eq!(error.to_string(), "Invalid number literal `-1.5e400` at 1:4");
+        // First invalid parent in the source order is reported
+        let error = TreeNode::parse("{a}>b+{c}>d").unwrap_err();
+        assert_eq!(error.to_string(), "Leaf node can't have any children at 1:4");
+        let error = TreeNode::parse("p>({text}>b)").unwrap_err();
+        assert_eq!(error.to_string(), "Leaf node can't have any children at 1:10");
+        assert_eq!((error.location(), error.message().as_str()), (Some((1, 10)), "Leaf node can't have any children"));
+        let error = TreeNode::from(InnerContentTreeNode { value: "text".into(), raw: false }).children_mut().unwrap_err();
+        assert_eq!(error.to_string(), "Leaf node can't have any children");
+
+        assert_eq!(TreeNode::parse("a[x=0.0]").unwrap(), TreeNode::parse("a[x=0.0]").unwrap());
+        assert_ne!(TreeNode::parse("a[x=0.0]").unwrap(), TreeNode::parse("a[x=-0.0]").unwrap());
+    }
+
+    #[test]
+    fn bindings() {
+        let tree = TreeNode::parse("a[href=@url class=@a class=@b]>{@text}+(li>$items%view)*@items key @items%id").unwrap();
+        let bindings = tree.bindings()
+            .iter()
+            .map(|usage| format!("{} {} {:?}", usage.role, usage.binding, usage.collections))
+            .collect::<Vec<String>>();
+        assert_eq!(bindings, [
+            "attribute `href` @url []",
+            "attribute `class` @a []",
+            "attribute `class` @b []",
+            "text @text []",
+            "collection @items []",
+            "collection key @items%id [LoopScope { collection: RootIdentifier(\"items\"), alias: None }]",
+            "subtree @items%view [LoopScope { collection: RootIdentifier(\"items\"), alias: None }]",
+        ]);
+    }
+
+    #[test]
+    fn definitions() {
+        assert_parsed("let item(text, url) = li>a[href=@url]>{@text}; ul>item('Home', '/')+item(@page%title, @page%url)",
+            expect![[r#"
+                - ul
+                  - li
+                    - a[href="/"]
+                      - [CONTENT] "Home"
+                  - li
+                    - a[href=@page%url]
+                      - [CONTENT] @page%title
+            "#]]
+        );
+
+        // Definitions see each other and are visible in groups; parameters are lexically scoped
+        assert_parsed(r#"
+            let title = h1>{@name};
+            let card(name) = div.card>title+(let name = span; name)+{@name};
+            card(@user%name)+(title)
+        "#,
+            expect![[r#"
+                - div[class="card"]
+                  - h1
+                    - [CONTENT] @name
+                  - span
+                  - [CONTENT] @user%name
+                - h1
+                  - [CONTENT] @name
+            "#]]
+        );
+
+        // Arguments bound to collection items, used as collection and its key
+        assert_parsed("let list(items, key) = ul>(li>{@items%name})*@items key @key; list(@users, @users%id)",
+            expect![[r#"
+                - ul
+                  - [COLLECTION] @users key @users%id
+                    - li
+                      - [CONTENT] @users%name
+            "#]]
+        );
+
+        let error = |input| TreeNode::parse(input).unwrap_err().to_string();
+        assert_eq!(error("let a = b; let b = c>a; a"), "Definition is recursive: a -> b -> a at 1:22");
+        assert_eq!(error("let a(x) = p>{@x}; a"), "`a` takes 1 argument(s), but 0 given at 1:20");
+        assert_eq!(error("let a(x) = p>{@x}; a(1, 2)"), "`a` takes 1 argument(s), but 2 given at 1:20");
+        assert_eq!(error("let a = p; let a = b; a"), "`a` is defined twice at 1:16");
+        assert_eq!(error("let a(x, x) = p; a(1, 2)"), "`x` is defined twice at 1:10");
+        assert_eq!(error("b(1)"), "`b` is not defined at 1:1");
+        assert_eq!(error("let a = p; a.class"), "Use of `a` can't have id, class or attributes at 1:12");
+        assert_eq!(error("let a(x) = li*@x; a('text')"), "`@x` is used as a binding, but `\"text\"` is given at 1:15");
+        assert_eq!(error("let a(x) = p>{@x%name}; a(1)"), "`@x%name` is used as a binding, but `1` is given at 1:15");
+    }
+
+    #[test]
+    fn lorem() {
+        assert_parsed("(p>lorem5)*2+(span>{lorem 2 sentences})+div>!{lorem 2 paragraphs}",
+            expect![[r#"
+                - p
+                  - [CONTENT] "Lorem ipsum dolor sit amet."
+                - p
+                  - [CONTENT] "Nunc nisl sed mauris euismod?"
+                - span
+                  - [CONTENT] "Dui elementum cras gravida felis. Pretium iaculis fusce at, et dui nunc in lectus euismod vivamus."
+                - div
+                  - p
+                    - [RAW] "Arcu pretium egestas commodo etiam dapibus pretium. Aliquam etiam quam nunc. Maecenas lobortis consequat ornare, tempor et arcu integer nec etiam. Feugiat pharetra etiam nam nulla nec cras justo aliquet mi. Curabitur enim ligula neque accumsan dignissim in mauris feugiat a."
+                  - p
+                    - [RAW] "Id dui eget curabitur metus, ac euismod? Molestie arcu diam non non et massa curabitur, ac accumsan mollis a. Donec pretium consequat dapibus curabitur posuere."
+            "#]]
+        );
+        // Literal text, plain elements and the definitions named `lorem` are kept
+        assert_parsed("let lorem = hr; p>{lorem ipsum}+{'lorem' 5}+lorem+lorem10.x+lorem-ipsum",
+            expect![[r#"
+                - p
+                  - [CONTENT] "lorem"
+                  - [CONTENT] " "
+                  - [CONTENT] "ipsum"
+                  - [CONTENT] "lorem"
+                  - [CONTENT] " "
+                  - [CONTENT] 5
+                  - hr
+                  - lorem10[class="x"]
+                  - lorem-ipsum
+            "#]]
+        );
+
+        let parse = |input| TreeNode::parse(input).unwrap();
+        assert_eq!(parse("p>lorem"), parse("p>{lorem 30}"));
+        assert_ne!(parse("p>lorem"), TreeNode::parse_seeded("p>lorem", 1).unwrap());
+        assert_eq!(TreeNode::parse_seeded("p>lorem", 1).unwrap(), TreeNode::parse_seeded("p>lorem", 1).unwrap());
+        assert_eq!(TreeNode::parse("lorem99999999999999999999").unwrap_err().to_string(),
+            "Invalid number literal `99999999999999999999` at 1:6");
+        // Count is limited, the text is generated while parsing
+        assert!(TreeNode::parse("p>lorem1000+{lorem 1000 paragraphs}").is_ok());
+        assert_eq!(TreeNode::parse("p>lorem999999999").unwrap_err().to_string(),
+            "Invalid number literal `999999999` at 1:8");
+        assert_eq!(TreeNode::parse("p>{lorem 99999999 paragraphs}").unwrap_err().to_string(),
+            "Invalid number literal `99999999` at 1:10");
+    }
 }
@@ -0,0 +1,280 @@
+use crate::escape::SafeHtml;
+use crate::tree::{Float, PropertyBinding, TreeNode};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+// Data which bindings are resolved against during template evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(isize),
+    Float(f64),
+    Text(String),
+    // Trusted markup, which is not escaped when bound to text content
+    SafeHtml(SafeHtml),
+    List(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+    // Component, inserted in place of `$binding` nodes
+    Tree(TreeNode),
+}
+
+impl Value {
+    pub fn object<K: Into<String>, V: Into<Value>>(fields: impl IntoIterator<Item = (K, V)>) -> Value {
+        Value::Object(fields.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn get_path(&self, path: &[String]) -> Option<&Value> {
+        path.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    // Values which could be represented as text or attribute value
+    pub fn is_scalar(&self) -> bool {
+        matches!(
+            self,
+            Value::Null | Value::Bool(_) | Value::Number(_) | Value::Float(_) | Value::Text(_) | Value::SafeHtml(_)
+        )
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Float(n) => f.write_str(&Float(*n).to_text()),
+            Value::Text(s) => f.write_str(s),
+            Value::SafeHtml(s) => write!(f, "{}", s),
+            Value::List(_) => f.write_str("<LIST>"),
+            Value::Object(_) => f.write_str("<OBJECT>"),
+            Value::Tree(_) => f.write_str("<TREE>"),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Text(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Text(value)
+    }
+}
+
+impl From<SafeHtml> for Value {
+    fn from(value: SafeHtml) -> Self {
+        Value::SafeHtml(value)
+    }
+}
+
+impl From<isize> for Value {
+    fn from(value: isize) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<i32> for Value {
+    fn from(value: i32) -> Self {
+        Value::Number(value as isize)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<TreeNode> for Value {
+    fn from(value: TreeNode) -> Self {
+        Value::Tree(value)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Self {
+        Value::List(values.into_iter().map(Into::into).collect())
+    }
+}
+
+// Implicit bindings available inside of the collection loop body, referring to the innermost loop
+pub const LOOP_VARIABLES: &[&str] = &["index", "first", "last", "even"];
+
+#[derive(Debug, Clone)]
+struct ScopeItem<'a> {
+    path: &'a [String],
+    item: &'a Value,
+    index: usize,
+    len: usize,
+}
+
+// Binding resolution context. Each collection item which is currently being processed is pushed
+// to the scope with its binding path: either loop variable (`@item` inside of
+// `(...) * @items as item`), or the collection path itself (`@items%name` inside of
+// `(...) * @items`). Bindings starting with this path are resolved against the current item
+// instead of the root object, the innermost loop wins.
+#[derive(Debug, Clone)]
+pub struct Scope<'a> {
+    root: &'a Value,
+    items: Vec<ScopeItem<'a>>,
+    // Scope the component is rendered in, its items are searched after the own ones
+    parent: Option<&'a Scope<'a>>,
+    // Number of the components the scope is nested in
+    depth: usize,
+}
+
+impl<'a> Scope<'a> {
+    pub fn new(root: &'a Value) -> Self {
+        Self { root, items: vec![], parent: None, depth: 0 }
+    }
+
+    // Scope of the component rendered in `parent`, which could have shorter lifetime than the
+    // parent itself (e.g. when the component is compiled during rendering)
+    pub(crate) fn nested(parent: &'a Scope<'a>) -> Self {
+        Self { root: parent.root, items: vec![], parent: Some(parent), depth: parent.depth + 1 }
+    }
+
+    // Number of the components the scope is nested in
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub(crate) fn enter_component(&mut self) {
+        self.depth += 1;
+    }
+
+    pub(crate) fn exit_component(&mut self) {
+        self.depth -= 1;
+    }
+
+    // Enters loop body for the item at `index` of collection with `len` items, `path` is the
+    // binding path referring to the item (see `CollectionTreeNode::item_path`)
+    pub fn push_item(&mut self, path: &'a [String], item: &'a Value, index: usize, len: usize) {
+        self.items.push(ScopeItem { path, item, index, len });
+    }
+
+    pub fn pop_item(&mut self) {
+        self.items.pop();
+    }
+
+    // Data fields win over the implicit loop variables of the same name
+    pub fn resolve(&self, binding: &PropertyBinding) -> Option<Cow<'a, Value>> {
+        self.resolve_data(binding).or_else(|| self.loop_variable(binding))
+    }
+
+    // Resolves binding against the collection items and the root object only
+    pub(crate) fn resolve_data(&self, binding: &PropertyBinding) -> Option<Cow<'a, Value>> {
+        let parts = binding.parts();
+        let mut scope = Some(self);
+        while let Some(current) = scope {
+            for ScopeItem { path, item, .. } in current.items.iter().rev() {
+                if let Some(rest) = parts.strip_prefix(*path) {
+                    return item.get_path(rest).map(Cow::Borrowed);
+                }
+            }
+            scope = current.parent;
+        }
+        self.root.get_path(parts).map(Cow::Borrowed)
+    }
+
+    fn loop_variable(&self, binding: &PropertyBinding) -> Option<Cow<'a, Value>> {
+        let (Some(current), [name]) = (self.current(), binding.parts()) else {
+            return None;
+        };
+        let value = match name.as_str() {
+            "index" => Value::Number(current.index as isize),
+            "first" => Value::Bool(current.index == 0),
+            "last" => Value::Bool(current.index + 1 == current.len),
+            "even" => Value::Bool(current.index % 2 == 0),
+            _ => return None,
+        };
+        Some(Cow::Owned(value))
+    }
+
+    // Innermost item, including the items of the parent scopes
+    fn current(&self) -> Option<&ScopeItem<'a>> {
+        self.items.last().or_else(|| self.parent.and_then(Scope::current))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scoped_resolution() {
+        let data = Value::object([
+            ("title", Value::from("Hello")),
+            ("items", Value::from(vec![
+                Value::object([("name", Value::from("one")), ("tags", Value::from(vec!["a", "b"]))]),
+            ])),
+        ]);
+
+        let items = PropertyBinding::from("items");
+        let tags = PropertyBinding::from("items%tags");
+        let tag_alias = ["tag".to_owned()];
+        let mut scope = Scope::new(&data);
+        assert_eq!(scope.resolve(&"title".into()).as_deref(), Some(&Value::from("Hello")));
+        assert_eq!(scope.resolve(&"items%name".into()), None);
+
+        let item = match data.get("items") {
+            Some(Value::List(list)) => &list[0],
+            _ => unreachable!(),
+        };
+        scope.push_item(items.parts(), item, 0, 1);
+        assert_eq!(scope.resolve(&"items%name".into()).as_deref(), Some(&Value::from("one")));
+        assert_eq!(scope.resolve(&"title".into()).as_deref(), Some(&Value::from("Hello")));
+
+        let tag = Value::from("a");
+        scope.push_item(tags.parts(), &tag, 0, 2);
+        assert_eq!(scope.resolve(&"items%tags".into()).as_deref(), Some(&Value::from("a")));
+        assert_eq!(scope.resolve(&"items%name".into()).as_deref(), Some(&Value::from("one")));
+        assert_eq!(scope.resolve(&"last".into()).as_deref(), Some(&Value::from(false)));
+        scope.pop_item();
+
+        scope.push_item(&tag_alias, &tag, 1, 2);
+        assert_eq!(scope.resolve(&"tag".into()).as_deref(), Some(&Value::from("a")));
+        assert_eq!(scope.resolve(&"items%tags".into()).as_deref(), Some(&Value::from(vec!["a", "b"])));
+        assert_eq!(scope.resolve(&"index".into()).as_deref(), Some(&Value::from(1)));
+        assert_eq!(scope.resolve(&"last".into()).as_deref(), Some(&Value::from(true)));
+        assert_eq!(scope.resolve(&"even".into()).as_deref(), Some(&Value::from(false)));
+
+        scope.pop_item();
+        scope.pop_item();
+        assert_eq!(scope.resolve(&"index".into()), None);
+        assert_eq!(scope.resolve(&"items%name".into()), None);
+    }
+
+    #[test]
+    fn shadowed_loop_variables() {
+        let data = Value::object([("first", Value::from("F")), ("items", Value::from(vec!["a", "b"]))]);
+        let items = ["items".to_owned()];
+        let mut scope = Scope::new(&data);
+        scope.push_item(&items, &Value::Null, 1, 2);
+        assert_eq!(scope.resolve(&"first".into()).as_deref(), Some(&Value::from("F")));
+        assert_eq!(scope.resolve(&"last".into()).as_deref(), Some(&Value::from(true)));
+
+        // Component rendered inside of the loop sees the same data
+        let nested = Scope::nested(&scope);
+        assert_eq!(nested.resolve(&"first".into()).as_deref(), Some(&Value::from("F")));
+        assert_eq!(nested.resolve(&"index".into()).as_deref(), Some(&Value::from(1)));
+    }
+}
@@ -0,0 +1,561 @@
+use crate::diff::format_path;
+use crate::eval::{
+    evaluate_attributes, evaluate_value, resolve_collection, resolve_component, resolve_scalar, EvalError,
+};
+use crate::render::RenderError;
+use crate::tree::{
+    Attribute, AttributeValue, CollectionTreeNode, GenericValue, InnerContentTreeNode, NormalTreeNode,
+    PropertyBinding, RootTreeNode, SubtreeTreeNode, TreeNode,
+};
+use crate::value::{Scope, Value};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use thiserror::Error;
+
+// Node of the virtual DOM: evaluated tree where every node maps to exactly one DOM node (raw
+// text should be applied by the runtime as a single node too, e.g. wrapped into `<span>`).
+// Elements may have a key, which identifies them among siblings between the renders.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum VNode {
+    Element {
+        name: String,
+        attributes: Vec<VAttribute>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+        children: Vec<VNode>,
+    },
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        raw: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VAttribute {
+    pub name: String,
+    // Missing for valueless attributes
+    pub value: Option<String>,
+}
+
+// DOM update operation. Paths are child indices from the root container, and refer to the DOM
+// state after all preceding patches were applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum Patch {
+    Insert { parent: Vec<usize>, index: usize, node: VNode },
+    Remove { parent: Vec<usize>, index: usize },
+    // Child is removed from `from` and inserted at `to`, which is an index after the removal
+    Move { parent: Vec<usize>, from: usize, to: usize },
+    SetAttribute { path: Vec<usize>, name: String, value: Option<String> },
+    RemoveAttribute { path: Vec<usize>, name: String },
+    SetText { path: Vec<usize>, text: String },
+}
+
+#[derive(Debug, Error)]
+pub enum PatchError {
+    #[error("Node {0} is not found")]
+    NodeNotFound(String),
+    #[error("Node {0} has unexpected type")]
+    UnexpectedNodeType(String),
+}
+
+impl VNode {
+    // Converts evaluated tree (see `eval::evaluate`) into virtual DOM. Elements are keyed by their
+    // `id` attribute, as collection items can't be identified after the evaluation.
+    pub fn from_tree(tree: &TreeNode) -> Result<Vec<VNode>, RenderError> {
+        let mut nodes = vec![];
+        from_tree_node(tree, &mut nodes)?;
+        Ok(nodes)
+    }
+}
+
+// Evaluates template against `data` (in the same way as `eval::evaluate`) into virtual DOM.
+// Elements generated for the collection item are keyed by the item: by the `id` field of the
+// object items, by the value of the scalar ones, and by the index otherwise. Other elements are
+// keyed by their `id` attribute.
+pub fn evaluate(tree: &TreeNode, data: &Value) -> Result<Vec<VNode>, EvalError> {
+    let mut scope = Scope::new(data);
+    let mut nodes = vec![];
+    evaluate_node(tree, &mut scope, &mut nodes)?;
+    Ok(nodes)
+}
+
+// Computes patches which transform `old` DOM children into `new` ones. Children are matched by
+// the key, unkeyed children are matched in order with the nodes of the same kind. Matched children
+// are updated in place, and only the ones out of the longest ordered sequence are moved.
+pub fn diff(old: &[VNode], new: &[VNode]) -> Vec<Patch> {
+    let mut patches = vec![];
+    diff_children(old, new, &mut vec![], &mut patches);
+    patches
+}
+
+// Applies patches to the virtual DOM; reference implementation of the patch semantics
+pub fn apply(nodes: &mut Vec<VNode>, patches: &[Patch]) -> Result<(), PatchError> {
+    for patch in patches {
+        match patch {
+            Patch::Insert { parent, index, node } => {
+                let children = children_at(nodes, parent)?;
+                if *index > children.len() {
+                    return Err(PatchError::NodeNotFound(child_path(parent, *index)));
+                }
+                children.insert(*index, node.clone());
+            },
+            Patch::Remove { parent, index } => {
+                let children = children_at(nodes, parent)?;
+                if *index >= children.len() {
+                    return Err(PatchError::NodeNotFound(child_path(parent, *index)));
+                }
+                children.remove(*index);
+            },
+            Patch::Move { parent, from, to } => {
+                let children = children_at(nodes, parent)?;
+                if *from >= children.len() || *to >= children.len() {
+                    return Err(PatchError::NodeNotFound(child_path(parent, (*from).max(*to))));
+                }
+                let node = children.remove(*from);
+                children.insert(*to, node);
+            },
+            Patch::SetAttribute { path, name, value } => {
+                let attributes = attributes_at(nodes, path)?;
+                match attributes.iter_mut().find(|a| a.name == *name) {
+                    Some(attribute) => attribute.value = value.clone(),
+                    None => attributes.push(VAttribute { name: name.clone(), value: value.clone() }),
+                }
+            },
+            Patch::RemoveAttribute { path, name } => {
+                attributes_at(nodes, path)?.retain(|a| a.name != *name);
+            },
+            Patch::SetText { path, text: new_text } => match node_at(nodes, path)? {
+                VNode::Text { text, .. } => *text = new_text.clone(),
+                VNode::Element { .. } => return Err(PatchError::UnexpectedNodeType(format_path(path))),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn from_tree_node(node: &TreeNode, out: &mut Vec<VNode>) -> Result<(), RenderError> {
+    match node {
+        TreeNode::Root(RootTreeNode { children }) => {
+            for child in children {
+                from_tree_node(child, out)?;
+            }
+        },
+        TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+            let mut element_children = vec![];
+            for child in children {
+                from_tree_node(child, &mut element_children)?;
+            }
+            out.push(element(name, element_attributes(attributes)?, element_children));
+        },
+        TreeNode::InnerContent(InnerContentTreeNode { value, raw }) => {
+            out.push(VNode::Text { text: value_text(value)?, raw: *raw });
+        },
+        TreeNode::Subtree(SubtreeTreeNode { property: binding })
+        | TreeNode::Collection(CollectionTreeNode { collection: binding, .. }) => {
+            return Err(RenderError::UnresolvedBinding(binding.clone()));
+        },
+        TreeNode::Error(node) => return Err(EvalError::from(node).into()),
+    }
+    Ok(())
+}
+
+fn evaluate_node<'a>(node: &'a TreeNode, scope: &mut Scope<'a>, out: &mut Vec<VNode>) -> Result<(), EvalError> {
+    match node {
+        TreeNode::Root(RootTreeNode { children }) => {
+            for child in children {
+                evaluate_node(child, scope, out)?;
+            }
+        },
+        TreeNode::Normal(NormalTreeNode { name, attributes, children }) => {
+            let attributes = element_attributes(&evaluate_attributes(attributes, scope)?)
+                .expect("Evaluated attributes don't have bindings");
+            let mut element_children = vec![];
+            for child in children {
+                evaluate_node(child, scope, &mut element_children)?;
+            }
+            out.push(element(name, attributes, element_children));
+        },
+        TreeNode::InnerContent(InnerContentTreeNode { value, raw }) => {
+            if let Some(evaluated) = evaluate_value(value, scope)? {
+                let text = evaluated.value.to_text().expect("Evaluated value is not a binding");
+                out.push(VNode::Text { text, raw: *raw || evaluated.trusted });
+            }
+        },
+        TreeNode::Subtree(SubtreeTreeNode { property }) => {
+            let component = resolve_component(scope, property)?;
+            scope.enter_component();
+            evaluate_node(component, scope, out)?;
+            scope.exit_component();
+        },
+        TreeNode::Collection(node @ CollectionTreeNode { nodes, collection, .. }) => {
+            let items = resolve_collection(scope, collection)?;
+            for (index, item) in items.iter().enumerate() {
+                let start = out.len();
+                scope.push_item(node.item_path(), item, index, items.len());
+                let key = item_key(node, scope, item, index)?;
+                for node in nodes {
+                    evaluate_node(node, scope, out)?;
+                }
+                scope.pop_item();
+                set_item_keys(&mut out[start..], collection, &key);
+            }
+        },
+        TreeNode::Error(node) => return Err(node.into()),
+    }
+    Ok(())
+}
+
+// Identity of the collection item: value of the key binding (`* @items key @items%code`), or
+// `id` field of the object item, value of the scalar one, or index. Item should be in the scope.
+pub(crate) fn item_key(node: &CollectionTreeNode, scope: &Scope, item: &Value, index: usize) -> Result<String, EvalError> {
+    if let Some(key) = &node.key {
+        return Ok(resolve_scalar(scope, key)?.to_string());
+    }
+    Ok(match item.get("id").unwrap_or(item) {
+        value @ (Value::Number(_) | Value::Text(_) | Value::Bool(_)) => value.to_string(),
+        _ => index.to_string(),
+    })
+}
+
+// Keys elements generated for the collection item; item may produce several elements
+pub(crate) fn set_item_keys(nodes: &mut [VNode], collection: &PropertyBinding, item_key: &str) {
+    let elements = nodes.iter_mut().filter_map(|node| match node {
+        VNode::Element { key, .. } => Some(key),
+        VNode::Text { .. } => None,
+    });
+    for (i, key) in elements.enumerate() {
+        *key = Some(match i {
+            0 => format!("{}:{}", collection, item_key),
+            i => format!("{}:{}#{}", collection, item_key, i),
+        });
+    }
+}
+
+pub(crate) fn element(name: &str, attributes: Vec<VAttribute>, children: Vec<VNode>) -> VNode {
+    let key = attributes
+        .iter()
+        .find(|a| a.name == "id")
+        .and_then(|a| a.value.as_ref())
+        .map(|id| format!("#{}", id));
+    VNode::Element { name: name.to_owned(), attributes, key, children }
+}
+
+pub(crate) fn element_attributes(attributes: &[Attribute]) -> Result<Vec<VAttribute>, RenderError> {
+    let mut element_attributes = Vec::with_capacity(attributes.len());
+    for Attribute { name, value, .. } in attributes {
+        let values = match value {
+            AttributeValue::None => {
+                element_attributes.push(VAttribute { name: name.clone(), value: None });
+                continue;
+            },
+            AttributeValue::Single(value) => std::slice::from_ref(value),
+            AttributeValue::Multiple(values) => values.as_slice(),
+        };
+        let values = values.iter().map(value_text).collect::<Result<Vec<_>, _>>()?;
+        element_attributes.push(VAttribute { name: name.clone(), value: Some(values.join(" ")) });
+    }
+    Ok(element_attributes)
+}
+
+fn value_text(value: &GenericValue) -> Result<String, RenderError> {
+    match value {
+        GenericValue::Binding(binding) => Err(RenderError::UnresolvedBinding(binding.clone())),
+        value => Ok(value.to_text().expect("Value is not a binding")),
+    }
+}
+
+// Identity of the node used for matching; unkeyed nodes of the same kind are matched in order
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum NodeIdentity<'a> {
+    Keyed(&'a str, &'a str),
+    Element(&'a str),
+    Text(bool),
+}
+
+fn identity(node: &VNode) -> NodeIdentity<'_> {
+    match node {
+        VNode::Element { name, key: Some(key), .. } => NodeIdentity::Keyed(name, key),
+        VNode::Element { name, key: None, .. } => NodeIdentity::Element(name),
+        VNode::Text { raw, .. } => NodeIdentity::Text(*raw),
+    }
+}
+
+fn diff_children(old: &[VNode], new: &[VNode], parent: &mut Vec<usize>, out: &mut Vec<Patch>) {
+    let mut unmatched = HashMap::<_, VecDeque<usize>>::new();
+    for (i, node) in old.iter().enumerate() {
+        unmatched.entry(identity(node)).or_default().push_back(i);
+    }
+    // Old child matched to each new child
+    let matches = new
+        .iter()
+        .map(|node| unmatched.get_mut(&identity(node)).and_then(|candidates| candidates.pop_front()))
+        .collect::<Vec<_>>();
+
+    // Removed children, from the end so the indices of the preceding ones stay valid
+    let mut removed = unmatched.into_values().flatten().collect::<Vec<_>>();
+    removed.sort_unstable();
+    for &index in removed.iter().rev() {
+        out.push(Patch::Remove { parent: parent.clone(), index });
+    }
+
+    // DOM children identified by the new child index
+    let mut old_to_new = vec![None; old.len()];
+    for (j, i) in matches.iter().enumerate() {
+        if let Some(i) = i {
+            old_to_new[*i] = Some(j);
+        }
+    }
+    let mut current = old_to_new.into_iter().flatten().collect::<Vec<_>>();
+    let stable = longest_increasing(&current);
+
+    // Children are placed from the end, before the already placed next sibling
+    for j in (0..new.len()).rev() {
+        let anchor = match j + 1 {
+            next if next < new.len() => position(&current, next),
+            _ => current.len(),
+        };
+        if matches[j].is_none() {
+            out.push(Patch::Insert { parent: parent.clone(), index: anchor, node: new[j].clone() });
+            current.insert(anchor, j);
+        } else if !stable.contains(&j) {
+            let from = position(&current, j);
+            current.remove(from);
+            let to = if from < anchor { anchor - 1 } else { anchor };
+            current.insert(to, j);
+            if from != to {
+                out.push(Patch::Move { parent: parent.clone(), from, to });
+            }
+        }
+    }
+
+    for (j, i) in matches.iter().enumerate() {
+        if let Some(i) = i {
+            parent.push(j);
+            diff_node(&old[*i], &new[j], parent, out);
+            parent.pop();
+        }
+    }
+}
+
+fn diff_node(old: &VNode, new: &VNode, path: &mut Vec<usize>, out: &mut Vec<Patch>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (
+            VNode::Element { attributes: old_attributes, children: old_children, .. },
+            VNode::Element { attributes: new_attributes, children: new_children, .. },
+        ) => {
+            diff_attributes(old_attributes, new_attributes, path, out);
+            diff_children(old_children, new_children, path, out);
+        },
+        (VNode::Text { .. }, VNode::Text { text, .. }) => {
+            out.push(Patch::SetText { path: path.clone(), text: text.clone() });
+        },
+        _ => unreachable!("Only nodes of the same kind are matched"),
+    }
+}
+
+pub(crate) fn diff_attributes(old: &[VAttribute], new: &[VAttribute], path: &[usize], out: &mut Vec<Patch>) {
+    for attribute in old {
+        if !new.iter().any(|a| a.name == attribute.name) {
+            out.push(Patch::RemoveAttribute { path: path.to_vec(), name: attribute.name.clone() });
+        }
+    }
+    for attribute in new {
+        if !old.contains(attribute) {
+            out.push(Patch::SetAttribute { path: path.to_vec(), name: attribute.name.clone(), value: attribute.value.clone() });
+        }
+    }
+}
+
+// Values of the longest strictly increasing subsequence
+pub(crate) fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // Index of the last value of the subsequence of each length, and predecessors of the values
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        let length = tails.partition_point(|&t| values[t] < *value);
+        previous[i] = length.checked_sub(1).map(|l| tails[l]);
+        match tails.get_mut(length) {
+            Some(tail) => *tail = i,
+            None => tails.push(i),
+        }
+    }
+
+    let mut sequence = vec![];
+    let mut next = tails.last().copied();
+    while let Some(i) = next {
+        sequence.push(values[i]);
+        next = previous[i];
+    }
+    sequence
+}
+
+fn position(children: &[usize], j: usize) -> usize {
+    children.iter().position(|&c| c == j).expect("Child is placed")
+}
+
+fn child_path(parent: &[usize], index: usize) -> String {
+    let mut path = parent.to_vec();
+    path.push(index);
+    format_path(&path)
+}
+
+fn node_at<'a>(nodes: &'a mut Vec<VNode>, path: &[usize]) -> Result<&'a mut VNode, PatchError> {
+    let (&last, parent) = path.split_last().ok_or_else(|| PatchError::UnexpectedNodeType(format_path(path)))?;
+    children_at(nodes, parent)?.get_mut(last).ok_or_else(|| PatchError::NodeNotFound(format_path(path)))
+}
+
+fn children_at<'a>(nodes: &'a mut Vec<VNode>, path: &[usize]) -> Result<&'a mut Vec<VNode>, PatchError> {
+    let mut children = nodes;
+    for (depth, &i) in path.iter().enumerate() {
+        children = match children.get_mut(i) {
+            Some(VNode::Element { children, .. }) => children,
+            Some(VNode::Text { .. }) => return Err(PatchError::UnexpectedNodeType(format_path(&path[..=depth]))),
+            None => return Err(PatchError::NodeNotFound(format_path(&path[..=depth]))),
+        };
+    }
+    Ok(children)
+}
+
+fn attributes_at<'a>(nodes: &'a mut Vec<VNode>, path: &[usize]) -> Result<&'a mut Vec<VAttribute>, PatchError> {
+    match node_at(nodes, path)? {
+        VNode::Element { attributes, .. } => Ok(attributes),
+        VNode::Text { .. } => Err(PatchError::UnexpectedNodeType(format_path(path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+    use expect_test::{expect, Expect};
+
+    fn assert_patches(old: &[VNode], new: &[VNode], expect: Expect) {
+        let patches = diff(old, new);
+        let mut patched = old.to_vec();
+        apply(&mut patched, &patches).unwrap();
+        assert_eq!(patched, new);
+
+        let patches = patches.iter().map(|p| serde_json::to_string(p).unwrap()).collect::<Vec<_>>();
+        expect.assert_eq(&patches.join("\n"));
+    }
+
+    fn list(items: &[(isize, &str)]) -> Vec<VNode> {
+        let tree = TreeNode::parse("ul>(li[title=@items%name]>{@items%name})*@items").unwrap();
+        let items = items.iter().map(|(id, name)| Value::object([("id", Value::from(*id)), ("name", Value::from(*name))]));
+        evaluate(&tree, &Value::object([("items", Value::List(items.collect()))])).unwrap()
+    }
+
+    #[test]
+    fn keyed_collection() {
+        let old = list(&[(1, "a"), (2, "b"), (3, "c"), (4, "d")]);
+        expect![[r#"[{"type":"element","name":"ul","attributes":[],"children":[{"type":"element","name":"li","attributes":[{"name":"title","value":"a"}],"key":"@items:1","children":[{"type":"text","text":"a"}]}]}]"#]]
+            .assert_eq(&serde_json::to_string(&list(&[(1, "a")])).unwrap());
+
+        assert_patches(&old, &list(&[(4, "d"), (1, "a"), (2, "b"), (3, "c")]), expect![[r#"{"op":"move","parent":[0],"from":3,"to":0}"#]]);
+        assert_patches(
+            &old,
+            &list(&[(1, "a"), (5, "e"), (3, "x"), (2, "b")]),
+            expect![[r#"
+                {"op":"remove","parent":[0],"index":3}
+                {"op":"move","parent":[0],"from":1,"to":2}
+                {"op":"insert","parent":[0],"index":1,"node":{"type":"element","name":"li","attributes":[{"name":"title","value":"e"}],"key":"@items:5","children":[{"type":"text","text":"e"}]}}
+                {"op":"set-attribute","path":[0,2],"name":"title","value":"x"}
+                {"op":"set-text","path":[0,2,0],"text":"x"}"#]],
+        );
+    }
+
+    fn coded_list(items: &[(&str, &str)]) -> Vec<VNode> {
+        let tree = TreeNode::parse("ul>((li>{@item%name})+hr)*@items as item key @item%code").unwrap();
+        let items = items.iter().map(|(code, name)| Value::object([("code", *code), ("name", *name)]));
+        evaluate(&tree, &Value::object([("items", Value::List(items.collect()))])).unwrap()
+    }
+
+    #[test]
+    fn explicit_keys() {
+        expect![[r#"[{"type":"element","name":"ul","attributes":[],"children":[{"type":"element","name":"li","attributes":[],"key":"@items:x","children":[{"type":"text","text":"a"}]},{"type":"element","name":"hr","attributes":[],"key":"@items:x#1","children":[]}]}]"#]]
+            .assert_eq(&serde_json::to_string(&coded_list(&[("x", "a")])).unwrap());
+        assert_patches(
+            &coded_list(&[("x", "a"), ("y", "b")]),
+            &coded_list(&[("y", "b"), ("z", "c"), ("x", "d")]),
+            expect![[r#"
+                {"op":"move","parent":[0],"from":1,"to":3}
+                {"op":"move","parent":[0],"from":0,"to":2}
+                {"op":"insert","parent":[0],"index":2,"node":{"type":"element","name":"hr","attributes":[],"key":"@items:z#1","children":[]}}
+                {"op":"insert","parent":[0],"index":2,"node":{"type":"element","name":"li","attributes":[],"key":"@items:z","children":[{"type":"text","text":"c"}]}}
+                {"op":"set-text","path":[0,4,0],"text":"d"}"#]],
+        );
+
+        let tree = TreeNode::parse("(li)*@items key @items%code").unwrap();
+        let data = Value::object([("items", Value::from(vec![Value::object([("id", 1)])]))]);
+        assert_eq!(evaluate(&tree, &data).unwrap_err().to_string(), "Binding @items%code is not found in data");
+    }
+
+    #[test]
+    fn unkeyed_nodes() {
+        let tree = |input: &str| VNode::from_tree(&TreeNode::parse(input).unwrap()).unwrap();
+        assert_patches(&tree("div>p+{a}+p#x"), &tree("div>p+{a}+p#x"), expect![[""]]);
+        assert_patches(
+            &tree("div[hidden class=a]>p+{a}+p#x"),
+            &tree("div[class=b]>span+{b}+p#x[flag]"),
+            expect![[r#"
+                {"op":"remove-attribute","path":[0],"name":"hidden"}
+                {"op":"set-attribute","path":[0],"name":"class","value":"b"}
+                {"op":"remove","parent":[0],"index":0}
+                {"op":"insert","parent":[0],"index":0,"node":{"type":"element","name":"span","attributes":[],"children":[]}}
+                {"op":"set-text","path":[0,1],"text":"b"}
+                {"op":"set-attribute","path":[0,2],"name":"flag","value":null}"#]],
+        );
+        assert!(VNode::from_tree(&TreeNode::parse("div>{@x}").unwrap()).is_err());
+    }
+
+    #[test]
+    fn randomized_patches() {
+        let mut random = Random::new(0x9e37_79b9_7f4a_7c15);
+        let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        for _ in 0..300 {
+            let items = |random: &mut Random| {
+                let mut items = (1..=8).map(|id| (id, names[random.next(names.len())])).collect::<Vec<_>>();
+                items.retain(|_| random.next(4) != 0);
+                for i in (1..items.len()).rev() {
+                    items.swap(i, random.next(i + 1));
+                }
+                items
+            };
+            let (old, new) = (items(&mut random), items(&mut random));
+            let (old, new) = (list(&old), list(&new));
+
+            let patches = diff(&old, &new);
+            let mut patched = old.clone();
+            apply(&mut patched, &patches).unwrap();
+            assert_eq!(patched, new);
+
+            let json = serde_json::to_string(&patches).unwrap();
+            assert_eq!(serde_json::from_str::<Vec<Patch>>(&json).unwrap(), patches);
+        }
+    }
+
+    #[test]
+    fn randomized_keyed_items() {
+        let mut random = Random::new(0x853c_49e6_748f_ea9b);
+        // Few codes, so some items share the key
+        let codes = ["a", "b", "c", "d", "e", "f"];
+        for _ in 0..300 {
+            let items = |random: &mut Random| {
+                let items = (0..random.next(8)).map(|_| (codes[random.next(codes.len())], codes[random.next(codes.len())]));
+                coded_list(&items.collect::<Vec<_>>())
+            };
+            let (old, new) = (items(&mut random), items(&mut random));
+
+            let patches = diff(&old, &new);
+            let mut patched = old.clone();
+            apply(&mut patched, &patches).unwrap();
+            assert_eq!(patched, new);
+        }
+    }
+}